@@ -0,0 +1,179 @@
+/* Batch Rendering
+ * ---------------
+ * "mandelbrot batch MANIFEST" renders a whole list of stills. The manifest
+ * has one job per line, each a JSON object with the same sections and keys
+ * as a job file (see config.rs):
+ *
+ *      {"view": {"upper_left": [-2, 1], "lower_right": [1, -1]}, "image": {"size": [3000, 2000], "output": "whole.png"}}
+ *      {"fractal": {"type": "julia", "c": [-0.8, 0.156]}, "view": ..., "render": {"palette": "fire"}}
+ *
+ * Blank lines are skipped. A fixed pool of threads takes jobs off the list one
+ * at a time, so many small jobs keep every thread busy. A job that can't be
+ * parsed, or whose image can't be written, is recorded as failed, and the rest
+ * carry on.
+ *
+ * As each job finishes, a line of JSON goes to the results log, saying how it
+ * went and how long it took:
+ *
+ *      {"line": 1, "output": "whole.png", "status": "ok", "seconds": 12.345}
+ *      {"line": 2, "status": "error", "seconds": 0.000, "error": "line 2: ..."}
+ */
+
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use config::Config;
+use json;
+use render::render_config;
+
+/// How one job went.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    /// The job's line in the manifest, counting from one.
+    pub line: usize,
+    /// The file the job writes, if the job could be parsed.
+    pub output: Option<String>,
+    pub seconds: f64,
+    pub error: Option<String>
+}
+
+impl Outcome {
+    /// Return this outcome as a line of the results log, without the newline.
+    pub fn to_json(&self) -> String {
+        let mut text = format!("{{\"line\": {}", self.line);
+        if let Some(ref output) = self.output {
+            text += &format!(", \"output\": {}", json::quote(output));
+        }
+        let status = if self.error.is_none() { "ok" } else { "error" };
+        text += &format!(", \"status\": \"{}\", \"seconds\": {:.3}", status, self.seconds);
+        if let Some(ref error) = self.error {
+            text += &format!(", \"error\": {}", json::quote(error));
+        }
+        text + "}"
+    }
+}
+
+/// Parse, render and write the job on line `line` of a manifest.
+fn run_job(line: usize, text: &str) -> Outcome {
+    let start = Instant::now();
+    let mut outcome = Outcome { line, output: None, seconds: 0.0, error: None };
+
+    let config = json::parse(text)
+        .map_err(|message| format!("line {}: {}", line, message))
+        .and_then(|job| Config::from_json(&job, line).map_err(|err| err.to_string()));
+    match config {
+        Ok(config) => {
+            outcome.output = Some(config.output.clone());
+            let pixels = render_config(&config, 1);
+            if let Err(err) = ::write_output(&config, &pixels) {
+                outcome.error = Some(format!("{}: {}", config.output, err));
+            }
+        }
+        Err(message) => outcome.error = Some(message)
+    }
+
+    outcome.seconds = start.elapsed().as_secs_f64();
+    outcome
+}
+
+/// Run every job in `manifest` using `threads` threads, writing a line to
+/// `log` as each finishes. Return the outcomes in manifest order.
+pub fn run<W: Write + Send>(manifest: &str, threads: usize, log: &mut W)
+    -> io::Result<Vec<Outcome>>
+{
+    let jobs: Vec<(usize, &str)> = manifest.lines()
+        .enumerate()
+        .map(|(index, text)| (index + 1, text.trim()))
+        .filter(|&(_, text)| !text.is_empty())
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let finished = Mutex::new((Vec::with_capacity(jobs.len()), log, Ok(())));
+
+    thread::scope(|spawner| {
+        for _ in 0 .. threads.max(1) {
+            spawner.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let (line, text) = match jobs.get(index) {
+                    Some(&job) => job,
+                    None => break
+                };
+                let outcome = run_job(line, text);
+
+                let mut guard = finished.lock().unwrap();
+                let (ref mut outcomes, ref mut log, ref mut result) = *guard;
+                if result.is_ok() {
+                    *result = writeln!(log, "{}", outcome.to_json()).and_then(|()| log.flush());
+                }
+                outcomes.push(outcome);
+            });
+        }
+    });
+
+    let (mut outcomes, _, result) = finished.into_inner().unwrap();
+    result?;
+    outcomes.sort_by_key(|outcome: &Outcome| outcome.line);
+    Ok(outcomes)
+}
+
+#[test]
+fn test_outcome_to_json() {
+    let ok = Outcome { line: 1, output: Some("a.png".to_string()), seconds: 1.5, error: None };
+    assert_eq!(ok.to_json(),
+               "{\"line\": 1, \"output\": \"a.png\", \"status\": \"ok\", \"seconds\": 1.500}");
+    let failed = Outcome { line: 2, output: None, seconds: 0.0, error: Some("bad \"x\"".to_string()) };
+    assert_eq!(json::parse(&failed.to_json()).unwrap(),
+               json::Json::Object(vec![
+                   ("line".to_string(), json::Json::Number("2".to_string())),
+                   ("status".to_string(), json::Json::String("error".to_string())),
+                   ("seconds".to_string(), json::Json::Number("0.000".to_string())),
+                   ("error".to_string(), json::Json::String("bad \"x\"".to_string()))
+               ]));
+}
+
+#[test]
+fn test_run_continues_past_failures() {
+    let dir = std::env::temp_dir().join(format!("mandelbrot-batch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = |name: &str| json::quote(&dir.join(name).to_string_lossy());
+    let view = "\"view\": {\"upper_left\": [-2, 1], \"lower_right\": [1, -1]}";
+
+    let manifest = format!(
+        "{{{}, \"image\": {{\"size\": [30, 20], \"output\": {}}}}}\n\
+         \n\
+         {{{}, \"image\": {{\"size\": [30, 20]}}}}\n\
+         {{\"view\": \n\
+         {{{}, \"image\": {{\"size\": [30, 20], \"output\": {}}}}}\n\
+         {{{}, \"image\": {{\"size\": [16, 8], \"output\": {}}}, \"render\": {{\"palette\": \"fire\"}}}}\n",
+        view, output("one.png"),
+        view,
+        view, output("missing/two.png"),
+        view, output("three.png"));
+
+    let mut log = Vec::new();
+    let outcomes = run(&manifest, 3, &mut log).unwrap();
+
+    let lines: Vec<usize> = outcomes.iter().map(|o| o.line).collect();
+    assert_eq!(lines, [1, 3, 4, 5, 6]);
+    let failed: Vec<usize> = outcomes.iter().filter(|o| o.error.is_some()).map(|o| o.line).collect();
+    assert_eq!(failed, [3, 4, 5]);
+    assert_eq!(outcomes[1].error.as_ref().unwrap(), "line 3: image.output: required key is missing");
+    assert!(outcomes[2].error.as_ref().unwrap().starts_with("line 4: "));
+    assert!(outcomes[3].error.as_ref().unwrap().contains("missing/two.png"));
+
+    assert!(dir.join("one.png").exists());
+    assert!(dir.join("three.png").exists());
+
+    // The log has one line per job, in whatever order they finished.
+    let log = String::from_utf8(log).unwrap();
+    assert_eq!(log.lines().count(), 5);
+    assert_eq!(log.lines().filter(|l| l.contains("\"status\": \"ok\"")).count(), 2);
+    for line in log.lines() {
+        assert!(json::parse(line).is_ok());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
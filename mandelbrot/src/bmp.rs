@@ -0,0 +1,192 @@
+/* BMP Files
+ * ---------
+ * A Windows bitmap is a 14-byte file header, a 40-byte BITMAPINFOHEADER, an
+ * optional color table, and then the pixels, uncompressed:
+ *
+ *      "BM", file size, pixel data offset
+ *      header size, width, height, planes, bits per pixel, compression, ...
+ *      256 four-byte entries (blue, green, red, zero), for 8-bit images
+ *      rows, each padded to a multiple of four bytes
+ *
+ * RGB images use 24 bits per pixel, stored blue first. Grayscale images use
+ * 8 bits per pixel, indexing a color table that runs from black to white.
+ * Samples are always 8 bits.
+ *
+ * The rows are stored bottom to top. Older programs don't accept the negative
+ * heights that mean top to bottom, so rather than buffer the whole image,
+ * "BmpWriter" seeks to where each row belongs as it arrives.
+ */
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use image::ImageWriter;
+#[cfg(test)]
+use png::Image;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+
+/// Pixels per meter, for 72 dots per inch.
+const RESOLUTION: u32 = 2835;
+
+/// Return the number of bytes in each row of pixels of a bitmap `width`
+/// pixels wide with `channels` bytes per pixel, including padding.
+fn stride(width: usize, channels: usize) -> usize {
+    (width * channels + 3) & !3
+}
+
+/// Writes a BMP image to `W` a few rows at a time.
+pub struct BmpWriter<W: Write + Seek> {
+    out: W,
+    bounds: (usize, usize),
+    channels: usize,
+    /// Where in the file the pixel rows begin.
+    offset: u64,
+    rows_written: usize
+}
+
+impl<W: Write + Seek> BmpWriter<W> {
+    /// Start writing an image of size `bounds` to `out`, in grayscale if
+    /// `channels` is one or RGB if it is three. `depth` must be 8.
+    pub fn new(mut out: W, bounds: (usize, usize), channels: usize, depth: u8)
+        -> io::Result<BmpWriter<W>>
+    {
+        if depth != 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "BMP files can only hold 8-bit samples"));
+        }
+        let table_size = if channels == 1 { 256 * 4 } else { 0 };
+        let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + table_size;
+        let image_size = stride(bounds.0, channels) * bounds.1;
+
+        let mut header = Vec::with_capacity(offset);
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&((offset + image_size) as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&(offset as u32).to_le_bytes());
+
+        header.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&(bounds.0 as i32).to_le_bytes());
+        header.extend_from_slice(&(bounds.1 as i32).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(channels as u16 * 8).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());      // no compression
+        header.extend_from_slice(&(image_size as u32).to_le_bytes());
+        header.extend_from_slice(&RESOLUTION.to_le_bytes());
+        header.extend_from_slice(&RESOLUTION.to_le_bytes());
+        header.extend_from_slice(&(table_size as u32 / 4).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+
+        if channels == 1 {
+            for gray in 0 ..= 255 {
+                header.extend_from_slice(&[gray, gray, gray, 0]);
+            }
+        }
+        out.write_all(&header)?;
+        Ok(BmpWriter { out, bounds, channels, offset: offset as u64, rows_written: 0 })
+    }
+}
+
+impl<W: Write + Seek> ImageWriter for BmpWriter<W> {
+    fn write_rows(&mut self, rows: &[u8]) -> io::Result<()> {
+        let (width, height) = self.bounds;
+        let stride = stride(width, self.channels);
+        let mut line = vec![0; stride];
+        for row in rows.chunks(width * self.channels) {
+            assert!(self.rows_written < height);
+            for (pixel, out) in row.chunks(self.channels).zip(line.chunks_mut(self.channels)) {
+                out.copy_from_slice(pixel);
+                out.reverse();      // RGB to BGR; a gray byte stays put
+            }
+            let position = self.offset + ((height - 1 - self.rows_written) * stride) as u64;
+            self.out.seek(SeekFrom::Start(position))?;
+            self.out.write_all(&line)?;
+            self.rows_written += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Decode the uncompressed 8-bit grayscale or 24-bit BMP file `file`, top
+/// row first.
+#[cfg(test)]
+pub fn read(file: &[u8]) -> io::Result<Image> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if file.len() < FILE_HEADER_SIZE + INFO_HEADER_SIZE || &file[.. 2] != b"BM" {
+        return Err(invalid("not a BMP file"));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([file[i], file[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([file[i], file[i + 1], file[i + 2], file[i + 3]]);
+
+    let offset = u32_at(10) as usize;
+    let width = u32_at(18) as i32;
+    let height = u32_at(22) as i32;
+    let bits = u16_at(28);
+    if width < 0 || u32_at(30) != 0 {
+        return Err(invalid("unsupported BMP file"));
+    }
+    let channels = match bits {
+        8 => 1,
+        24 => 3,
+        _ => return Err(invalid("unsupported BMP bit depth"))
+    };
+    let bounds = (width as usize, height.unsigned_abs() as usize);
+    let stride = stride(bounds.0, channels);
+    if file.len() < offset + stride * bounds.1 {
+        return Err(invalid("BMP file is truncated"));
+    }
+
+    let mut pixels = Vec::with_capacity(bounds.0 * bounds.1 * channels);
+    for y in 0 .. bounds.1 {
+        let stored = if height > 0 { bounds.1 - 1 - y } else { y };
+        let start = offset + stored * stride;
+        for pixel in file[start .. start + bounds.0 * channels].chunks(channels) {
+            pixels.extend(pixel.iter().rev());
+        }
+    }
+
+    // Look gray indexes up in the color table, insisting it be gray.
+    if channels == 1 {
+        let table = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+        for p in &mut pixels {
+            let entry = &file[table + *p as usize * 4 .. table + *p as usize * 4 + 3];
+            if entry[0] != entry[1] || entry[1] != entry[2] {
+                return Err(invalid("color-mapped BMP files are not supported"));
+            }
+            *p = entry[0];
+        }
+    }
+    Ok(Image { bounds, channels, depth: 8, pixels })
+}
+
+#[test]
+fn test_round_trip() {
+    use std::io::Cursor;
+
+    // Three pixels wide, so that the RGB rows need padding.
+    let rgb: Vec<u8> = (0 .. 3 * 2 * 3).map(|i| i as u8 * 11).collect();
+    let mut file = Cursor::new(Vec::new());
+    let mut writer = Box::new(BmpWriter::new(&mut file, (3, 2), 3, 8).unwrap());
+    writer.write_rows(&rgb[.. 9]).unwrap();
+    writer.write_rows(&rgb[9 ..]).unwrap();
+    writer.finish().unwrap();
+    let file = file.into_inner();
+    assert_eq!(file.len(), 54 + 12 * 2);
+    // The bottom row comes first, blue first.
+    assert_eq!(&file[54 .. 57], &[rgb[11], rgb[10], rgb[9]]);
+    assert_eq!(read(&file).unwrap(), Image { bounds: (3, 2), channels: 3, depth: 8, pixels: rgb });
+
+    let gray = [0, 64, 128, 255, 1, 2];
+    let mut file = Cursor::new(Vec::new());
+    let mut writer = Box::new(BmpWriter::new(&mut file, (2, 3), 1, 8).unwrap());
+    writer.write_rows(&gray).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(read(&file.into_inner()).unwrap().pixels, gray);
+
+    assert!(BmpWriter::new(Cursor::new(Vec::new()), (2, 3), 1, 16).is_err());
+    assert!(read(b"GIF89a").is_err());
+}
@@ -0,0 +1,309 @@
+/* Checkpoints
+ * -----------
+ * A render that takes hours shouldn't have to start over when the machine
+ * reboots. While rendering with checkpoints, we work through the image a batch
+ * of rows at a time, and every so often save everything we know to a check-
+ * point file: the job file of the render, which rows are finished, and the
+ * pixels themselves. The file starts with a few lines of text,
+ *
+ *      mandelbrot checkpoint 2
+ *      config 412
+ *
+ * then the job file, as "Config::to_toml" writes it, in that many bytes, one
+ * byte per row that is 1 if the row is done, and then the pixels. We write it
+ * to a temporary file and rename it into place, so a crash in the middle of
+ * saving leaves the previous checkpoint intact.
+ *
+ * Resuming loads the file, checks that it was made for the same render that's
+ * being asked for, and renders only the rows that aren't done. Since
+ * "render_config_rows" gives the same pixels no matter how the image is
+ * divided up, an interrupted and resumed render is identical to an
+ * uninterrupted one. Dithering carries errors from each row to the next, so
+ * the pixels saved are the samples before it, and it and the overlay are
+ * applied once every row is done.
+ */
+
+use std::fs;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use render::{finish_samples, render_config_rows, sample_config};
+
+const MAGIC: &str = "mandelbrot checkpoint 2";
+
+/// How many rows each thread renders between chances to save a checkpoint.
+const ROWS_PER_THREAD: usize = 16;
+
+/// A render in progress.
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub config: Config,
+    done: Vec<bool>,
+    pixels: Vec<u8>
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Checkpoint {
+    /// Start a render of the image `config` describes with no rows done.
+    pub fn new(config: Config) -> Checkpoint {
+        let (width, height) = config.bounds;
+        Checkpoint {
+            done: vec![false; height],
+            pixels: vec![0; width * height * sample_config(&config).pixel_bytes()],
+            config
+        }
+    }
+
+    /// Load the checkpoint saved at `path`.
+    #[cfg(test)]
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        Checkpoint::read(path, None)
+    }
+
+    /// Load the checkpoint at `path` and check that it was made for a render
+    /// of the image `config` describes.
+    pub fn resume(path: &Path, config: &Config) -> io::Result<Checkpoint> {
+        Checkpoint::read(path, Some(config))
+    }
+
+    /// Load the checkpoint at `path`, refusing it if it wasn't made for a
+    /// render of the image `expected` describes, if given. Nothing the size
+    /// of the image is allocated until the header has been checked.
+    fn read(path: &Path, expected: Option<&Config>) -> io::Result<Checkpoint> {
+        let mut input = io::BufReader::new(fs::File::open(path)?);
+        let mut fields = Vec::new();
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Err(invalid("checkpoint header is truncated".to_string()));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            fields.push(line.to_string());
+        }
+
+        if fields.first().map(|s| s.as_str()) != Some(MAGIC) {
+            return Err(invalid(format!("{} is not a checkpoint file", path.display())));
+        }
+        let field = |name: &str| {
+            fields.iter()
+                .find_map(|f| f.strip_prefix(name).and_then(|v| v.strip_prefix(' ')))
+                .ok_or_else(|| invalid(format!("checkpoint has no {} field", name)))
+        };
+        let length: u64 = field("config")?.parse()
+            .map_err(|_| invalid("bad config length in checkpoint".to_string()))?;
+        let mut text = String::new();
+        input.by_ref().take(length).read_to_string(&mut text)?;
+        if text.len() as u64 != length {
+            return Err(invalid("checkpoint config is truncated".to_string()));
+        }
+        let config = Config::parse(&text)
+            .map_err(|err| invalid(format!("bad config in checkpoint: {}", err)))?;
+        if let Some(expected) = expected {
+            if config != *expected {
+                return Err(invalid(format!(
+                    "checkpoint {} is for a different render, of\n{}refusing to resume it",
+                    path.display(), text)));
+            }
+        }
+
+        let (width, height) = config.bounds;
+        let size = width.checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(sample_config(&config).pixel_bytes()))
+            .and_then(|size| size.checked_add(height))
+            .ok_or_else(|| invalid("checkpoint image is too large".to_string()))?;
+        let rest = input.get_ref().metadata()?.len().saturating_sub(input.stream_position()?);
+        if rest < size as u64 {
+            return Err(invalid("checkpoint is truncated".to_string()));
+        }
+
+        let mut checkpoint = Checkpoint::new(config);
+        let mut done = vec![0; checkpoint.done.len()];
+        input.read_exact(&mut done)?;
+        input.read_exact(&mut checkpoint.pixels)?;
+        if input.read(&mut [0])? != 0 {
+            return Err(invalid("checkpoint has trailing data".to_string()));
+        }
+        checkpoint.done = done.iter().map(|&d| d != 0).collect();
+        Ok(checkpoint)
+    }
+
+    /// Save this checkpoint to `path`, replacing any previous one.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = Path::new(&temp);
+
+        let mut out = io::BufWriter::new(fs::File::create(temp)?);
+        let text = self.config.to_toml();
+        write!(out, "{}\nconfig {}\n\n{}", MAGIC, text.len(), text)?;
+        let done: Vec<u8> = self.done.iter().map(|&d| d as u8).collect();
+        out.write_all(&done)?;
+        out.write_all(&self.pixels)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        fs::rename(temp, path)
+    }
+
+    /// Return the number of rows rendered so far.
+    pub fn rows_done(&self) -> usize {
+        self.done.iter().filter(|&&d| d).count()
+    }
+
+    /// Return true if every row has been rendered.
+    pub fn is_complete(&self) -> bool {
+        self.done.iter().all(|&d| d)
+    }
+
+    /// Return the finished image: the pixels rendered so far, dithered and
+    /// overlaid as the configuration asks.
+    pub fn image(&self) -> Vec<u8> {
+        finish_samples(&self.config, self.pixels.clone())
+    }
+
+    /// Render the rows that aren't done yet using `threads` threads, saving
+    /// a checkpoint to `path` whenever `interval` has passed since the last
+    /// one.
+    ///
+    /// After each batch of rows, `keep_going` is called with the number of
+    /// rows done; if it returns false, we save a checkpoint and stop. Return
+    /// true if the render is complete.
+    pub fn render<F>(&mut self,
+                     path: &Path,
+                     threads: usize,
+                     interval: Duration,
+                     mut keep_going: F)
+        -> io::Result<bool>
+        where F: FnMut(usize) -> bool
+    {
+        let threads = threads.max(1);
+        let samples = sample_config(&self.config);
+        let row_bytes = self.config.bounds.0 * samples.pixel_bytes();
+        let mut last_save = Instant::now();
+
+        while !self.is_complete() {
+            // The next run of rows not done yet, up to a batch of them.
+            let top = self.done.iter().position(|&done| !done).unwrap();
+            let rows = self.done[top ..].iter()
+                .take(threads * ROWS_PER_THREAD)
+                .take_while(|&&done| !done)
+                .count();
+            let pixels = &mut self.pixels[top * row_bytes .. (top + rows) * row_bytes];
+            render_config_rows(&samples, pixels, top, threads);
+            for done in &mut self.done[top .. top + rows] {
+                *done = true;
+            }
+
+            let stop = !keep_going(self.rows_done());
+            if stop || last_save.elapsed() >= interval {
+                self.save(path)?;
+                last_save = Instant::now();
+            }
+            if stop {
+                return Ok(self.is_complete());
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir()
+        .join(format!("mandelbrot-{}-{}.checkpoint", name, std::process::id()))
+}
+
+#[cfg(test)]
+fn test_config(bounds: (usize, usize)) -> Config {
+    use num::Complex;
+    Config::new(bounds, Complex { re: -2.0, im: 1.2 }, Complex { re: 0.6, im: -1.2 },
+                "unused.png")
+}
+
+#[test]
+fn test_interrupted_render_resumes_to_same_image() {
+    use dither::Dither;
+
+    // The plain render, and one with everything that isn't a row at a time.
+    let plain = test_config((40, 150));
+    let mut fancy = test_config((40, 150));
+    fancy.palette = Some("fire".to_string());
+    fancy.dither = Dither::FloydSteinberg;
+    fancy.overlay = Some("axes,ticks".to_string());
+    fancy.title = Some("Resumed".to_string());
+    for config in [plain, fancy] {
+        let path = temp_path("resume");
+        let expected = ::render::render_config(&config, 1);
+
+        // Stop after the first batch, as if the machine had gone down.
+        let mut first = Checkpoint::new(config.clone());
+        let complete = first.render(&path, 2, Duration::from_secs(3600), |_| false).unwrap();
+        assert!(!complete);
+        assert_eq!(first.rows_done(), 2 * ROWS_PER_THREAD);
+        drop(first);
+
+        let mut resumed = Checkpoint::resume(&path, &config).unwrap();
+        assert_eq!(resumed.rows_done(), 2 * ROWS_PER_THREAD);
+        let mut batches = 0;
+        let complete = resumed.render(&path, 3, Duration::ZERO, |_| { batches += 1; true })
+            .unwrap();
+        assert!(complete);
+        assert_eq!(batches, 3);
+        assert_eq!(resumed.image(), expected);
+
+        // The checkpoint on disk is complete too.
+        assert!(Checkpoint::load(&path).unwrap().is_complete());
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn test_empty_render() {
+    let path = temp_path("empty");
+    let mut checkpoint = Checkpoint::new(test_config((0, 5)));
+    assert!(checkpoint.render(&path, 4, Duration::ZERO, |_| true).unwrap());
+    assert!(checkpoint.image().is_empty());
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn test_resume_rejects_different_render() {
+    use num::Complex;
+
+    let config = test_config((8, 4));
+    let path = temp_path("mismatch");
+    Checkpoint::new(config.clone()).save(&path).unwrap();
+
+    assert!(Checkpoint::resume(&path, &config).is_ok());
+    let moved = Config { upper_left: Complex { re: -2.0, im: 1.2000001 }, ..config.clone() };
+    let err = Checkpoint::resume(&path, &moved).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(Checkpoint::resume(&path, &Config { bounds: (8, 5), ..config.clone() }).is_err());
+    let colored = Config { palette: Some("fire".to_string()), ..config.clone() };
+    assert!(Checkpoint::resume(&path, &colored).is_err());
+    assert!(Checkpoint::resume(&path, &Config { limit: 1000, ..config }).is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_load_checks_size_before_allocating() {
+    // Headers claiming images too large to hold, or larger than the file,
+    // are refused before anything that size is allocated.
+    let path = temp_path("huge");
+    for bounds in [(1 << 40, 1 << 40), (100_000, 100_000)] {
+        let text = test_config(bounds).to_toml();
+        fs::write(&path, format!("{}\nconfig {}\n\n{}", MAGIC, text.len(), text)).unwrap();
+        let err = Checkpoint::load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(Checkpoint::resume(&path, &test_config((8, 4))).is_err());
+    }
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,1089 @@
+/* Job Files
+ * ---------
+ * A long command line is easy to get wrong and hard to keep. A job file
+ * describes a render instead, in a small subset of TOML:
+ *
+ *      # The seahorse valley, in color.
+ *      [fractal]
+ *      type = "mandelbrot"             # or "julia", with c = [re, im]
+ *
+ *      [view]
+ *      upper_left = [-0.8, 0.2]
+ *      lower_right = [-0.7, 0.125]
+ *      rotation = 15.0                 # degrees counterclockwise
+ *
+ *      [image]
+ *      size = [1000, 750]
+ *      output = "seahorses.png"
+ *      format = "png"                  # or "pnm", "bmp", "tiff"; see image.rs
+ *      depth = 8                       # bits per sample, or 16
+ *      dither = "bayer"                # or "floyd-steinberg"; see dither.rs
+ *      overlay = "axes,ticks"          # draw coordinates on it; see overlay.rs
+ *      title = "Seahorse valley"       # a line of text across the top
+ *
+ *      [render]
+ *      limit = 1000                    # iterations before giving up
+ *      antialias = 3                   # 3x3 samples per pixel
+ *      palette = "ultra"               # see palette.rs; omit for grayscale
+ *      cycle = 64.0                    # iterations per trip through the palette
+ *      coloring = "stripe:5"           # or "escape", the default, or "triangle"
+ *      trap = "circle:0,0,0.5"         # color by orbit trap instead; see trap.rs
+ *      light = "phong:45,30"           # shade as a lit surface; see light.rs
+ *      precision = "fixed"             # or "double", the default; see fixed.rs
+ *
+ * Instead of corners, the view may give a center, with a zoom or a radius,
+ * and is then fitted to the image's shape; see view.rs:
+ *
+ *      [view]
+ *      center = [-0.75, 0.1]
+ *      zoom = 40.0                     # or radius = 0.05
+ *
+ * Only "view" and "image.size" and "image.output" are required. Values are
+ * strings in double quotes, integers, floats, booleans, or arrays of those.
+ * Strings take the escapes \", \\, \n, \t and \uXXXX.
+ *
+ * Mistakes are reported with the line and the full name of the key, like
+ * "line 7: view.upper_left: expected [re, im]". "Config::to_toml" writes a
+ * job file back out, which is how "--dump-config" shows the effective
+ * settings of any command line.
+ */
+
+use std::error::Error;
+use std::fmt;
+
+use dither::Dither;
+use fixed::Precision;
+use fractal::Fractal;
+use json::Json;
+use light::Light;
+use num::Complex;
+use overlay::Overlay;
+use palette::Palette;
+use render;
+use trap::Trap;
+use view::{View, BASE_RADIUS};
+
+/// The file formats we can write; see image.rs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Png,
+    /// PGM for grayscale, PPM for color.
+    Pnm,
+    Bmp,
+    Tiff
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::Png  => "png",
+            Format::Pnm  => "pnm",
+            Format::Bmp  => "bmp",
+            Format::Tiff => "tiff"
+        }
+    }
+
+    /// Parse a format name, which may also be any file extension that
+    /// `from_extension` recognizes.
+    pub fn parse(s: &str) -> Option<Format> {
+        match s {
+            "png"                 => Some(Format::Png),
+            "pnm" | "pgm" | "ppm" => Some(Format::Pnm),
+            "bmp"                 => Some(Format::Bmp),
+            "tiff" | "tif"        => Some(Format::Tiff),
+            _                     => None
+        }
+    }
+
+    /// Return the format that the file name `path` suggests, if its
+    /// extension is one we know, in either case.
+    pub fn from_extension(path: &str) -> Option<Format> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        Format::parse(&extension.to_ascii_lowercase())
+    }
+}
+
+/// How to turn an escaped orbit into a position along the palette; see
+/// "Orbit Averages" in orbit.rs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Coloring {
+    /// By smooth escape time.
+    Escape,
+    /// By stripe average, with this many stripes per turn about the origin.
+    Stripe(f64),
+    /// By triangle inequality average.
+    Triangle
+}
+
+/// The stripe density "stripe" alone asks for.
+pub const DEFAULT_STRIPE_DENSITY: f64 = 5.0;
+
+impl Coloring {
+    /// Parse "escape", "triangle", "stripe", or "stripe:DENSITY".
+    pub fn parse(s: &str) -> Option<Coloring> {
+        match s.split_once(':') {
+            None => match s {
+                "escape"   => Some(Coloring::Escape),
+                "stripe"   => Some(Coloring::Stripe(DEFAULT_STRIPE_DENSITY)),
+                "triangle" => Some(Coloring::Triangle),
+                _          => None
+            },
+            Some(("stripe", density)) => match density.trim().parse::<f64>() {
+                Ok(density) if density.is_finite() => Some(Coloring::Stripe(density)),
+                _ => None
+            },
+            Some(_) => None
+        }
+    }
+
+    /// Write this coloring so that `parse` reads it back exactly.
+    pub fn spec(&self) -> String {
+        match *self {
+            Coloring::Escape => "escape".to_string(),
+            Coloring::Stripe(density) => format!("stripe:{:?}", density),
+            Coloring::Triangle => "triangle".to_string()
+        }
+    }
+}
+
+/// Everything needed to make one image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub fractal: Fractal,
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    /// How far the frame between the corners is turned about its center,
+    /// counterclockwise, in degrees.
+    pub rotation: f64,
+    pub bounds: (usize, usize),
+    pub output: String,
+    /// The format to write, or `None` to go by the output file's extension;
+    /// see `format`.
+    pub format: Option<Format>,
+    /// Bits per sample: 8, or 16 for formats that allow it.
+    pub depth: u8,
+    /// How to reduce the render to 8-bit samples.
+    pub dither: Dither,
+    /// Overlays as `Overlay::parse` accepts them, to draw over the render,
+    /// or `None`.
+    pub overlay: Option<String>,
+    /// A title to write across the top of the image, or `None`.
+    pub title: Option<String>,
+    pub limit: u32,
+    /// The number of samples per pixel along each axis.
+    pub antialias: u32,
+    /// A palette as `Palette::parse` accepts it, or `None` for grayscale.
+    pub palette: Option<String>,
+    /// How many iterations one trip through the palette takes, or `None` to
+    /// stretch the palette once over the whole iteration limit.
+    pub cycle: Option<f64>,
+    pub coloring: Coloring,
+    /// An orbit trap as `Trap::parse` accepts it, to color by instead of
+    /// escape time, or `None`.
+    pub trap: Option<String>,
+    /// A light as `Light::parse` accepts it, to shade with, or `None`.
+    pub light: Option<String>,
+    /// The arithmetic to iterate with.
+    pub precision: Precision
+}
+
+/// The largest number of samples per pixel along each axis we allow.
+pub const MAX_ANTIALIAS: u32 = 16;
+
+/// A problem with a job file.
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    /// The line the problem is on, counting from one, if it's on a line.
+    pub line: Option<usize>,
+    /// The full name of the key involved, like "view.upper_left".
+    pub key: Option<String>,
+    pub message: String
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if let Some(ref key) = self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+/// A value on the right-hand side of an `=`.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Value>)
+}
+
+impl Value {
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(i) => Some(i as f64),
+            Value::Float(x) => Some(x),
+            _ => None
+        }
+    }
+
+    fn as_complex(&self) -> Option<Complex<f64>> {
+        match *self {
+            Value::Array(ref items) if items.len() == 2 =>
+                Some(Complex { re: items[0].as_f64()?, im: items[1].as_f64()? }),
+            _ => None
+        }
+    }
+}
+
+/// Remove a comment from the end of `line`, minding `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[.. i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parse a double-quoted string, returning it and whatever follows it.
+fn parse_string(s: &str) -> Result<(String, &str), String> {
+    let mut result = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '"' => return Ok((result, &s[i + 1 ..])),
+            '\\' => match chars.next() {
+                Some((_, '"'))  => result.push('"'),
+                Some((_, '\\')) => result.push('\\'),
+                Some((_, 'n'))  => result.push('\n'),
+                Some((_, 't'))  => result.push('\t'),
+                Some((i, 'u'))  => {
+                    let ch = s.get(i + 1 .. i + 5)
+                        .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| "bad \\u escape in string".to_string())?;
+                    result.push(ch);
+                    chars.nth(3);
+                }
+                _ => return Err("unknown escape in string".to_string())
+            },
+            _ => result.push(ch)
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Return `s` as a quoted string "parse_string" reads back unchanged.
+fn quote(s: &str) -> String {
+    let mut text = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"'  => text += "\\\"",
+            '\\' => text += "\\\\",
+            '\n' => text += "\\n",
+            '\t' => text += "\\t",
+            ch if ch.is_control() => text += &format!("\\u{:04x}", ch as u32),
+            ch => text.push(ch)
+        }
+    }
+    text.push('"');
+    text
+}
+
+/// Parse a value that isn't an array.
+fn parse_scalar(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if s.starts_with('"') {
+        let (string, rest) = parse_string(s)?;
+        if !rest.trim().is_empty() {
+            return Err("unexpected text after string".to_string());
+        }
+        return Ok(Value::Str(string));
+    }
+    match s {
+        "true"  => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        ""      => return Err("missing value".to_string()),
+        _       => {}
+    }
+
+    let digits = s.replace('_', "");
+    if let Ok(i) = digits.parse::<i64>() {
+        return Ok(Value::Int(i));
+    }
+    match digits.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(Value::Float(x)),
+        _ => Err(format!("can't understand value `{}`", s))
+    }
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner.strip_suffix(']').ok_or("unterminated array")?;
+        let mut items = Vec::new();
+        let mut parts: Vec<&str> = inner.split(',').collect();
+        if parts.last().map(|p| p.trim().is_empty()) == Some(true) {
+            parts.pop();
+        }
+        for part in parts {
+            if part.contains('"') || part.contains('[') {
+                return Err("arrays may only hold numbers and booleans".to_string());
+            }
+            items.push(parse_scalar(part)?);
+        }
+        return Ok(Value::Array(items));
+    }
+    parse_scalar(s)
+}
+
+/// The keys a job file may use, by section.
+const KEYS: &[(&str, &[&str])] = &[
+    ("fractal", &["type", "c"]),
+    ("view",    &["upper_left", "lower_right", "center", "zoom", "radius", "rotation"]),
+    ("image",   &["size", "output", "format", "depth", "dither", "overlay", "title"]),
+    ("render",  &["limit", "antialias", "palette", "cycle", "coloring", "trap", "light",
+                 "precision"])
+];
+
+/// One `key = value` line, with its key qualified by its section.
+struct Entry {
+    line: usize,
+    key: String,
+    value: Value
+}
+
+/// Split `text` into entries, checking sections and key names.
+fn entries(text: &str) -> Result<Vec<Entry>, ConfigError> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut section: Option<&str> = None;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let content = strip_comment(raw).trim();
+        let error = |key: Option<String>, message: String| {
+            ConfigError { line: Some(line), key, message }
+        };
+
+        if content.is_empty() {
+            continue;
+        }
+        if let Some(name) = content.strip_prefix('[') {
+            let name = name.strip_suffix(']')
+                .ok_or_else(|| error(None, "malformed section header".to_string()))?
+                .trim();
+            section = Some(KEYS.iter().find(|&&(s, _)| s == name).map(|&(s, _)| s)
+                .ok_or_else(|| error(None, format!("unknown section [{}]", name)))?);
+            continue;
+        }
+
+        let equals = content.find('=')
+            .ok_or_else(|| error(None, "expected `key = value`".to_string()))?;
+        let name = content[.. equals].trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(error(None, format!("bad key `{}`", name)));
+        }
+        let section = section
+            .ok_or_else(|| error(Some(name.to_string()), "key is outside any section".to_string()))?;
+        let key = format!("{}.{}", section, name);
+
+        check_key(&entries, line, section, name)?;
+        let value = parse_value(&content[equals + 1 ..])
+            .map_err(|message| error(Some(key.clone()), message))?;
+        entries.push(Entry { line, key, value });
+    }
+    Ok(entries)
+}
+
+/// Check that `name` is a key of `section`, and that `entries` doesn't
+/// already set it.
+fn check_key(entries: &[Entry], line: usize, section: &str, name: &str)
+    -> Result<(), ConfigError>
+{
+    let key = format!("{}.{}", section, name);
+    let known = KEYS.iter().any(|&(s, names)| s == section && names.contains(&name));
+    if !known {
+        return Err(ConfigError { line: Some(line), key: Some(key), message: "unknown key".to_string() });
+    }
+    if let Some(previous) = entries.iter().find(|e| e.key == key) {
+        let message = format!("already set on line {}", previous.line);
+        return Err(ConfigError { line: Some(line), key: Some(key), message });
+    }
+    Ok(())
+}
+
+/* The same settings can be given as a JSON object, with an object for each
+ * section:
+ *
+ *      {"view": {"upper_left": [-2, 1], "lower_right": [1, -1]},
+ *       "image": {"size": [300, 200], "output": "a.png"}}
+ *
+ * which is how batch manifests (see batch.rs) describe their jobs, one per
+ * line. Since the whole object is on one line, every error names that line.
+ */
+
+/// Convert a JSON value to the corresponding job file value.
+fn json_value(json: &Json) -> Result<Value, String> {
+    match *json {
+        Json::Bool(b) => Ok(Value::Bool(b)),
+        Json::String(ref s) => Ok(Value::Str(s.clone())),
+        Json::Number(ref text) => parse_scalar(text),
+        Json::Array(ref items) => {
+            let mut values = Vec::with_capacity(items.len());
+            for item in items {
+                match *item {
+                    Json::Array(_) | Json::Object(_) | Json::String(_) | Json::Null =>
+                        return Err("arrays may only hold numbers and booleans".to_string()),
+                    _ => values.push(json_value(item)?)
+                }
+            }
+            Ok(Value::Array(values))
+        }
+        Json::Null | Json::Object(_) => Err("expected a value".to_string())
+    }
+}
+
+/// Return the entries of a job given as a JSON object on line `line`.
+fn json_entries(json: &Json, line: usize) -> Result<Vec<Entry>, ConfigError> {
+    let error = |key: Option<String>, message: &str| {
+        ConfigError { line: Some(line), key, message: message.to_string() }
+    };
+    let sections = match *json {
+        Json::Object(ref members) => members,
+        _ => return Err(error(None, "expected an object"))
+    };
+
+    let mut entries = Vec::new();
+    for (section, keys) in sections {
+        if !KEYS.iter().any(|&(s, _)| s == section) {
+            return Err(error(None, &format!("unknown section \"{}\"", section)));
+        }
+        let keys = match *keys {
+            Json::Object(ref keys) => keys,
+            _ => return Err(error(Some(section.clone()), "expected an object"))
+        };
+        for (name, value) in keys {
+            check_key(&entries, line, section, name)?;
+            let key = format!("{}.{}", section, name);
+            let value = json_value(value).map_err(|message| error(Some(key.clone()), &message))?;
+            entries.push(Entry { line, key, value });
+        }
+    }
+    Ok(entries)
+}
+
+impl Config {
+    /// Return the configuration for a plain grayscale render of the region
+    /// between `upper_left` and `lower_right` at size `bounds`, written to
+    /// `output`.
+    pub fn new(bounds: (usize, usize),
+               upper_left: Complex<f64>,
+               lower_right: Complex<f64>,
+               output: &str)
+        -> Config
+    {
+        Config {
+            fractal: Fractal::Mandelbrot,
+            upper_left, lower_right,
+            rotation: 0.0,
+            bounds,
+            output: output.to_string(),
+            format: None,
+            depth: 8,
+            dither: Dither::None,
+            overlay: None,
+            title: None,
+            limit: 255,
+            antialias: 1,
+            palette: None,
+            cycle: None,
+            coloring: Coloring::Escape,
+            trap: None,
+            light: None,
+            precision: Precision::Double
+        }
+    }
+
+    /// Parse the text of a job file.
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        Config::from_entries(&entries(text)?)
+    }
+
+    /// Build a configuration from a job given as a JSON object on line `line`
+    /// of some file.
+    pub fn from_json(json: &Json, line: usize) -> Result<Config, ConfigError> {
+        Config::from_entries(&json_entries(json, line)?).map_err(|mut err| {
+            err.line = err.line.or(Some(line));
+            err
+        })
+    }
+
+    fn from_entries(entries: &[Entry]) -> Result<Config, ConfigError> {
+        let find = |key: &str| entries.iter().find(|e| e.key == key);
+        let bad = |entry: &Entry, message: &str| ConfigError {
+            line: Some(entry.line),
+            key: Some(entry.key.clone()),
+            message: message.to_string()
+        };
+        let missing = |key: &str| ConfigError {
+            line: None,
+            key: Some(key.to_string()),
+            message: "required key is missing".to_string()
+        };
+
+        let point = |key: &str| -> Result<Complex<f64>, ConfigError> {
+            let entry = find(key).ok_or_else(|| missing(key))?;
+            entry.value.as_complex().ok_or_else(|| bad(entry, "expected [re, im]"))
+        };
+        let positive = |entry: &Entry| -> Result<f64, ConfigError> {
+            match entry.value.as_f64() {
+                Some(x) if x > 0.0 && x.is_finite() => Ok(x),
+                _ => Err(bad(entry, "expected a positive number"))
+            }
+        };
+        let scale = (find("view.zoom"), find("view.radius"));
+        let view = if find("view.center").is_some() {
+            if let Some(entry) = find("view.upper_left").or_else(|| find("view.lower_right")) {
+                return Err(bad(entry, "can't be given with view.center"));
+            }
+            let radius = match scale {
+                (Some(_), Some(entry)) => return Err(bad(entry, "can't be given with view.zoom")),
+                (Some(entry), None) => BASE_RADIUS / positive(entry)?,
+                (None, Some(entry)) => positive(entry)?,
+                (None, None) => BASE_RADIUS
+            };
+            Some(View::new(point("view.center")?, radius))
+        } else {
+            if let Some(entry) = scale.0.or(scale.1) {
+                return Err(bad(entry, "needs view.center"));
+            }
+            None
+        };
+
+        let entry = find("image.size").ok_or_else(|| missing("image.size"))?;
+        let bounds = match entry.value {
+            Value::Array(ref items) => match items[..] {
+                [Value::Int(w), Value::Int(h)] if w > 0 && h > 0 => (w as usize, h as usize),
+                _ => return Err(bad(entry, "expected [width, height] in pixels"))
+            },
+            _ => return Err(bad(entry, "expected [width, height] in pixels"))
+        };
+
+        let entry = find("image.output").ok_or_else(|| missing("image.output"))?;
+        let output = match entry.value {
+            Value::Str(ref s) if !s.is_empty() => s.clone(),
+            _ => return Err(bad(entry, "expected a file name in quotes"))
+        };
+
+        let mut config = match view {
+            Some(view) => {
+                let (upper_left, lower_right) = view.corners(bounds);
+                Config::new(bounds, upper_left, lower_right, &output)
+            }
+            None => Config::new(bounds, point("view.upper_left")?,
+                                point("view.lower_right")?, &output)
+        };
+        if let Some(entry) = find("view.rotation") {
+            config.rotation = match entry.value.as_f64() {
+                Some(x) if x.is_finite() => x,
+                _ => return Err(bad(entry, "expected a number of degrees"))
+            };
+        }
+
+        if let Some(entry) = find("image.format") {
+            config.format = match entry.value {
+                Value::Str(ref s) => Format::parse(s),
+                _ => None
+            }.ok_or_else(|| bad(entry, "expected \"png\", \"pnm\", \"bmp\" or \"tiff\""))
+                .map(Some)?;
+        }
+        if let Some(entry) = find("image.depth") {
+            config.depth = match entry.value {
+                Value::Int(8) => 8,
+                Value::Int(16) => 16,
+                _ => return Err(bad(entry, "expected 8 or 16"))
+            };
+        }
+        if let Some(entry) = find("image.dither") {
+            config.dither = match entry.value {
+                Value::Str(ref s) => Dither::parse(s),
+                _ => None
+            }.ok_or_else(|| bad(entry, "expected \"none\", \"floyd-steinberg\" or \"bayer\""))?;
+        }
+        if let Some(entry) = find("image.overlay") {
+            config.overlay = match entry.value {
+                Value::Str(ref s) if Overlay::parse(s).is_some() => Some(s.clone()),
+                _ => return Err(bad(entry, "expected a list of \"axes\", \"ticks\" and \"grid\", \
+                                            in quotes"))
+            };
+        }
+        if let Some(entry) = find("image.title") {
+            config.title = match entry.value {
+                Value::Str(ref s) => Some(s.clone()),
+                _ => return Err(bad(entry, "expected text in quotes"))
+            };
+        }
+        if let Err(message) = config.check_format() {
+            let dithered = config.dither != Dither::None && config.depth != 8;
+            let entry = find("image.dither").filter(|_| dithered)
+                .or_else(|| find("image.depth")).or_else(|| find("image.format"))
+                .or_else(|| find("image.output")).unwrap();
+            return Err(bad(entry, &message));
+        }
+
+        let kind = find("fractal.type");
+        let c = find("fractal.c");
+        match kind.map(|e| &e.value) {
+            None => {}
+            Some(Value::Str(s)) if s == "mandelbrot" => {}
+            Some(Value::Str(s)) if s == "julia" => {
+                let entry = c.ok_or_else(|| missing("fractal.c"))?;
+                let c = entry.value.as_complex().ok_or_else(|| bad(entry, "expected [re, im]"))?;
+                config.fractal = Fractal::Julia(c);
+            }
+            Some(_) => return Err(bad(kind.unwrap(), "expected \"mandelbrot\" or \"julia\""))
+        }
+        if let (Fractal::Mandelbrot, Some(entry)) = (config.fractal, c) {
+            return Err(bad(entry, "only a julia fractal takes c"));
+        }
+
+        if let Some(entry) = find("render.limit") {
+            config.limit = match entry.value {
+                Value::Int(n) if n > 0 && n <= u32::MAX as i64 => n as u32,
+                _ => return Err(bad(entry, "expected a positive whole number"))
+            };
+        }
+        if let Some(entry) = find("render.antialias") {
+            config.antialias = match entry.value {
+                Value::Int(n) if (1 ..= MAX_ANTIALIAS as i64).contains(&n) => n as u32,
+                _ => return Err(bad(entry, "expected a whole number from 1 to 16"))
+            };
+        }
+        if let Some(entry) = find("render.palette") {
+            config.palette = match entry.value {
+                Value::Str(ref s) if Palette::parse(s).is_some() => Some(s.clone()),
+                _ => return Err(bad(entry, "expected a palette name or a list of \
+                                            RRGGBB colors, in quotes"))
+            };
+        }
+        if let Some(entry) = find("render.cycle") {
+            config.cycle = match entry.value.as_f64() {
+                Some(x) if x > 0.0 => Some(x),
+                _ => return Err(bad(entry, "expected a positive number"))
+            };
+        }
+        if let Some(entry) = find("render.coloring") {
+            config.coloring = match entry.value {
+                Value::Str(ref s) => Coloring::parse(s),
+                _ => None
+            }.ok_or_else(|| bad(entry, "expected \"escape\", \"stripe\", \"stripe:DENSITY\" \
+                                        or \"triangle\""))?;
+        }
+        if let Some(entry) = find("render.trap") {
+            if let Some(coloring) = find("render.coloring") {
+                if config.coloring != Coloring::Escape {
+                    return Err(bad(coloring, "can't be given with render.trap"));
+                }
+            }
+            config.trap = match entry.value {
+                Value::Str(ref s) if Trap::parse(s).is_some() => Some(s.clone()),
+                _ => return Err(bad(entry, "expected a trap like \"circle:RE,IM,RADIUS\", \
+                                            in quotes"))
+            };
+        }
+        if let Some(entry) = find("render.light") {
+            config.light = match entry.value {
+                Value::Str(ref s) if Light::parse(s).is_some() => Some(s.clone()),
+                _ => return Err(bad(entry, "expected a light like \"phong:ANGLE,HEIGHT\", \
+                                            in quotes"))
+            };
+        }
+        if let Some(entry) = find("render.precision") {
+            config.precision = match entry.value {
+                Value::Str(ref s) => Precision::parse(s),
+                _ => None
+            }.ok_or_else(|| bad(entry, "expected \"double\" or \"fixed\""))?;
+            config.check_precision().map_err(|message| bad(entry, &message))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Return the view this configuration shows.
+    pub fn view(&self) -> View {
+        View { rotation: self.rotation, ..View::from_corners(self.upper_left, self.lower_right) }
+    }
+
+    /// Show `view` instead, fitted to the image size.
+    pub fn set_view(&mut self, view: View) {
+        let (upper_left, lower_right) = view.corners(self.bounds);
+        self.upper_left = upper_left;
+        self.lower_right = lower_right;
+        self.rotation = view.rotation;
+    }
+
+    /// Return the point that pixel `pixel` of an image of size `bounds` shows.
+    /// This is exactly "render::pixel_to_point" unless the view is rotated.
+    pub fn pixel_to_point(&self, bounds: (usize, usize), pixel: (usize, usize)) -> Complex<f64> {
+        let point = render::pixel_to_point(bounds, pixel, self.upper_left, self.lower_right);
+        self.view().rotate(point)
+    }
+
+    /// Return the format to write: the one asked for, or else the one the
+    /// output file's extension suggests, or else PNG.
+    pub fn format(&self) -> Format {
+        self.format.or_else(|| Format::from_extension(&self.output)).unwrap_or(Format::Png)
+    }
+
+    /// Check that the format can hold samples of this depth.
+    pub fn check_format(&self) -> Result<(), String> {
+        if self.format() == Format::Bmp && self.depth != 8 {
+            return Err("BMP files can only hold 8-bit samples".to_string());
+        }
+        if self.dither != Dither::None && self.depth != 8 {
+            return Err("dithering is only for 8-bit samples".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check that the precision can compute everything the coloring needs.
+    pub fn check_precision(&self) -> Result<(), String> {
+        if self.precision == Precision::Fixed &&
+            (self.trap.is_some() || self.coloring != Coloring::Escape || self.light.is_some())
+        {
+            return Err("fixed precision only colors by escape time, without traps, \
+                        orbit averages or lighting".to_string());
+        }
+        Ok(())
+    }
+
+    /// Return the number of samples per pixel of the rendered image.
+    pub fn channels(&self) -> usize {
+        if self.palette.is_some() { 3 } else { 1 }
+    }
+
+    /// Return the number of bytes per pixel of the rendered image.
+    pub fn pixel_bytes(&self) -> usize {
+        self.channels() * self.depth as usize / 8
+    }
+
+    /// Return true if this is the plain grayscale Mandelbrot render that the
+    /// original program made, which some rendering modes are limited to.
+    pub fn is_plain(&self) -> bool {
+        self.fractal == Fractal::Mandelbrot && self.rotation == 0.0 && self.limit == 255 &&
+            self.antialias == 1 && self.palette.is_none() && self.depth == 8 &&
+            self.dither == Dither::None && self.coloring == Coloring::Escape && self.trap.is_none() &&
+            self.light.is_none() && self.overlay.is_none() && self.title.is_none() &&
+            self.precision == Precision::Double
+    }
+
+    /// Write this configuration as a job file.
+    pub fn to_toml(&self) -> String {
+        let mut text = String::from("[fractal]\n");
+        text += &format!("type = {}\n", quote(self.fractal.name()));
+        if let Fractal::Julia(c) = self.fractal {
+            text += &format!("c = [{:?}, {:?}]\n", c.re, c.im);
+        }
+        text += &format!("\n[view]\nupper_left = [{:?}, {:?}]\nlower_right = [{:?}, {:?}]\n",
+                         self.upper_left.re, self.upper_left.im,
+                         self.lower_right.re, self.lower_right.im);
+        if self.rotation != 0.0 {
+            text += &format!("rotation = {:?}\n", self.rotation);
+        }
+        let view = self.view();
+        text += &format!("# or: center = [{:?}, {:?}], zoom = {:?}\n",
+                         view.center.re, view.center.im, view.zoom());
+        text += &format!("\n[image]\nsize = [{}, {}]\noutput = {}\n",
+                         self.bounds.0, self.bounds.1, quote(&self.output));
+        if let Some(format) = self.format {
+            text += &format!("format = {}\n", quote(format.name()));
+        }
+        if self.depth != 8 {
+            text += &format!("depth = {}\n", self.depth);
+        }
+        if self.dither != Dither::None {
+            text += &format!("dither = {}\n", quote(self.dither.name()));
+        }
+        if let Some(ref overlay) = self.overlay {
+            text += &format!("overlay = {}\n", quote(overlay));
+        }
+        if let Some(ref title) = self.title {
+            text += &format!("title = {}\n", quote(title));
+        }
+        text += &format!("\n[render]\nlimit = {}\nantialias = {}\n", self.limit, self.antialias);
+        if let Some(ref palette) = self.palette {
+            text += &format!("palette = {}\n", quote(palette));
+        }
+        if let Some(cycle) = self.cycle {
+            text += &format!("cycle = {:?}\n", cycle);
+        }
+        if self.coloring != Coloring::Escape {
+            text += &format!("coloring = {}\n", quote(&self.coloring.spec()));
+        }
+        if let Some(ref trap) = self.trap {
+            text += &format!("trap = {}\n", quote(trap));
+        }
+        if let Some(ref light) = self.light {
+            text += &format!("light = {}\n", quote(light));
+        }
+        if self.precision != Precision::Double {
+            text += &format!("precision = {}\n", quote(self.precision.name()));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+const EXAMPLE: &str = "\
+# The seahorse valley, in color.
+[fractal]
+type = \"julia\"   # a comment
+c = [-0.8, 0.156]
+
+[view]
+upper_left = [-1.5, 1]
+lower_right = [1.5, -1.0]
+
+[image]
+size = [1_000, 750]
+output = \"sea \\\"horses\\\" #1.png\"
+dither = \"floyd-steinberg\"
+overlay = \"axes, grid\"
+title = \"Seahorses\"
+
+[render]
+limit = 1000
+antialias = 3
+palette = \"ultra\"
+cycle = 64
+trap = \"cross:0.5,0\"
+light = \"lambert:90\"
+";
+
+#[test]
+fn test_parse_example() {
+    let config = Config::parse(EXAMPLE).unwrap();
+    assert_eq!(config, Config {
+        fractal: Fractal::Julia(Complex { re: -0.8, im: 0.156 }),
+        upper_left: Complex { re: -1.5, im: 1.0 },
+        lower_right: Complex { re: 1.5, im: -1.0 },
+        rotation: 0.0,
+        bounds: (1000, 750),
+        output: "sea \"horses\" #1.png".to_string(),
+        format: None,
+        depth: 8,
+        dither: Dither::FloydSteinberg,
+        overlay: Some("axes, grid".to_string()),
+        title: Some("Seahorses".to_string()),
+        limit: 1000,
+        antialias: 3,
+        palette: Some("ultra".to_string()),
+        cycle: Some(64.0),
+        coloring: Coloring::Escape,
+        trap: Some("cross:0.5,0".to_string()),
+        light: Some("lambert:90".to_string()),
+        precision: Precision::Double
+    });
+}
+
+#[test]
+fn test_to_toml_round_trip() {
+    let config = Config::parse(EXAMPLE).unwrap();
+    assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+
+    let plain = Config::new((400, 300), Complex { re: -1.2, im: 0.35 },
+                            Complex { re: -1.0, im: 0.2 }, "mandel.png");
+    assert_eq!(Config::parse(&plain.to_toml()).unwrap(), plain);
+    assert!(plain.is_plain());
+    assert!(!config.is_plain());
+
+    let fixed = Config { precision: Precision::Fixed, ..plain.clone() };
+    assert_eq!(Config::parse(&fixed.to_toml()).unwrap(), fixed);
+    assert!(!fixed.is_plain());
+
+    // Any limit "--limit" takes survives the trip.
+    let highest = Config { limit: u32::MAX, ..plain.clone() };
+    assert_eq!(Config::parse(&highest.to_toml()).unwrap(), highest);
+    let over = plain.to_toml().replace("limit = 255", "limit = 4294967296");
+    assert!(Config::parse(&over).is_err());
+
+    for coloring in [Coloring::Stripe(2.5), Coloring::Triangle] {
+        let config = Config { coloring, trap: None, ..config.clone() };
+        assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+    }
+
+    // Titles with quotes, backslashes and control characters come back as
+    // they went.
+    let title = "\"Seahorse\nvalley\"\t\\ \r\x07\u{7f}\u{e9}";
+    let titled = Config { title: Some(title.to_string()), ..plain.clone() };
+    assert_eq!(Config::parse(&titled.to_toml()).unwrap(), titled);
+    assert_eq!(parse_string("\"a\\u00e9\\u0007\"").unwrap(), ("a\u{e9}\u{7}".to_string(), ""));
+    for bad in ["\"\\u12\"", "\"\\u+12a\"", "\"\\ud800\"", "\"\\u"] {
+        assert!(parse_string(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_errors_name_key_and_line() {
+    let check = |text: &str, expected: &str| {
+        assert_eq!(Config::parse(text).unwrap_err().to_string(), expected);
+    };
+    let valid = "[view]\nupper_left = [-2, 1]\nlower_right = [1, -1]\n\
+                 [image]\nsize = [300, 200]\noutput = \"a.png\"\n";
+    assert!(Config::parse(valid).is_ok());
+
+    check(&valid.replace("[-2, 1]", "[-2]"), "line 2: view.upper_left: expected [re, im]");
+    check(&valid.replace("[300, 200]", "[300, 0]"),
+          "line 5: image.size: expected [width, height] in pixels");
+    check(&format!("{}[render]\nlimit = 0\n", valid),
+          "line 8: render.limit: expected a positive whole number");
+    check(&format!("{}[render]\nantialias = 17\n", valid),
+          "line 8: render.antialias: expected a whole number from 1 to 16");
+    check(&format!("{}[render]\npalette = \"plaid\"\n", valid),
+          "line 8: render.palette: expected a palette name or a list of RRGGBB colors, in quotes");
+    check(&format!("{}[render]\ncoloring = \"stripe:x\"\n", valid),
+          "line 8: render.coloring: expected \"escape\", \"stripe\", \"stripe:DENSITY\" \
+           or \"triangle\"");
+    check(&format!("{}[render]\ncoloring = \"triangle\"\ntrap = \"point\"\n", valid),
+          "line 8: render.coloring: can't be given with render.trap");
+    check(&format!("{}[render]\ntrap = \"star:1\"\n", valid),
+          "line 8: render.trap: expected a trap like \"circle:RE,IM,RADIUS\", in quotes");
+    check(&format!("{}[render]\nlight = \"phong:0,100\"\n", valid),
+          "line 8: render.light: expected a light like \"phong:ANGLE,HEIGHT\", in quotes");
+    check(&format!("{}[render]\nprecision = \"quad\"\n", valid),
+          "line 8: render.precision: expected \"double\" or \"fixed\"");
+    check(&format!("{}[render]\ncoloring = \"triangle\"\nprecision = \"fixed\"\n", valid),
+          "line 9: render.precision: fixed precision only colors by escape time, without traps, \
+           orbit averages or lighting");
+    check(&format!("{}[render]\nlimt = 10\n", valid), "line 8: render.limt: unknown key");
+    check(&format!("{}[colour]\n", valid), "line 7: unknown section [colour]");
+    check(&format!("{}size = [1, 1]\n", valid),
+          "line 7: image.size: already set on line 5");
+    check(&format!("limit = 3\n{}", valid), "line 1: limit: key is outside any section");
+    check(&format!("{}output = \"b.png\n", valid.replace("output = \"a.png\"\n", "")),
+          "line 6: image.output: unterminated string");
+    check(&valid.replace("upper_left", "# upper_left"), "view.upper_left: required key is missing");
+    check(&format!("[fractal]\ntype = \"julia\"\n{}", valid), "fractal.c: required key is missing");
+    check(&format!("[fractal]\nc = [0, 0]\n{}", valid), "line 2: fractal.c: only a julia fractal takes c");
+    check(&format!("[fractal]\ntype = \"burning ship\"\n{}", valid),
+          "line 2: fractal.type: expected \"mandelbrot\" or \"julia\"");
+    check(&valid.replace("a.png", "a.tga\"\nformat = \"tga"),
+          "line 7: image.format: expected \"png\", \"pnm\", \"bmp\" or \"tiff\"");
+    check(&format!("{}depth = 12\n", valid), "line 7: image.depth: expected 8 or 16");
+    check(&format!("{}overlay = \"axes,labels\"\n", valid),
+          "line 7: image.overlay: expected a list of \"axes\", \"ticks\" and \"grid\", in quotes");
+    check(&format!("{}title = 3\n", valid), "line 7: image.title: expected text in quotes");
+    check(&format!("{}depth = 16\n", valid.replace("a.png", "a.bmp")),
+          "line 7: image.depth: BMP files can only hold 8-bit samples");
+    check(&format!("{}dither = \"ordered\"\n", valid),
+          "line 7: image.dither: expected \"none\", \"floyd-steinberg\" or \"bayer\"");
+    check(&format!("{}depth = 16\ndither = \"bayer\"\n", valid),
+          "line 8: image.dither: dithering is only for 8-bit samples");
+}
+
+#[test]
+fn test_format() {
+    let mut config = Config::new((4, 3), Complex { re: -2.0, im: 1.0 },
+                                 Complex { re: 1.0, im: -1.0 }, "out.PPM");
+    assert_eq!(config.format(), Format::Pnm);
+    config.output = "out.tif".to_string();
+    assert_eq!(config.format(), Format::Tiff);
+    config.output = "out".to_string();
+    assert_eq!(config.format(), Format::Png);
+    config.format = Some(Format::Bmp);
+    assert_eq!(config.format(), Format::Bmp);
+    assert!(config.check_format().is_ok());
+    config.depth = 16;
+    assert!(config.check_format().is_err());
+    assert_eq!(config.pixel_bytes(), 2);
+
+    config.format = None;
+    assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+    config.dither = Dither::Bayer;
+    assert!(config.check_format().is_err());
+    config.depth = 8;
+    assert!(config.check_format().is_ok() && !config.is_plain());
+}
+
+#[test]
+fn test_view_keys() {
+    let image = "[image]\nsize = [300, 200]\noutput = \"a.png\"\n";
+    let config = Config::parse(&format!("[view]\ncenter = [-0.5, 0]\nzoom = 2\nrotation = 30\n{}",
+                                        image)).unwrap();
+    assert_eq!((config.upper_left, config.lower_right),
+               (Complex { re: -2.0, im: 1.0 }, Complex { re: 1.0, im: -1.0 }));
+    assert_eq!(config.rotation, 30.0);
+    assert_eq!(config.view(), View { center: Complex { re: -0.5, im: 0.0 }, radius: 1.0, rotation: 30.0 });
+    assert!(!config.is_plain());
+    assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+
+    let radius = Config::parse(&format!("[view]\ncenter = [-0.5, 0]\nradius = 1\n{}", image));
+    assert_eq!(radius.unwrap().upper_left, config.upper_left);
+    let default = Config::parse(&format!("[view]\ncenter = [0, 0]\n{}", image)).unwrap();
+    assert_eq!(default.view().zoom(), 1.0);
+
+    let check = |view: &str, expected: &str| {
+        let err = Config::parse(&format!("[view]\n{}{}", view, image)).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    };
+    check("center = [0, 0]\nupper_left = [-1, 1]\n",
+          "line 3: view.upper_left: can't be given with view.center");
+    check("center = [0, 0]\nzoom = 2\nradius = 1\n",
+          "line 4: view.radius: can't be given with view.zoom");
+    check("center = [0, 0]\nzoom = 0\n", "line 3: view.zoom: expected a positive number");
+    check("zoom = 2\n", "line 2: view.zoom: needs view.center");
+    check("center = [0, 0]\nrotation = \"left\"\n",
+          "line 3: view.rotation: expected a number of degrees");
+}
+
+#[test]
+fn test_pixel_to_point() {
+    let mut config = Config::new((40, 20), Complex { re: -2.0, im: 1.0 },
+                                 Complex { re: 2.0, im: -1.0 }, "a.png");
+    assert_eq!(config.pixel_to_point((40, 20), (13, 7)),
+               render::pixel_to_point((40, 20), (13, 7), config.upper_left, config.lower_right));
+
+    config.rotation = 180.0;
+    let point = config.pixel_to_point((40, 20), (0, 0));
+    assert!((point - Complex { re: 2.0, im: -1.0 }).norm() < 1e-12);
+}
+
+#[test]
+fn test_from_json() {
+    use json::parse;
+
+    let job = parse("{\"fractal\": {\"type\": \"julia\", \"c\": [-0.8, 0.156]}, \
+                     \"view\": {\"upper_left\": [-1.5, 1], \"lower_right\": [1.5, -1.0]}, \
+                     \"image\": {\"size\": [1000, 750], \"output\": \"a.png\"}, \
+                     \"render\": {\"palette\": \"ultra\", \"cycle\": 64}}").unwrap();
+    let config = Config::from_json(&job, 3).unwrap();
+    assert_eq!(config.fractal, Fractal::Julia(Complex { re: -0.8, im: 0.156 }));
+    assert_eq!(config.bounds, (1000, 750));
+    assert_eq!(config.cycle, Some(64.0));
+
+    let check = |text: &str, expected: &str| {
+        let err = Config::from_json(&parse(text).unwrap(), 3).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    };
+    check("[]", "line 3: expected an object");
+    check("{\"colour\": {}}", "line 3: unknown section \"colour\"");
+    check("{\"view\": {\"centre\": [0, 0]}}", "line 3: view.centre: unknown key");
+    check("{\"view\": {\"upper_left\": [\"a\", 0]}}",
+          "line 3: view.upper_left: arrays may only hold numbers and booleans");
+    check("{\"view\": {\"upper_left\": [0, 0], \"lower_right\": [1, 1]}}",
+          "line 3: image.size: required key is missing");
+}
@@ -0,0 +1,512 @@
+/* Deflate Compression
+ * -------------------
+ * Deflate, the compression inside zlib and so inside every PNG file, has two
+ * layers. LZ77 replaces any run of bytes that appeared in the last 32 KiB
+ * with a (length, distance) pair pointing back at it; then Huffman codes
+ * spell out the literal bytes and pairs in as few bits as possible.
+ *
+ * We find matches with hash chains: the three bytes at each position are
+ * hashed, "head" remembers the latest position with each hash, and "prev"
+ * links every position to the one before it with the same hash. Walking a
+ * chain visits candidate matches, nearest first.
+ *
+ * For the Huffman layer we use the fixed codes the format defines, which need
+ * no tables in the output. Rendered images are mostly long runs and repeated
+ * rows, which LZ77 alone shrinks enormously. A block that wouldn't get any
+ * smaller is stored instead.
+ *
+ * "Deflater" takes its input a piece at a time, and holds no more than the
+ * block it's working on and the 32 KiB window before it, so it can compress
+ * a file of any size.
+ */
+
+/// How far back a match may reach.
+const WINDOW_SIZE: usize = 32768;
+
+/// The most input we compress as one block. This is also the most a stored
+/// block can hold.
+const BLOCK_SIZE: usize = 65535;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// How many candidates to try before settling for the best match so far.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: usize = 15;
+
+/// The lengths at which each length code (257 to 285) starts, and how many
+/// extra bits follow it.
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// The same for distance codes.
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                  8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The order in which a dynamic block lists its code length code lengths.
+#[cfg(test)]
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                        14, 1, 15];
+
+/// Return the index of the code whose range, starting at `base[index]`,
+/// holds `value`.
+fn code_index(base: &[u16], value: usize) -> usize {
+    base.iter().rposition(|&b| b as usize <= value).unwrap()
+}
+
+/// Return the fixed Huffman code and its length in bits for the literal or
+/// length symbol `symbol`.
+fn fixed_code(symbol: usize) -> (u32, u32) {
+    match symbol {
+        0 ..= 143   => (0x30 + symbol as u32, 8),
+        144 ..= 255 => (0x190 + (symbol - 144) as u32, 9),
+        256 ..= 279 => ((symbol - 256) as u32, 7),
+        _           => (0xc0 + (symbol - 280) as u32, 8)
+    }
+}
+
+/// Gathers bits least significant first, as deflate packs them.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which goes most significant bit first.
+    fn put_code(&mut self, code: u32, length: u32) {
+        self.put(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.put(0, 8 - self.count);
+        }
+    }
+}
+
+/// One step of a block: a literal byte, or a copy of earlier output.
+#[derive(Copy, Clone)]
+enum Symbol {
+    Literal(u8),
+    Copy { length: usize, distance: usize }
+}
+
+/// Compresses a stream of bytes into raw deflate data.
+pub struct Deflater {
+    /// The window of already compressed input, followed by the input not yet
+    /// compressed, which starts at `pending`.
+    buffer: Vec<u8>,
+    pending: usize,
+    /// The position in the whole input of `buffer[0]`.
+    base: usize,
+    /// The position in the whole input of the next byte to hash.
+    hashed: usize,
+    /// For each hash, one more than the latest position with that hash, or
+    /// zero if there is none.
+    head: Vec<usize>,
+    /// For each position in the window, one more than the previous position
+    /// with the same hash, or zero.
+    prev: Vec<usize>,
+    bits: BitWriter
+}
+
+impl Deflater {
+    pub fn new() -> Deflater {
+        Deflater {
+            buffer: Vec::with_capacity(WINDOW_SIZE + BLOCK_SIZE),
+            pending: 0,
+            base: 0,
+            hashed: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW_SIZE],
+            bits: BitWriter { out: Vec::new(), bits: 0, count: 0 }
+        }
+    }
+
+    /// Compress `data`, which follows whatever was written before.
+    pub fn write(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let room = BLOCK_SIZE - (self.buffer.len() - self.pending);
+            let (now, later) = data.split_at(room.min(data.len()));
+            self.buffer.extend_from_slice(now);
+            data = later;
+            if self.buffer.len() - self.pending == BLOCK_SIZE {
+                self.compress_block(false);
+            }
+        }
+    }
+
+    /// Remove and return the compressed data produced so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bits.out)
+    }
+
+    /// Compress whatever input remains as the final block, and return the
+    /// rest of the compressed data.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.compress_block(true);
+        self.bits.align();
+        self.bits.out
+    }
+
+    fn hash(&self, index: usize) -> usize {
+        let b = &self.buffer[index .. index + MIN_MATCH];
+        let key = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        (key.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Add every position before `position` whose three bytes we have to the
+    /// hash chains.
+    fn hash_until(&mut self, position: usize) {
+        while self.hashed < position && self.hashed - self.base + MIN_MATCH <= self.buffer.len() {
+            let h = self.hash(self.hashed - self.base);
+            self.prev[self.hashed % WINDOW_SIZE] = self.head[h];
+            self.head[h] = self.hashed + 1;
+            self.hashed += 1;
+        }
+    }
+
+    /// Return the longest match for the bytes at `index` in the buffer that
+    /// doesn't run past its end, as a (length, distance) pair.
+    fn longest_match(&self, index: usize) -> (usize, usize) {
+        let limit = MAX_MATCH.min(self.buffer.len() - index);
+        if limit < MIN_MATCH {
+            return (0, 0);
+        }
+        let position = self.base + index;
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(index)];
+        for _ in 0 .. MAX_CHAIN {
+            if candidate == 0 || position - (candidate - 1) > WINDOW_SIZE {
+                break;
+            }
+            let start = candidate - 1 - self.base;
+            let earlier = &self.buffer[start .. start + limit];
+            let here = &self.buffer[index .. index + limit];
+            if earlier[best.0.min(limit - 1)] == here[best.0.min(limit - 1)] {
+                let length = earlier.iter().zip(here).take_while(|&(a, b)| a == b).count();
+                if length > best.0 {
+                    best = (length, index - start);
+                    if length == limit {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[(candidate - 1) % WINDOW_SIZE];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        if best.0 < MIN_MATCH { (0, 0) } else { best }
+    }
+
+    fn compress_block(&mut self, last: bool) {
+        let mut symbols = Vec::new();
+        let mut index = self.pending;
+        while index < self.buffer.len() {
+            self.hash_until(self.base + index);
+            let (length, distance) = self.longest_match(index);
+            if length == 0 {
+                symbols.push(Symbol::Literal(self.buffer[index]));
+                index += 1;
+            } else {
+                symbols.push(Symbol::Copy { length, distance });
+                index += length;
+            }
+        }
+        self.hash_until(self.base + index);
+
+        let input = &self.buffer[self.pending ..];
+        let fixed_bits: usize = 3 + 7 + symbols.iter().map(|symbol| match *symbol {
+            Symbol::Literal(byte) => fixed_code(byte as usize).1 as usize,
+            Symbol::Copy { length, distance } => {
+                let l = code_index(&LENGTH_BASE, length);
+                let d = code_index(&DISTANCE_BASE, distance);
+                fixed_code(257 + l).1 as usize + LENGTH_EXTRA[l] as usize +
+                    5 + DISTANCE_EXTRA[d] as usize
+            }
+        }).sum::<usize>();
+        let stored_bits = 3 + 7 + 32 + input.len() * 8;
+
+        let bits = &mut self.bits;
+        if stored_bits < fixed_bits {
+            bits.put(last as u32, 3);
+            bits.align();
+            let length = input.len() as u16;
+            bits.out.extend_from_slice(&length.to_le_bytes());
+            bits.out.extend_from_slice(&(!length).to_le_bytes());
+            bits.out.extend_from_slice(input);
+        } else {
+            bits.put(last as u32 | 1 << 1, 3);
+            for symbol in symbols {
+                match symbol {
+                    Symbol::Literal(byte) => {
+                        let (code, length) = fixed_code(byte as usize);
+                        bits.put_code(code, length);
+                    }
+                    Symbol::Copy { length, distance } => {
+                        let l = code_index(&LENGTH_BASE, length);
+                        let (code, code_length) = fixed_code(257 + l);
+                        bits.put_code(code, code_length);
+                        bits.put((length - LENGTH_BASE[l] as usize) as u32, LENGTH_EXTRA[l] as u32);
+                        let d = code_index(&DISTANCE_BASE, distance);
+                        bits.put_code(d as u32, 5);
+                        bits.put((distance - DISTANCE_BASE[d] as usize) as u32,
+                                 DISTANCE_EXTRA[d] as u32);
+                    }
+                }
+            }
+            let (code, length) = fixed_code(256);
+            bits.put_code(code, length);
+        }
+
+        // Keep only the window the next block may refer back to.
+        let drop = self.buffer.len().saturating_sub(WINDOW_SIZE);
+        self.buffer.drain(.. drop);
+        self.base += drop;
+        self.pending = self.buffer.len();
+    }
+}
+
+/* Decompression
+ * -------------
+ * Nothing we do needs to read compressed data back, so this is only built
+ * for the tests, which check what the compressor wrote and read PNG files
+ * that may use any kind of block. "inflate" follows the format directly,
+ * decoding Huffman codes a bit at a time.
+ */
+
+#[cfg(test)]
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u32
+}
+
+#[cfg(test)]
+impl<'a> BitReader<'a> {
+    fn get(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0 .. count {
+            let byte = *self.data.get(self.position).ok_or("compressed data is truncated")?;
+            value |= ((byte as u32 >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code: how many codes there are of each length, and
+/// the symbols in code order.
+#[cfg(test)]
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>
+}
+
+#[cfg(test)]
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0 .. lengths.len() as u16)
+            .filter(|&s| lengths[s as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<usize, String> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1 .. 16 {
+            code |= input.get(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+/// Decompress the raw deflate data `data`.
+#[cfg(test)]
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut input = BitReader { data, position: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = input.get(1)?;
+        match input.get(2)? {
+            0 => {
+                input.align();
+                let header = data.get(input.position .. input.position + 4)
+                    .ok_or("compressed data is truncated")?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if !length != u16::from_le_bytes([header[2], header[3]]) {
+                    return Err("stored block length is corrupt".to_string());
+                }
+                let start = input.position + 4;
+                let block = data.get(start .. start + length as usize)
+                    .ok_or("compressed data is truncated")?;
+                out.extend_from_slice(block);
+                input.position = start + length as usize;
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                for (symbol, length) in lengths.iter_mut().enumerate() {
+                    *length = fixed_code(symbol).1 as u8;
+                }
+                inflate_block(&mut input, &mut out, &Huffman::new(&lengths),
+                              &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let literals = input.get(5)? as usize + 257;
+                let distances = input.get(5)? as usize + 1;
+                let code_lengths = input.get(4)? as usize + 4;
+                let mut lengths = [0; 19];
+                for &symbol in &CODE_LENGTH_ORDER[.. code_lengths] {
+                    lengths[symbol] = input.get(3)? as u8;
+                }
+                let code = Huffman::new(&lengths);
+
+                let mut lengths = Vec::with_capacity(literals + distances);
+                while lengths.len() < literals + distances {
+                    let (value, repeat) = match code.decode(&mut input)? {
+                        symbol @ 0 ..= 15 => (symbol as u8, 1),
+                        16 => (*lengths.last().ok_or("repeat with no length")?,
+                               3 + input.get(2)?),
+                        17 => (0, 3 + input.get(3)?),
+                        _  => (0, 11 + input.get(7)?)
+                    };
+                    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                }
+                if lengths.len() != literals + distances {
+                    return Err("code lengths overrun".to_string());
+                }
+                inflate_block(&mut input, &mut out, &Huffman::new(&lengths[.. literals]),
+                              &Huffman::new(&lengths[literals ..]))?;
+            }
+            _ => return Err("invalid block type".to_string())
+        }
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+fn inflate_block(input: &mut BitReader, out: &mut Vec<u8>,
+                 literals: &Huffman, distances: &Huffman)
+    -> Result<(), String>
+{
+    loop {
+        let symbol = literals.decode(input)?;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let l = symbol - 257;
+            if l >= LENGTH_BASE.len() {
+                return Err("invalid length code".to_string());
+            }
+            let length = LENGTH_BASE[l] as usize + input.get(LENGTH_EXTRA[l] as u32)? as usize;
+            let d = distances.decode(input)?;
+            if d >= DISTANCE_BASE.len() {
+                return Err("invalid distance code".to_string());
+            }
+            let distance = DISTANCE_BASE[d] as usize + input.get(DISTANCE_EXTRA[d] as u32)? as usize;
+            if distance > out.len() {
+                return Err("distance reaches before the start".to_string());
+            }
+            let start = out.len() - distance;
+            for i in 0 .. length {
+                out.push(out[start + i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn deflate(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut deflater = Deflater::new();
+    let mut out = Vec::new();
+    for piece in pieces {
+        deflater.write(piece);
+        out.extend(deflater.take_output());
+    }
+    out.extend(deflater.finish());
+    out
+}
+
+#[test]
+fn test_round_trip() {
+    assert_eq!(inflate(&deflate(&[])).unwrap(), b"");
+    assert_eq!(inflate(&deflate(&[b"a"])).unwrap(), b"a");
+
+    let text = b"It was the best of times, it was the worst of times, it was the age of wisdom";
+    let compressed = deflate(&[text]);
+    assert!(compressed.len() < text.len());
+    assert_eq!(inflate(&compressed).unwrap(), &text[..]);
+
+    // Long runs, matches across pieces and blocks, and data that won't
+    // compress, which goes into stored blocks.
+    let mut data = vec![7; 100_000];
+    let mut seed = 12345u32;
+    for _ in 0 .. 150_000 {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        data.push((seed >> 16) as u8);
+    }
+    data.extend_from_within(230_000 .. 250_000);
+    let pieces: Vec<&[u8]> = data.chunks(9_999).collect();
+    let compressed = deflate(&pieces);
+    assert!(compressed.len() < 160_000);
+    assert_eq!(inflate(&compressed).unwrap(), data);
+}
+
+#[test]
+fn test_inflate_dynamic() {
+    // Eighty random letters, mostly "a", as zlib compresses them.
+    let compressed = [0x2d, 0x8a, 0xc9, 0x11, 0x00, 0x30, 0x00, 0x01, 0x6b, 0x75, 0xf4, 0x5f,
+                      0x43, 0x6c, 0x12, 0x0f, 0xcc, 0x22, 0x27, 0xd1, 0x34, 0xb3, 0x6e, 0x2b,
+                      0x75, 0xd0, 0xc4, 0xbc, 0x40, 0x93, 0x90, 0xf7, 0xb2, 0xfe, 0x7d, 0x63,
+                      0xea, 0xb2, 0x18, 0x7e, 0x00];
+    assert_eq!(inflate(&compressed).unwrap(),
+               &b"abcccaaaacaabacaaaadcaabccabaabcabadaaaabbadabaababacaabaaabacaadaacdbdbaabbcaab"[..]);
+    assert!(inflate(&[0x07]).is_err());
+}
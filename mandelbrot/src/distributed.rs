@@ -0,0 +1,449 @@
+/* Distributed Rendering
+ * ---------------------
+ * One render can be spread across several machines. A coordinator cuts the
+ * image into square tiles and listens for workers; each worker connects,
+ * renders whatever tiles it's handed, and sends the pixels back. The
+ * coordinator puts the tiles together and writes the image.
+ *
+ * They talk over TCP in frames: a four-byte big-endian length, then a one-
+ * byte message type, then the message. All numbers are big-endian.
+ *
+ *      HELLO   worker -> coordinator   the four bytes "MBW1"
+ *      JOB     coordinator -> worker   tile id (u32), image width and height,
+ *                                      tile left, top, width and height (u32
+ *                                      each), and the image's upper-left and
+ *                                      lower-right corners (four f64s)
+ *      RESULT  worker -> coordinator   tile id (u32), then the tile's pixels
+ *      DONE    coordinator -> worker   nothing; there's no more work
+ *
+ * A worker that drops its connection, sends garbage, or takes longer than the
+ * timeout to answer is presumed dead: its tile goes back on the queue for
+ * someone else, and the coordinator stops talking to it.
+ */
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use num::Complex;
+use render::render_window;
+
+const HELLO: u8 = 1;
+const JOB: u8 = 2;
+const RESULT: u8 = 3;
+const DONE: u8 = 4;
+
+const HELLO_MAGIC: &[u8; 4] = b"MBW1";
+
+/// The largest frame either side will accept.
+const MAX_FRAME: usize = 64 << 20;
+
+/// The largest tiles whose RESULT frames, type, id and pixels, fit in one.
+pub const MAX_TILE_SIZE: usize = (MAX_FRAME - 5).isqrt();
+
+/// How long the coordinator sleeps between checks for new workers.
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Write one frame of type `kind` holding `body` to `out`.
+pub fn write_frame<W: Write>(out: &mut W, kind: u8, body: &[u8]) -> io::Result<()> {
+    if body.len() >= MAX_FRAME {
+        return Err(protocol_error("frame too long"));
+    }
+    out.write_all(&(body.len() as u32 + 1).to_be_bytes())?;
+    out.write_all(&[kind])?;
+    out.write_all(body)?;
+    out.flush()
+}
+
+/// Read one frame from `input`, returning its type and body.
+pub fn read_frame<R: Read>(input: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length == 0 || length > MAX_FRAME {
+        return Err(protocol_error("bad frame length"));
+    }
+    let mut kind = [0];
+    input.read_exact(&mut kind)?;
+    let mut body = vec![0; length - 1];
+    input.read_exact(&mut body)?;
+    Ok((kind[0], body))
+}
+
+#[test]
+fn test_frames() {
+    let mut buffer = Vec::new();
+    write_frame(&mut buffer, RESULT, &[1, 2, 3]).unwrap();
+    assert_eq!(buffer, [0, 0, 0, 4, RESULT, 1, 2, 3]);
+    assert_eq!(read_frame(&mut &buffer[..]).unwrap(), (RESULT, vec![1, 2, 3]));
+    assert!(read_frame(&mut &buffer[..5]).is_err());
+    assert!(read_frame(&mut &[0u8, 0, 0, 0][..]).is_err());
+
+    // Frames too long to read are never written.
+    let mut buffer = Vec::new();
+    assert!(write_frame(&mut buffer, RESULT, &vec![0; MAX_FRAME]).is_err());
+    assert!(buffer.is_empty());
+    let larger = MAX_TILE_SIZE + 1;
+    assert!(write_frame(&mut buffer, RESULT, &vec![0; 4 + larger * larger]).is_err());
+    // The largest tiles still make it.
+    write_frame(&mut buffer, RESULT, &vec![0; 4 + MAX_TILE_SIZE * MAX_TILE_SIZE]).unwrap();
+    assert_eq!(read_frame(&mut &buffer[..]).unwrap().1.len(), 4 + MAX_TILE_SIZE * MAX_TILE_SIZE);
+}
+
+/// One tile of the image to be rendered.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Job {
+    pub id: u32,
+    pub bounds: (usize, usize),
+    pub origin: (usize, usize),
+    pub window: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>
+}
+
+impl Job {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(60);
+        for n in [self.id as usize, self.bounds.0, self.bounds.1,
+                  self.origin.0, self.origin.1, self.window.0, self.window.1] {
+            body.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        for x in [self.upper_left.re, self.upper_left.im,
+                  self.lower_right.re, self.lower_right.im] {
+            body.extend_from_slice(&x.to_be_bytes());
+        }
+        body
+    }
+
+    fn decode(body: &[u8]) -> io::Result<Job> {
+        if body.len() != 60 {
+            return Err(protocol_error("bad job length"));
+        }
+        let word = |i: usize| {
+            u32::from_be_bytes([body[i * 4], body[i * 4 + 1], body[i * 4 + 2], body[i * 4 + 3]])
+                as usize
+        };
+        let float = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&body[28 + i * 8 .. 36 + i * 8]);
+            f64::from_be_bytes(bytes)
+        };
+        let job = Job {
+            id: word(0) as u32,
+            bounds: (word(1), word(2)),
+            origin: (word(3), word(4)),
+            window: (word(5), word(6)),
+            upper_left: Complex { re: float(0), im: float(1) },
+            lower_right: Complex { re: float(2), im: float(3) }
+        };
+        if job.origin.0 + job.window.0 > job.bounds.0
+            || job.origin.1 + job.window.1 > job.bounds.1
+        {
+            return Err(protocol_error("job window lies outside the image"));
+        }
+        Ok(job)
+    }
+
+    /// Render this tile using `threads` threads.
+    pub fn render(&self, threads: usize) -> Vec<u8> {
+        let mut pixels = vec![0; self.window.0 * self.window.1];
+        if pixels.is_empty() {
+            return pixels;
+        }
+        let rows_per_band = self.window.1 / threads.max(1) + 1;
+        thread::scope(|spawner| {
+            for (i, band) in pixels.chunks_mut(rows_per_band * self.window.0).enumerate() {
+                let origin = (self.origin.0, self.origin.1 + i * rows_per_band);
+                let window = (self.window.0, band.len() / self.window.0);
+                spawner.spawn(move || {
+                    render_window(band, self.bounds, origin, window,
+                                  self.upper_left, self.lower_right);
+                });
+            }
+        });
+        pixels
+    }
+}
+
+#[test]
+fn test_job_encoding() {
+    let job = Job {
+        id: 7,
+        bounds: (300, 200),
+        origin: (128, 64),
+        window: (128, 64),
+        upper_left: Complex { re: -2.0, im: 1.0 },
+        lower_right: Complex { re: 1.0, im: -0.1 }
+    };
+    assert_eq!(Job::decode(&job.encode()).unwrap(), job);
+    assert!(Job::decode(&job.encode()[1..]).is_err());
+    assert!(Job::decode(&Job { window: (173, 64), ..job }.encode()).is_err());
+}
+
+/// Connect to the coordinator at `addr` and render tiles until it says
+/// there are no more, using `threads` threads for each tile. Return the
+/// number of tiles rendered.
+pub fn run_worker<A: ToSocketAddrs>(addr: A, threads: usize) -> io::Result<usize> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    write_frame(&mut stream, HELLO, HELLO_MAGIC)?;
+
+    let mut tiles = 0;
+    loop {
+        match read_frame(&mut stream)? {
+            (JOB, body) => {
+                let job = Job::decode(&body)?;
+                let mut result = job.id.to_be_bytes().to_vec();
+                result.extend_from_slice(&job.render(threads));
+                write_frame(&mut stream, RESULT, &result)?;
+                tiles += 1;
+            }
+            (DONE, _) => return Ok(tiles),
+            _ => return Err(protocol_error("unexpected message from coordinator"))
+        }
+    }
+}
+
+/// What the coordinator's connection threads share.
+struct State {
+    /// Tiles nobody is working on, by index.
+    pending: VecDeque<usize>,
+    /// Tiles not yet received.
+    remaining: usize,
+    pixels: Vec<u8>
+}
+
+/// A coordinator listening for workers.
+pub struct Coordinator {
+    listener: TcpListener,
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    tile_size: usize,
+    timeout: Duration
+}
+
+impl Coordinator {
+    /// Listen on `addr` for workers to help render the region of the plane
+    /// between `upper_left` and `lower_right` as an image of size `bounds`,
+    /// in tiles `tile_size` pixels square. A worker that takes longer than
+    /// `timeout` to return a tile is given up on. Tiles larger than
+    /// `MAX_TILE_SIZE` wouldn't fit in a frame, and are refused.
+    pub fn bind<A: ToSocketAddrs>(addr: A,
+                                  bounds: (usize, usize),
+                                  upper_left: Complex<f64>,
+                                  lower_right: Complex<f64>,
+                                  tile_size: usize,
+                                  timeout: Duration)
+        -> io::Result<Coordinator>
+    {
+        assert!(tile_size > 0);
+        if tile_size > MAX_TILE_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("tiles can be at most {} pixels square",
+                                              MAX_TILE_SIZE)));
+        }
+        Ok(Coordinator {
+            listener: TcpListener::bind(addr)?,
+            bounds, upper_left, lower_right, tile_size, timeout
+        })
+    }
+
+    /// Return the address the coordinator is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Return the jobs the image is divided into, in order of id.
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs = Vec::new();
+        for top in (0 .. self.bounds.1).step_by(self.tile_size) {
+            for left in (0 .. self.bounds.0).step_by(self.tile_size) {
+                jobs.push(Job {
+                    id: jobs.len() as u32,
+                    bounds: self.bounds,
+                    origin: (left, top),
+                    window: (self.tile_size.min(self.bounds.0 - left),
+                             self.tile_size.min(self.bounds.1 - top)),
+                    upper_left: self.upper_left,
+                    lower_right: self.lower_right
+                });
+            }
+        }
+        jobs
+    }
+
+    /// Hand out tiles to workers as they connect, and return the finished
+    /// image once every tile is back.
+    pub fn run(self) -> io::Result<Vec<u8>> {
+        let jobs = self.jobs();
+        let state = Mutex::new(State {
+            pending: (0 .. jobs.len()).collect(),
+            remaining: jobs.len(),
+            pixels: vec![0; self.bounds.0 * self.bounds.1]
+        });
+        let changed = Condvar::new();
+
+        self.listener.set_nonblocking(true)?;
+        thread::scope(|spawner| -> io::Result<()> {
+            while state.lock().unwrap().remaining > 0 {
+                match self.listener.accept() {
+                    Ok((stream, peer)) => {
+                        let (jobs, state, changed) = (&jobs, &state, &changed);
+                        let timeout = self.timeout;
+                        spawner.spawn(move || {
+                            if let Err(err) = serve_worker(stream, jobs, state, changed, timeout) {
+                                eprintln!("mandelbrot coordinator: worker {}: {}", peer, err);
+                            }
+                        });
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL);
+                    }
+                    Err(err) => return Err(err)
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(state.into_inner().unwrap().pixels)
+    }
+}
+
+/// Feed jobs to the worker at the other end of `stream` until there are none
+/// left. If the worker fails, put its tile back on the queue.
+fn serve_worker(mut stream: TcpStream,
+                jobs: &[Job],
+                state: &Mutex<State>,
+                changed: &Condvar,
+                timeout: Duration)
+    -> io::Result<()>
+{
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    match read_frame(&mut stream)? {
+        (HELLO, ref magic) if magic == HELLO_MAGIC => {}
+        _ => return Err(protocol_error("expected a worker's hello"))
+    }
+
+    loop {
+        // Wait for a tile to hand out. If none are pending but some are still
+        // out with other workers, one of those might yet come back to us.
+        let index = {
+            let mut state = state.lock().unwrap();
+            loop {
+                if let Some(index) = state.pending.pop_front() {
+                    break Some(index);
+                }
+                if state.remaining == 0 {
+                    break None;
+                }
+                state = changed.wait(state).unwrap();
+            }
+        };
+        let job = match index {
+            Some(index) => &jobs[index],
+            None => return write_frame(&mut stream, DONE, &[])
+        };
+
+        match exchange(&mut stream, job) {
+            Ok(pixels) => {
+                let mut state = state.lock().unwrap();
+                for (row, line) in pixels.chunks(job.window.0).enumerate() {
+                    let start = (job.origin.1 + row) * job.bounds.0 + job.origin.0;
+                    state.pixels[start .. start + job.window.0].copy_from_slice(line);
+                }
+                state.remaining -= 1;
+                changed.notify_all();
+            }
+            Err(err) => {
+                state.lock().unwrap().pending.push_front(job.id as usize);
+                changed.notify_all();
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Send `job` to the worker and wait for its pixels.
+fn exchange(stream: &mut TcpStream, job: &Job) -> io::Result<Vec<u8>> {
+    write_frame(stream, JOB, &job.encode())?;
+    match read_frame(stream)? {
+        (RESULT, body) => {
+            if body.len() != 4 + job.window.0 * job.window.1
+                || body[..4] != job.id.to_be_bytes()
+            {
+                return Err(protocol_error("result doesn't match job"));
+            }
+            Ok(body[4..].to_vec())
+        }
+        _ => Err(protocol_error("expected a result"))
+    }
+}
+
+#[test]
+fn test_coordinator_with_workers_on_localhost() {
+    let bounds = (150, 110);
+    let upper_left = Complex { re: -2.0, im: 1.1 };
+    let lower_right = Complex { re: 1.0, im: -1.1 };
+    let coordinator = Coordinator::bind("127.0.0.1:0", bounds, upper_left, lower_right,
+                                        32, Duration::from_secs(10)).unwrap();
+    let addr = coordinator.local_addr().unwrap();
+    assert_eq!(coordinator.jobs().len(), 20);
+    let err = Coordinator::bind("127.0.0.1:0", bounds, upper_left, lower_right,
+                                MAX_TILE_SIZE + 1, Duration::from_secs(10)).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let running = thread::spawn(move || coordinator.run());
+
+    // A worker that takes a job and dies without answering. Its tile must be
+    // handed to someone else.
+    {
+        let mut dead = TcpStream::connect(addr).unwrap();
+        write_frame(&mut dead, HELLO, HELLO_MAGIC).unwrap();
+        assert_eq!(read_frame(&mut dead).unwrap().0, JOB);
+    }
+
+    let workers: Vec<_> = (0 .. 3)
+        .map(|_| thread::spawn(move || run_worker(addr, 2).unwrap()))
+        .collect();
+    let tiles: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+    assert_eq!(tiles, 20);
+
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    ::render::render(&mut expected, bounds, upper_left, lower_right);
+    assert_eq!(running.join().unwrap().unwrap(), expected);
+}
+
+#[test]
+fn test_coordinator_retries_timed_out_worker() {
+    let bounds = (40, 30);
+    let upper_left = Complex { re: -2.0, im: 1.1 };
+    let lower_right = Complex { re: 1.0, im: -1.1 };
+    let coordinator = Coordinator::bind("127.0.0.1:0", bounds, upper_left, lower_right,
+                                        64, Duration::from_millis(200)).unwrap();
+    let addr = coordinator.local_addr().unwrap();
+    let running = thread::spawn(move || coordinator.run());
+
+    // This worker takes the only tile and then hangs, keeping its connection
+    // open, until the coordinator gives up on it.
+    let mut hung = TcpStream::connect(addr).unwrap();
+    write_frame(&mut hung, HELLO, HELLO_MAGIC).unwrap();
+    assert_eq!(read_frame(&mut hung).unwrap().0, JOB);
+
+    assert_eq!(run_worker(addr, 1).unwrap(), 1);
+
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    ::render::render(&mut expected, bounds, upper_left, lower_right);
+    assert_eq!(running.join().unwrap().unwrap(), expected);
+    drop(hung);
+}
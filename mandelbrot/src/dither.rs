@@ -0,0 +1,208 @@
+/* Dithering
+ * ---------
+ * Eight bits per sample isn't quite enough for a slow gradient: where the
+ * escape time creeps from one gray level to the next across many pixels, the
+ * image shows flat bands with visible steps between them. Dithering renders
+ * with sixteen bits instead, and then chooses each 8-bit sample so that the
+ * rounding errors even out over nearby pixels, trading the bands for fine
+ * noise. "--dither" and "image.dither" choose how:
+ *
+ *      none                round each sample to the nearest level; the default
+ *      floyd-steinberg     pass each pixel's rounding error on to the pixels
+ *                          right of and below it, which haven't been rounded
+ *                          yet, in the proportions 7, 3, 5 and 1 sixteenths
+ *      bayer               nudge each sample up or down by a threshold from
+ *                          an 8x8 pattern before rounding; grainier, but each
+ *                          pixel depends only on its own value and position
+ *
+ * The renderer produces the image a band of rows at a time, so a "Ditherer"
+ * carries the errors headed for the next row from one band to the next.
+ *
+ * Sixel output has the same problem a second time: it can only show 256
+ * colors, so it bands wherever the palette runs short. "sixel::encode"
+ * spreads the difference between each pixel and its palette color the same
+ * two ways; see sixel.rs.
+ */
+
+/// How to reduce 16-bit samples to 8 bits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dither {
+    None,
+    FloydSteinberg,
+    Bayer
+}
+
+impl Dither {
+    /// Return the name used for this method in job files.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Dither::None           => "none",
+            Dither::FloydSteinberg => "floyd-steinberg",
+            Dither::Bayer          => "bayer"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Dither> {
+        match s {
+            "none"            => Some(Dither::None),
+            "floyd-steinberg" => Some(Dither::FloydSteinberg),
+            "bayer"           => Some(Dither::Bayer),
+            _                 => None
+        }
+    }
+}
+
+/// The 8x8 Bayer matrix: each threshold from 0 to 63 appears once, spread so
+/// that any run of thresholds is as evenly scattered as it can be.
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21]
+];
+
+/// Return the Bayer threshold for the pixel at column `x` of row `y`, between
+/// minus and plus one half of a level.
+pub fn bayer_threshold(x: usize, y: usize) -> f64 {
+    (BAYER[y % 8][x % 8] as f64 + 0.5) / 64.0 - 0.5
+}
+
+/// Reduces an image's rows from 16-bit samples to 8-bit ones, top to bottom.
+pub struct Ditherer {
+    method: Dither,
+    /// The number of samples in a row: its width times its channels.
+    row_samples: usize,
+    channels: usize,
+    /// The row the next call to `rows` starts at.
+    row: usize,
+    /// For Floyd-Steinberg, the error passed down to each sample of the next
+    /// row, in 8-bit levels.
+    below: Vec<f64>
+}
+
+impl Ditherer {
+    /// Return a ditherer for an image `width` pixels wide, with `channels`
+    /// samples per pixel.
+    pub fn new(method: Dither, width: usize, channels: usize) -> Ditherer {
+        Ditherer {
+            method,
+            row_samples: width * channels,
+            channels,
+            row: 0,
+            below: vec![0.0; width * channels]
+        }
+    }
+
+    /// Reduce the next rows of the image, `wide`, holding whole rows of
+    /// big-endian 16-bit samples, to 8-bit samples in `narrow`.
+    pub fn rows(&mut self, wide: &[u8], narrow: &mut [u8]) {
+        assert_eq!(wide.len(), narrow.len() * 2);
+        let channels = self.channels;
+        let level = |i: usize| u16::from_be_bytes([wide[i * 2], wide[i * 2 + 1]]) as f64 / 257.0;
+        let round = |x: f64| x.round().clamp(0.0, 255.0);
+
+        for (y, out) in narrow.chunks_mut(self.row_samples.max(1)).enumerate() {
+            let start = y * self.row_samples;
+            match self.method {
+                Dither::None => {
+                    for (i, sample) in out.iter_mut().enumerate() {
+                        *sample = round(level(start + i)) as u8;
+                    }
+                }
+                Dither::Bayer => {
+                    for (i, sample) in out.iter_mut().enumerate() {
+                        let threshold = bayer_threshold(i / channels, self.row + y);
+                        *sample = round(level(start + i) + threshold) as u8;
+                    }
+                }
+                Dither::FloydSteinberg => {
+                    let mut error = std::mem::replace(&mut self.below, vec![0.0; out.len()]);
+                    for i in 0 .. out.len() {
+                        let wanted = level(start + i) + error[i];
+                        let chosen = round(wanted);
+                        out[i] = chosen as u8;
+                        let e = wanted - chosen;
+                        let x = i / channels;
+                        if i + channels < out.len() {
+                            error[i + channels] += e * 7.0 / 16.0;
+                            self.below[i + channels] += e / 16.0;
+                        }
+                        if x > 0 {
+                            self.below[i - channels] += e * 3.0 / 16.0;
+                        }
+                        self.below[i] += e * 5.0 / 16.0;
+                    }
+                }
+            }
+        }
+        self.row += narrow.len() / self.row_samples.max(1);
+    }
+}
+
+/// Return a 16-bit image `width` samples wide and `height` tall, of
+/// `channels` samples per pixel, whose 8-bit level is `level(x, y)`.
+#[cfg(test)]
+fn wide_image<F: Fn(usize, usize) -> f64>(width: usize, height: usize, level: F) -> Vec<u8> {
+    let mut wide = Vec::new();
+    for y in 0 .. height {
+        for x in 0 .. width {
+            wide.extend_from_slice(&((level(x, y) * 257.0).round() as u16).to_be_bytes());
+        }
+    }
+    wide
+}
+
+#[test]
+fn test_dither_parse() {
+    for method in [Dither::None, Dither::FloydSteinberg, Dither::Bayer] {
+        assert_eq!(Dither::parse(method.name()), Some(method));
+    }
+    assert_eq!(Dither::parse("ordered"), None);
+}
+
+#[test]
+fn test_dither_keeps_levels_on_average() {
+    // A gray a quarter of the way from level 100 to 101: rounding alone makes
+    // it all 100, but dithering mixes in enough 101s to match on average.
+    let (width, height) = (64, 64);
+    let wide = wide_image(width, height, |_, _| 100.25);
+    for method in [Dither::None, Dither::FloydSteinberg, Dither::Bayer] {
+        let mut narrow = vec![0; width * height];
+        Ditherer::new(method, width, 1).rows(&wide, &mut narrow);
+        assert!(narrow.iter().all(|&s| s == 100 || s == 101), "{:?}", method);
+        let mean = narrow.iter().map(|&s| s as f64).sum::<f64>() / narrow.len() as f64;
+        let expected = if method == Dither::None { 100.0 } else { 100.25 };
+        assert!((mean - expected).abs() < 0.01, "{:?}: {}", method, mean);
+    }
+
+    // Levels that 8 bits can hold exactly come through untouched.
+    let wide = wide_image(16, 8, |x, _| (x * 17) as f64);
+    for method in [Dither::FloydSteinberg, Dither::Bayer] {
+        let mut narrow = vec![0; 16 * 8];
+        Ditherer::new(method, 16, 1).rows(&wide, &mut narrow);
+        assert!(narrow.iter().enumerate().all(|(i, &s)| s as usize == i % 16 * 17));
+    }
+}
+
+#[test]
+fn test_dither_in_bands() {
+    // Dithering an RGB image a few rows at a time matches doing it at once.
+    let (width, height) = (13, 11);
+    let wide = wide_image(width * 3, height, |x, y| (x * 5 + y * 3) as f64 * 0.37);
+    for method in [Dither::FloydSteinberg, Dither::Bayer] {
+        let mut whole = vec![0; width * 3 * height];
+        Ditherer::new(method, width, 3).rows(&wide, &mut whole);
+
+        let mut banded = vec![0; whole.len()];
+        let mut ditherer = Ditherer::new(method, width, 3);
+        let rows = width * 3 * 4;
+        for (narrow, wide) in banded.chunks_mut(rows).zip(wide.chunks(rows * 2)) {
+            ditherer.rows(wide, narrow);
+        }
+        assert_eq!(banded, whole, "{:?}", method);
+    }
+}
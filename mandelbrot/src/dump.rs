@@ -0,0 +1,334 @@
+/* Iteration Dumps
+ * ---------------
+ * Trying a new palette shouldn't mean computing every orbit again. A dump
+ * saves the result of "sample" for every pixel, so "mandelbrot recolor" can
+ * color it any number of ways in a fraction of the time.
+ *
+ * A dump describes itself in a few lines of text, followed by a blank line
+ * and the samples:
+ *
+ *      mandelbrot iterations 1
+ *      bounds 1000x750
+ *      upper_left -1.2,0.35
+ *      lower_right -1,0.2
+ *      limit 1000
+ *      fields count smooth z distance
+ *
+ * The samples are stored row by row, left to right. Each one holds the listed
+ * fields in order, all little-endian: "count" is a u32, with 0xffffffff for a
+ * point that never escaped; "smooth" and "distance" are f64s; "z" is two f64s,
+ * the real part first. Only "count" is required.
+ */
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::thread;
+
+use num::Complex;
+use orbit::{sample, Measures, Sample};
+use palette::{position, Palette};
+use parsing::{parse_complex, parse_pair};
+use render::pixel_to_point;
+
+const MAGIC: &str = "mandelbrot iterations 1";
+
+/// The value of "count" for a point that never escaped.
+pub const NEVER: u32 = u32::MAX;
+
+/// Which optional fields a dump stores besides the escape count.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fields {
+    pub smooth: bool,
+    pub z: bool,
+    pub distance: bool
+}
+
+impl Fields {
+    /// Every field.
+    pub const ALL: Fields = Fields { smooth: true, z: true, distance: true };
+
+    /// Parse a comma-separated list of field names. "count" is always
+    /// stored, so it may be listed or not.
+    pub fn parse(s: &str) -> Option<Fields> {
+        let mut fields = Fields { smooth: false, z: false, distance: false };
+        for name in s.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            match name {
+                "count"    => {}
+                "smooth"   => fields.smooth = true,
+                "z"        => fields.z = true,
+                "distance" => fields.distance = true,
+                _          => return None
+            }
+        }
+        Some(fields)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        let mut names = vec!["count"];
+        if self.smooth { names.push("smooth"); }
+        if self.z { names.push("z"); }
+        if self.distance { names.push("distance"); }
+        names
+    }
+
+    /// Return the number of bytes each sample occupies.
+    pub fn record_size(&self) -> usize {
+        4 + 8 * (self.smooth as usize + 2 * self.z as usize + self.distance as usize)
+    }
+}
+
+/// The samples for every pixel of an image, and how they were made.
+#[derive(Debug, PartialEq)]
+pub struct Dump {
+    pub bounds: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub limit: u32,
+    pub fields: Fields,
+    /// The samples, row by row. Fields not in `fields` are zero.
+    pub samples: Vec<Sample>
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Dump {
+    /// Sample every pixel of an image of size `bounds` covering the region
+    /// between `upper_left` and `lower_right`, iterating at most `limit`
+    /// times, using `threads` threads.
+    pub fn render(bounds: (usize, usize),
+                  upper_left: Complex<f64>,
+                  lower_right: Complex<f64>,
+                  limit: u32,
+                  fields: Fields,
+                  threads: usize)
+        -> Dump
+    {
+        let blank = Sample {
+            count: None, smooth: 0.0, z: Complex { re: 0.0, im: 0.0 }, distance: 0.0,
+            normal: Complex { re: 0.0, im: 0.0 }, trap: f64::INFINITY, stripe: 0.0,
+            triangle: 0.0
+        };
+        let mut samples = vec![blank; bounds.0 * bounds.1];
+
+        if !samples.is_empty() {
+            let rows_per_band = bounds.1 / threads.max(1) + 1;
+            thread::scope(|spawner| {
+                for (i, band) in samples.chunks_mut(rows_per_band * bounds.0).enumerate() {
+                    spawner.spawn(move || {
+                        for (j, s) in band.iter_mut().enumerate() {
+                            let pixel = (j % bounds.0, i * rows_per_band + j / bounds.0);
+                            let point = pixel_to_point(bounds, pixel, upper_left, lower_right);
+                            *s = keep(sample(point, limit, &Measures::default()), fields);
+                        }
+                    });
+                }
+            });
+        }
+
+        Dump { bounds, upper_left, lower_right, limit, fields, samples }
+    }
+
+    /// Write this dump to `out`.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "{}\nbounds {}x{}\nupper_left {:?},{:?}\nlower_right {:?},{:?}\n\
+                     limit {}\nfields {}\n\n",
+               MAGIC, self.bounds.0, self.bounds.1,
+               self.upper_left.re, self.upper_left.im,
+               self.lower_right.re, self.lower_right.im,
+               self.limit, self.fields.names().join(" "))?;
+
+        let mut record = Vec::with_capacity(self.fields.record_size());
+        for s in &self.samples {
+            record.clear();
+            record.extend_from_slice(&s.count.unwrap_or(NEVER).to_le_bytes());
+            if self.fields.smooth {
+                record.extend_from_slice(&s.smooth.to_le_bytes());
+            }
+            if self.fields.z {
+                record.extend_from_slice(&s.z.re.to_le_bytes());
+                record.extend_from_slice(&s.z.im.to_le_bytes());
+            }
+            if self.fields.distance {
+                record.extend_from_slice(&s.distance.to_le_bytes());
+            }
+            out.write_all(&record)?;
+        }
+        out.flush()
+    }
+
+    /// Read a dump from `input`.
+    pub fn read<R: BufRead>(input: &mut R) -> io::Result<Dump> {
+        let mut header = Vec::new();
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Err(invalid("dump header is truncated".to_string()));
+            }
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+            header.push(line);
+        }
+        if header.first().map(|s| s.as_str()) != Some(MAGIC) {
+            return Err(invalid("not an iteration dump".to_string()));
+        }
+        let field = |name: &str| {
+            header.iter()
+                .find_map(|f| f.strip_prefix(name).and_then(|v| v.strip_prefix(' ')))
+                .ok_or_else(|| invalid(format!("dump has no {} line", name)))
+        };
+        let bad = |name: &str| invalid(format!("bad {} line in dump", name));
+
+        let bounds = parse_pair(field("bounds")?, 'x').ok_or_else(|| bad("bounds"))?;
+        let upper_left = parse_complex(field("upper_left")?).ok_or_else(|| bad("upper_left"))?;
+        let lower_right = parse_complex(field("lower_right")?).ok_or_else(|| bad("lower_right"))?;
+        let limit = field("limit")?.parse().map_err(|_| bad("limit"))?;
+        let names = field("fields")?;
+        if names.split(' ').next() != Some("count") {
+            return Err(bad("fields"));
+        }
+        let fields = Fields::parse(&names.replace(' ', ",")).ok_or_else(|| bad("fields"))?;
+        if fields.names().join(" ") != names {
+            return Err(bad("fields"));
+        }
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let (width, height): (usize, usize) = bounds;
+        if Some(data.len()) != width.checked_mul(height)
+            .and_then(|n| n.checked_mul(fields.record_size()))
+        {
+            return Err(invalid(format!("dump should hold {}x{} samples", width, height)));
+        }
+
+        let samples = data.chunks(fields.record_size()).map(|record| {
+            let mut rest = record;
+            let mut take = |n: usize| {
+                let (head, tail) = rest.split_at(n);
+                rest = tail;
+                head
+            };
+            let count = u32::from_le_bytes(take(4).try_into().unwrap());
+            let mut float = || f64::from_le_bytes(take(8).try_into().unwrap());
+            let smooth = if fields.smooth { float() } else { 0.0 };
+            let z = if fields.z {
+                let re = float();
+                Complex { re, im: float() }
+            } else {
+                Complex { re: 0.0, im: 0.0 }
+            };
+            let distance = if fields.distance { float() } else { 0.0 };
+            Sample {
+                count: if count == NEVER { None } else { Some(count) },
+                smooth, z, distance,
+                normal: Complex { re: 0.0, im: 0.0 }, trap: f64::INFINITY, stripe: 0.0,
+                triangle: 0.0
+            }
+        }).collect();
+
+        Ok(Dump { bounds, upper_left, lower_right, limit, fields, samples })
+    }
+
+    /// Save this dump to the file at `path`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.write(&mut io::BufWriter::new(fs::File::create(path)?))
+    }
+
+    /// Load the dump saved at `path`.
+    pub fn load(path: &Path) -> io::Result<Dump> {
+        Dump::read(&mut io::BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Color every pixel with `palette`, returning red, green and blue bytes
+    /// for each. Points in the set are `inside`.
+    ///
+    /// Escaped points are placed along the palette by their smooth escape
+    /// time if the dump has it, or their count if not; see
+    /// `palette::position` for the meaning of `cycle`.
+    pub fn recolor(&self, palette: &Palette, cycle: Option<f64>, inside: [u8; 3]) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.samples.len() * 3);
+        for s in &self.samples {
+            let color = match s.count {
+                None => inside,
+                Some(count) => {
+                    let time = if self.fields.smooth { s.smooth } else { count as f64 };
+                    palette.color(position(time, self.limit, cycle))
+                }
+            };
+            rgb.extend_from_slice(&color);
+        }
+        rgb
+    }
+}
+
+/// Clear the fields of `s` that `fields` doesn't keep, and those dumps never
+/// keep, so that a dump that has been written and read back compares equal
+/// to the original.
+fn keep(mut s: Sample, fields: Fields) -> Sample {
+    if !fields.smooth { s.smooth = 0.0; }
+    if !fields.z { s.z = Complex { re: 0.0, im: 0.0 }; }
+    if !fields.distance { s.distance = 0.0; }
+    s.normal = Complex { re: 0.0, im: 0.0 };
+    s
+}
+
+#[test]
+fn test_fields_parse() {
+    assert_eq!(Fields::parse("count,smooth,z,distance"), Some(Fields::ALL));
+    assert_eq!(Fields::parse("distance"),
+               Some(Fields { smooth: false, z: false, distance: true }));
+    assert_eq!(Fields::parse(""), Some(Fields { smooth: false, z: false, distance: false }));
+    assert_eq!(Fields::parse("smooth,colour"), None);
+}
+
+#[test]
+fn test_dump_round_trip() {
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    for &fields in &[Fields::ALL, Fields::parse("count").unwrap(), Fields::parse("z").unwrap()] {
+        let dump = Dump::render((31, 17), upper_left, lower_right, 200, fields, 3);
+
+        let mut file = Vec::new();
+        dump.write(&mut file).unwrap();
+        assert_eq!(Dump::read(&mut &file[..]).unwrap(), dump);
+
+        // Losing the last byte is noticed.
+        file.pop();
+        assert!(Dump::read(&mut &file[..]).is_err());
+    }
+}
+
+#[test]
+fn test_dump_counts_match_render() {
+    let bounds = (40, 30);
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    let dump = Dump::render(bounds, upper_left, lower_right, 255, Fields::ALL, 2);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    ::render::render(&mut pixels, bounds, upper_left, lower_right);
+    for (s, &p) in dump.samples.iter().zip(&pixels) {
+        assert_eq!(p, match s.count { None => 0, Some(count) => 255 - count as u8 });
+    }
+}
+
+#[test]
+fn test_recolor() {
+    let dump = Dump::render((8, 8), Complex { re: -2.0, im: 1.0 },
+                            Complex { re: 1.0, im: -1.0 }, 50, Fields::ALL, 1);
+    let palette = Palette::parse("ff0000,0000ff").unwrap();
+    let rgb = dump.recolor(&palette, None, [0, 255, 0]);
+    assert_eq!(rgb.len(), 8 * 8 * 3);
+
+    for (s, color) in dump.samples.iter().zip(rgb.chunks(3)) {
+        match s.count {
+            None => assert_eq!(color, [0, 255, 0]),
+            Some(_) => assert_eq!(color[1], 0)
+        }
+    }
+}
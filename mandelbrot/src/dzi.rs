@@ -0,0 +1,224 @@
+/* Deep Zoom Images
+ * ----------------
+ * A Deep Zoom Image (DZI) is a pyramid of tiles that zoomable viewers such as
+ * OpenSeadragon know how to display. It consists of an XML descriptor,
+ *
+ *      <Image TileSize="254" Overlap="1" Format="png" xmlns="...">
+ *          <Size Width="100000" Height="75000"/>
+ *      </Image>
+ *
+ * next to a directory holding one subdirectory per level. The highest level,
+ * "max", is the image at full size; each level below it is half the width and
+ * height of the one above (rounding up), down to level 0, which is a single
+ * pixel. Each level is cut into tiles named "{column}_{row}.png", and each
+ * tile also includes "Overlap" pixels of its neighbours on every side that
+ * has one.
+ *
+ * The usual way to build a pyramid is to make the full image and shrink it
+ * repeatedly, which would mean holding gigapixels in memory. We can do
+ * better: every level shows the same region of the complex plane, just with
+ * fewer pixels, so we render each tile of each level directly with
+ * "render_window". Only one tile per thread is ever in memory.
+ */
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use num::Complex;
+use png::write_png;
+use render::render_window;
+
+/// The shape of a Deep Zoom pyramid.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pyramid {
+    /// The width and height of the full-size image.
+    pub bounds: (usize, usize),
+    /// The width and height of a tile, not counting overlap.
+    pub tile_size: usize,
+    /// How many pixels of each neighbouring tile a tile includes.
+    pub overlap: usize
+}
+
+impl Pyramid {
+    /// Return the number of the highest, full-size level.
+    pub fn max_level(&self) -> u32 {
+        let longest = self.bounds.0.max(self.bounds.1).max(1);
+        usize::BITS - (longest - 1).leading_zeros()
+    }
+
+    /// Return the width and height of the image at `level`.
+    pub fn level_bounds(&self, level: u32) -> (usize, usize) {
+        let shift = self.max_level() - level;
+        (self.bounds.0.div_ceil(1 << shift).max(1),
+         self.bounds.1.div_ceil(1 << shift).max(1))
+    }
+
+    /// Return the number of columns and rows of tiles at `level`.
+    pub fn tile_counts(&self, level: u32) -> (usize, usize) {
+        let bounds = self.level_bounds(level);
+        (bounds.0.div_ceil(self.tile_size), bounds.1.div_ceil(self.tile_size))
+    }
+
+    /// Return the upper-left pixel and the width and height of the tile at
+    /// `column` and `row` of `level`, including its overlap.
+    pub fn tile_window(&self, level: u32, column: usize, row: usize)
+        -> ((usize, usize), (usize, usize))
+    {
+        let bounds = self.level_bounds(level);
+        let span = |index: usize, length: usize| {
+            let start = (index * self.tile_size).saturating_sub(self.overlap);
+            let end = ((index + 1) * self.tile_size + self.overlap).min(length);
+            (start, end - start)
+        };
+        let (left, width) = span(column, bounds.0);
+        let (top, height) = span(row, bounds.1);
+        ((left, top), (width, height))
+    }
+
+    /// Return the XML descriptor for this pyramid.
+    pub fn descriptor(&self) -> String {
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\"\n\
+                 \x20      TileSize=\"{}\" Overlap=\"{}\" Format=\"png\">\n\
+                 \x20   <Size Width=\"{}\" Height=\"{}\"/>\n\
+                 </Image>\n",
+                self.tile_size, self.overlap, self.bounds.0, self.bounds.1)
+    }
+}
+
+#[test]
+fn test_pyramid_levels() {
+    let pyramid = Pyramid { bounds: (1000, 600), tile_size: 254, overlap: 1 };
+    assert_eq!(pyramid.max_level(), 10);
+    assert_eq!(pyramid.level_bounds(10), (1000, 600));
+    assert_eq!(pyramid.level_bounds(9), (500, 300));
+    assert_eq!(pyramid.level_bounds(8), (250, 150));
+    assert_eq!(pyramid.level_bounds(1), (2, 2));
+    assert_eq!(pyramid.level_bounds(0), (1, 1));
+    assert_eq!(pyramid.tile_counts(10), (4, 3));
+    assert_eq!(pyramid.tile_counts(0), (1, 1));
+
+    let square = Pyramid { bounds: (256, 256), tile_size: 256, overlap: 0 };
+    assert_eq!(square.max_level(), 8);
+    assert_eq!(square.tile_counts(8), (1, 1));
+}
+
+#[test]
+fn test_pyramid_tile_window() {
+    let pyramid = Pyramid { bounds: (1000, 600), tile_size: 254, overlap: 1 };
+    assert_eq!(pyramid.tile_window(10, 0, 0), ((0, 0), (255, 255)));
+    assert_eq!(pyramid.tile_window(10, 1, 1), ((253, 253), (256, 256)));
+    assert_eq!(pyramid.tile_window(10, 3, 2), ((761, 507), (239, 93)));
+    assert_eq!(pyramid.tile_window(0, 0, 0), ((0, 0), (1, 1)));
+}
+
+/// Return the path of the descriptor and of the tile directory for a pyramid
+/// named `base`: `base.dzi` and `base_files`.
+pub fn paths(base: &Path) -> (PathBuf, PathBuf) {
+    let name = base.file_name().map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    (base.with_file_name(format!("{}.dzi", name)),
+     base.with_file_name(format!("{}_files", name)))
+}
+
+/// Render the region of the complex plane between `upper_left` and
+/// `lower_right` as the Deep Zoom pyramid described by `pyramid`, writing
+/// `base.dzi` and the tiles under `base_files/`, using `threads` threads.
+pub fn export(base: &Path,
+              pyramid: &Pyramid,
+              upper_left: Complex<f64>,
+              lower_right: Complex<f64>,
+              threads: usize)
+    -> io::Result<()>
+{
+    assert!(pyramid.tile_size > 0);
+    let (descriptor, files) = paths(base);
+
+    for level in 0 ..= pyramid.max_level() {
+        let level_dir = files.join(level.to_string());
+        fs::create_dir_all(&level_dir)?;
+
+        let bounds = pyramid.level_bounds(level);
+        let (columns, rows) = pyramid.tile_counts(level);
+        let next = AtomicUsize::new(0);
+        let error = Mutex::new(None);
+
+        // Threads take tiles one at a time until they run out, or until
+        // one of them fails to write its tile.
+        std::thread::scope(|spawner| {
+            for _ in 0 .. threads.max(1) {
+                spawner.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= columns * rows || error.lock().unwrap().is_some() {
+                        break;
+                    }
+                    let (column, row) = (index % columns, index / columns);
+                    let (origin, window) = pyramid.tile_window(level, column, row);
+
+                    let mut pixels = vec![0; window.0 * window.1];
+                    render_window(&mut pixels, bounds, origin, window,
+                                  upper_left, lower_right);
+
+                    let path = level_dir.join(format!("{}_{}.png", column, row));
+                    if let Err(err) = write_tile(&path, &pixels, window) {
+                        *error.lock().unwrap() = Some(err);
+                    }
+                });
+            }
+        });
+
+        if let Some(err) = error.into_inner().unwrap() {
+            return Err(err);
+        }
+    }
+
+    // Write the descriptor last, so that a viewer never finds one pointing
+    // at missing tiles.
+    fs::write(descriptor, pyramid.descriptor())
+}
+
+fn write_tile(path: &Path, pixels: &[u8], bounds: (usize, usize)) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_png(&mut out, pixels, bounds)?;
+    out.flush()
+}
+
+#[cfg(test)]
+fn png_size(path: &Path) -> (usize, usize) {
+    let file = fs::read(path).unwrap();
+    let word = |i: usize| u32::from_be_bytes([file[i], file[i + 1], file[i + 2], file[i + 3]]);
+    (word(16) as usize, word(20) as usize)
+}
+
+#[test]
+fn test_export() {
+    let dir = std::env::temp_dir()
+        .join(format!("mandelbrot-dzi-{}", std::process::id()));
+    let base = dir.join("set");
+    let pyramid = Pyramid { bounds: (300, 200), tile_size: 128, overlap: 1 };
+
+    export(&base, &pyramid,
+           Complex { re: -2.0, im: 1.0 }, Complex { re: 1.0, im: -1.0 }, 3).unwrap();
+
+    let descriptor = fs::read_to_string(dir.join("set.dzi")).unwrap();
+    assert!(descriptor.contains("TileSize=\"128\" Overlap=\"1\" Format=\"png\""));
+    assert!(descriptor.contains("<Size Width=\"300\" Height=\"200\"/>"));
+
+    for level in 0 ..= pyramid.max_level() {
+        let (columns, rows) = pyramid.tile_counts(level);
+        for row in 0 .. rows {
+            for column in 0 .. columns {
+                let path = dir.join(format!("set_files/{}/{}_{}.png", level, column, row));
+                assert_eq!(png_size(&path), pyramid.tile_window(level, column, row).1);
+            }
+        }
+        let extra = dir.join(format!("set_files/{}/{}_0.png", level, columns));
+        assert!(!extra.exists());
+    }
+    assert_eq!(png_size(&dir.join("set_files/9/2_1.png")), (45, 73));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
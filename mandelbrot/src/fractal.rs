@@ -0,0 +1,66 @@
+/* Fractals
+ * --------
+ * Besides the Mandelbrot set, the same iteration draws Julia sets: fix "c",
+ * and ask for each starting point "z" whether its orbit escapes. Every point
+ * of the Mandelbrot set has a connected Julia set, and every point outside it
+ * a dust of disconnected pieces, so the two make good companions.
+ */
+
+use fixed::{convert, escape_from, escape_time, Fixed, Precision};
+use num::Complex;
+use orbit::{julia_sample, sample, Measures, Sample};
+
+/// Which set to plot.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Fractal {
+    Mandelbrot,
+    /// The Julia set for the given value of `c`.
+    Julia(Complex<f64>)
+}
+
+impl Fractal {
+    /// Return the name used for this kind of fractal in job files.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Fractal::Mandelbrot => "mandelbrot",
+            Fractal::Julia(_)   => "julia"
+        }
+    }
+
+    /// Iterate at most `limit` times for the pixel showing `point`, with
+    /// `precision`, measuring what `measures` asks for along the way. Fixed
+    /// precision measures only the escape count; see fixed.rs.
+    pub fn sample(&self, point: Complex<f64>, limit: u32, measures: &Measures,
+                  precision: Precision)
+        -> Sample
+    {
+        match (*self, precision) {
+            (Fractal::Mandelbrot, Precision::Double) => sample(point, limit, measures),
+            (Fractal::Julia(c), Precision::Double)   => julia_sample(point, c, limit, measures),
+            (Fractal::Mandelbrot, Precision::Fixed)  => {
+                Sample::from_count(escape_time(convert::<Fixed>(point), limit), limit)
+            }
+            (Fractal::Julia(c), Precision::Fixed)    => {
+                Sample::from_count(escape_from(convert::<Fixed>(point), convert(c), limit), limit)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fractal_sample() {
+    let point = Complex { re: -0.75, im: 0.1 };
+    let none = Measures::default();
+    let double = Precision::Double;
+    assert_eq!(Fractal::Mandelbrot.sample(point, 100, &none, double), sample(point, 100, &none));
+    let c = Complex { re: 0.285, im: 0.01 };
+    assert_eq!(Fractal::Julia(c).sample(point, 100, &none, double),
+               julia_sample(point, c, 100, &none));
+
+    // Fixed precision escapes at the same iteration, away from the boundary.
+    for fractal in [Fractal::Mandelbrot, Fractal::Julia(c)] {
+        let fixed = fractal.sample(point, 100, &none, Precision::Fixed);
+        assert_eq!(fixed.count, fractal.sample(point, 100, &none, double).count);
+        assert_eq!(fixed.smooth, fixed.count.map_or(100.0, |count| count as f64));
+    }
+}
@@ -0,0 +1,89 @@
+/* Image Files
+ * -----------
+ * We can save images in several formats, each suited to different uses:
+ *
+ *      png     compressed, and carries the job that made it; the default
+ *      pnm     PGM or PPM, trivial to read from quick tools
+ *      bmp     for older viewers; 8-bit samples only
+ *      tiff    uncompressed, 8 or 16 bits per sample, for print work
+ *
+ * Each format's writer implements "ImageWriter", taking the image a few rows
+ * at a time, so the renderer can hand rows over as they're done whatever the
+ * format. The format comes from "--format" or "image.format" if given, and
+ * from the output file's extension otherwise; see "Config::format".
+ */
+
+use std::io::{self, Seek, Write};
+
+use bmp::BmpWriter;
+use config::Format;
+use png::PngWriter;
+use pnm::PnmWriter;
+use tiff::TiffWriter;
+
+/// Something that writes an image's rows, top to bottom, to a file.
+pub trait ImageWriter {
+    /// Write the next rows of the image, which `rows` holds whole. Each
+    /// sample is one byte, or two big-endian bytes for 16-bit images.
+    fn write_rows(&mut self, rows: &[u8]) -> io::Result<()>;
+
+    /// Finish the file, once every row has been written.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W: Write> ImageWriter for PngWriter<W> {
+    fn write_rows(&mut self, rows: &[u8]) -> io::Result<()> {
+        PngWriter::write_rows(self, rows)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        PngWriter::finish(*self)?.flush()
+    }
+}
+
+/// Start writing an image of size `bounds` to `out` in format `format`, with
+/// `channels` samples per pixel, one for grayscale or three for RGB, of
+/// `depth` bits each, 8 or 16. Formats that can carry text get the
+/// `(keyword, text)` pairs in `text`.
+pub fn create<'a, W: Write + Seek + 'a>(out: W,
+                                        format: Format,
+                                        bounds: (usize, usize),
+                                        channels: usize,
+                                        depth: u8,
+                                        text: &[(&str, &str)])
+    -> io::Result<Box<dyn ImageWriter + 'a>>
+{
+    Ok(match format {
+        Format::Png  => Box::new(PngWriter::new(out, bounds, channels, depth, text)?),
+        Format::Pnm  => Box::new(PnmWriter::new(out, bounds, channels, depth)?),
+        Format::Bmp  => Box::new(BmpWriter::new(out, bounds, channels, depth)?),
+        Format::Tiff => Box::new(TiffWriter::new(out, bounds, channels, depth)?)
+    })
+}
+
+#[test]
+fn test_every_format_round_trips() {
+    use std::io::Cursor;
+    use {bmp, png, pnm, tiff};
+
+    let bounds = (7, 5);
+    let rgb: Vec<u8> = (0 .. bounds.0 * bounds.1 * 3).map(|i| (i * 29 % 256) as u8).collect();
+    for &format in &[Format::Png, Format::Pnm, Format::Bmp, Format::Tiff] {
+        let mut file = Cursor::new(Vec::new());
+        let mut writer = create(&mut file, format, bounds, 3, 8, &[]).unwrap();
+        for band in rgb.chunks(bounds.0 * 3 * 2) {
+            writer.write_rows(band).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let file = file.into_inner();
+        let image = match format {
+            Format::Png  => png::read(&file),
+            Format::Pnm  => pnm::read(&file),
+            Format::Bmp  => bmp::read(&file),
+            Format::Tiff => tiff::read(&file)
+        }.unwrap();
+        assert_eq!(image, png::Image { bounds, channels: 3, depth: 8, pixels: rgb.clone() },
+                   "{:?}", format);
+    }
+}
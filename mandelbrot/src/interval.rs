@@ -0,0 +1,437 @@
+/* Rigorous Membership
+ * -------------------
+ * "escape_time" returning "None" only means the orbit hadn't escaped yet
+ * when we stopped looking, and rounding error means even "Some" is an
+ * opinion. For plots where correctness matters, "classify" answers for a
+ * whole pixel at once, and only says what it can prove:
+ *
+ *      exterior    every point of the pixel escapes
+ *      interior    every point of the pixel is in the set
+ *      unknown     neither could be shown within the iteration limit
+ *
+ * It iterates on intervals: each number is a range "[lo, hi]" known to
+ * contain the true value, and every operation rounds its result's bounds
+ * outward by a unit in the last place, so the ranges stay honest however
+ * the floating point rounds. Starting from the pixel's rectangle of "c"s, the
+ * rectangle of "z"s then holds every orbit of every point in the pixel.
+ *
+ * Exterior is the easy half: once every "z" in the rectangle lies outside
+ * the circle of radius two, every orbit escapes.
+ *
+ * Interior takes a cycle. Points in the set's interior mostly have orbits
+ * drawn into an attracting cycle of some period "p", which we spot with
+ * ordinary floating point: Brent's method keeps a reference point, moved
+ * every power of two iterations, and watches for the pixel's center's orbit
+ * to come back to it. Then, with intervals, we draw a small box "B" about
+ * the cycle's point nearest zero, and apply the iteration to it "p" times,
+ * for every "c" in the pixel at once. If the result lies strictly inside
+ * "B", then for each "c", the "p"th iterate maps "B" into a smaller part of
+ * itself, and a complex-differentiable function that does that has a fixed
+ * point there attracting all of "B" (the Earle-Hamilton theorem, a cousin of
+ * the Schwarz lemma): the cycle attracts. And an attracting cycle always
+ * attracts the orbit of zero, the one critical point of z^2 + c, which is
+ * then bounded: every point of the pixel is in the set. The box starts as
+ * wide as the pixel, and grows to fit its image, since the cycle moves as
+ * "c" does; going around the cycle two, four or eight times, which pulls
+ * the box in harder, sometimes succeeds where once doesn't.
+ *
+ * A wide rectangle of "c"s spreads into a wider and wider rectangle of "z"s,
+ * faster than its orbits escape, so a pixel that can't be settled whole is
+ * cut into quarters, and those into quarters, a few times over. It's
+ * exterior or interior if all its pieces are.
+ */
+
+use num::Complex;
+
+/// A closed range of real numbers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64
+}
+
+impl Interval {
+    pub fn new(lo: f64, hi: f64) -> Interval {
+        Interval { lo, hi }
+    }
+
+    /// Return the interval holding only `x`.
+    pub fn point(x: f64) -> Interval {
+        Interval { lo: x, hi: x }
+    }
+
+    /// Return an interval holding both `lo` and `hi` as computed, rounded
+    /// to the nearest, along with the exact values they stand for.
+    fn outward(lo: f64, hi: f64) -> Interval {
+        Interval { lo: lo.next_down(), hi: hi.next_up() }
+    }
+
+    pub fn add(self, other: Interval) -> Interval {
+        Interval::outward(self.lo + other.lo, self.hi + other.hi)
+    }
+
+    pub fn sub(self, other: Interval) -> Interval {
+        Interval::outward(self.lo - other.hi, self.hi - other.lo)
+    }
+
+    pub fn mul(self, other: Interval) -> Interval {
+        let products = [self.lo * other.lo, self.lo * other.hi,
+                        self.hi * other.lo, self.hi * other.hi];
+        let lo = products.iter().cloned().fold(f64::INFINITY, f64::min);
+        let hi = products.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        Interval::outward(lo, hi)
+    }
+
+    /// Return the interval of squares of this interval's members, which is
+    /// narrower than `self.mul(self)` when it straddles zero.
+    pub fn square(self) -> Interval {
+        let (a, b) = (self.lo * self.lo, self.hi * self.hi);
+        if self.lo <= 0.0 && self.hi >= 0.0 {
+            Interval { lo: 0.0, hi: a.max(b).next_up() }
+        } else {
+            Interval { lo: a.min(b).next_down().max(0.0), hi: a.max(b).next_up() }
+        }
+    }
+
+    /// Return twice this interval, which needs no rounding.
+    fn double(self) -> Interval {
+        Interval { lo: self.lo * 2.0, hi: self.hi * 2.0 }
+    }
+
+    pub fn width(&self) -> f64 {
+        self.hi - self.lo
+    }
+
+    /// Return the interval of numbers in both this one and `other`, which
+    /// must overlap.
+    fn intersect(&self, other: &Interval) -> Interval {
+        Interval { lo: self.lo.max(other.lo), hi: self.hi.min(other.hi) }
+    }
+
+    /// Return true if this interval lies strictly inside `other`.
+    pub fn inside(&self, other: &Interval) -> bool {
+        other.lo < self.lo && self.hi < other.hi
+    }
+}
+
+/// A rectangle of complex numbers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub re: Interval,
+    pub im: Interval
+}
+
+impl Rect {
+    /// Return the rectangle with corners `a` and `b`, in either order.
+    pub fn new(a: Complex<f64>, b: Complex<f64>) -> Rect {
+        Rect {
+            re: Interval::new(a.re.min(b.re), a.re.max(b.re)),
+            im: Interval::new(a.im.min(b.im), a.im.max(b.im))
+        }
+    }
+
+    /// Return the rectangle holding only `z`.
+    pub fn point(z: Complex<f64>) -> Rect {
+        Rect { re: Interval::point(z.re), im: Interval::point(z.im) }
+    }
+
+    fn add(&self, other: &Rect) -> Rect {
+        Rect { re: self.re.add(other.re), im: self.im.add(other.im) }
+    }
+
+    /// Return a rectangle holding the product of every pair of points from
+    /// this one and `other`.
+    fn mul(&self, other: &Rect) -> Rect {
+        Rect {
+            re: self.re.mul(other.re).sub(self.im.mul(other.im)),
+            im: self.re.mul(other.im).add(self.im.mul(other.re))
+        }
+    }
+
+    /// Return a rectangle holding `z * z + c` for every `z` in this one and
+    /// `c` in `c`.
+    pub fn square_add(&self, c: &Rect) -> Rect {
+        Rect {
+            re: self.re.square().sub(self.im.square()).add(c.re),
+            im: self.re.mul(self.im).double().add(c.im)
+        }
+    }
+
+    /// Return the least and greatest squared distances from the origin of
+    /// the points in this rectangle, or bounds on them.
+    fn norm_sqr(&self) -> Interval {
+        self.re.square().add(self.im.square())
+    }
+
+    fn center(&self) -> Complex<f64> {
+        Complex { re: (self.re.lo + self.re.hi) / 2.0, im: (self.im.lo + self.im.hi) / 2.0 }
+    }
+
+    fn intersect(&self, other: &Rect) -> Rect {
+        Rect { re: self.re.intersect(&other.re), im: self.im.intersect(&other.im) }
+    }
+
+    /// Return true if this rectangle lies strictly inside `other`.
+    fn inside(&self, other: &Rect) -> bool {
+        self.re.inside(&other.re) && self.im.inside(&other.im)
+    }
+}
+
+/// What we could prove about a pixel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Class {
+    Exterior,
+    Interior,
+    Unknown
+}
+
+/// How close, in floating point, an orbit must come back to its reference
+/// point to suggest a cycle worth checking.
+const CYCLE_TOLERANCE: f64 = 1e-10;
+
+/// Once the rectangle of orbits is this wide, it can't shrink back enough to
+/// show the pixel is exterior.
+const HOPELESS_WIDTH: f64 = 4.0;
+
+/// How many boxes `attracting_cycle` tries before giving up.
+const TRAP_ATTEMPTS: u32 = 8;
+
+/// Return true if, for every `c` in `c`, there's an attracting cycle of
+/// period `period` through a box about `z`; see above.
+fn attracting_cycle(z: Complex<f64>, c: &Rect, period: u32) -> bool {
+    // Iterating a box directly overstates its image a little at each step,
+    // often more than the cycle's attraction makes up for. So also follow
+    // the box's center exactly, along with bounds on the derivatives by "z"
+    // and by "c" over the whole box; by the mean value theorem, the image
+    // lies within the center's image plus those derivatives times the
+    // distances from the center. It lies within both bounds.
+    let one = Rect::point(Complex { re: 1.0, im: 0.0 });
+    let zero = Rect::point(Complex { re: 0.0, im: 0.0 });
+    let c_center = c.center();
+    let c_offset = Rect {
+        re: c.re.sub(Interval::point(c_center.re)),
+        im: c.im.sub(Interval::point(c_center.im))
+    };
+    let mut center = Rect::point(z);
+    for _ in 0 .. period {
+        center = center.square_add(&Rect::point(c_center));
+    }
+
+    // Start with a box as wide as the pixel, and whenever its image doesn't
+    // fit, try one a bit larger than the image.
+    let mut radius = c.re.width().max(c.im.width()).max(f64::EPSILON);
+    for _ in 0 .. TRAP_ATTEMPTS {
+        let offset = Rect { re: Interval::outward(-radius, radius), im: Interval::outward(-radius, radius) };
+        let trap = Rect::point(z).add(&offset);
+        let (mut orbits, mut by_z, mut by_c) = (trap, one, zero);
+        for _ in 0 .. period {
+            let twice = Rect { re: orbits.re.double(), im: orbits.im.double() };
+            by_z = twice.mul(&by_z);
+            by_c = twice.mul(&by_c).add(&one);
+            orbits = orbits.square_add(c);
+            if orbits.norm_sqr().lo > 4.0 {
+                return false;
+            }
+        }
+        let image = center.add(&by_z.mul(&offset)).add(&by_c.mul(&c_offset))
+            .intersect(&orbits);
+        if image.inside(&trap) {
+            return true;
+        }
+        let reach = [image.re.lo - z.re, image.re.hi - z.re, image.im.lo - z.im, image.im.hi - z.im]
+            .iter().fold(0.0, |a: f64, b| a.max(b.abs()));
+        radius = reach * 1.5;
+    }
+    false
+}
+
+/// How many times `classify` may cut a rectangle into quarters.
+const SPLITS: u32 = 3;
+
+/// Classify the pixel covering the rectangle `c`, iterating at most `limit`
+/// times.
+pub fn classify(c: &Rect, limit: u32) -> Class {
+    classify_split(c, limit, SPLITS)
+}
+
+/// Like `classify`, but cut `c` into quarters at most `splits` times.
+fn classify_split(c: &Rect, limit: u32, splits: u32) -> Class {
+    let class = classify_whole(c, limit);
+    if class != Class::Unknown || splits == 0 || c.re.width() == 0.0 {
+        return class;
+    }
+    let middle = c.center();
+    let corners = [Complex { re: c.re.lo, im: c.im.hi }, Complex { re: c.re.hi, im: c.im.hi },
+                   Complex { re: c.re.lo, im: c.im.lo }, Complex { re: c.re.hi, im: c.im.lo }];
+    let first = classify_split(&Rect::new(corners[0], middle), limit, splits - 1);
+    for &corner in &corners[1 ..] {
+        if first == Class::Unknown || classify_split(&Rect::new(corner, middle), limit, splits - 1) != first {
+            return Class::Unknown;
+        }
+    }
+    first
+}
+
+/// Classify the rectangle `c` without cutting it up.
+fn classify_whole(c: &Rect, limit: u32) -> Class {
+    // The rectangle holding every orbit, until it grows hopelessly wide.
+    let mut z = Some(Rect::point(Complex { re: 0.0, im: 0.0 }));
+
+    // The floating-point orbit of the pixel's center, for spotting cycles.
+    let center = c.center();
+    let mut orbit = Complex { re: 0.0, im: 0.0 };
+    let (mut reference, mut reference_step, mut power) = (orbit, 0, 1);
+    // Whether the orbit has come back to the current reference point yet.
+    let mut returned = false;
+
+    for i in 1 ..= limit {
+        if let Some(ref mut z) = z {
+            *z = z.square_add(c);
+            if z.norm_sqr().lo > 4.0 {
+                return Class::Exterior;
+            }
+        }
+        if z.is_some_and(|z| z.re.width().max(z.im.width()) > HOPELESS_WIDTH) {
+            z = None;
+        }
+
+        orbit = orbit * orbit + center;
+        if orbit.norm_sqr() > 4.0 {
+            // The center escapes, so the pixel can't be interior, and the
+            // rectangle has grown too wide to show it's exterior.
+            if z.is_none() {
+                return Class::Unknown;
+            }
+        } else if !returned && (orbit - reference).norm_sqr() < CYCLE_TOLERANCE * CYCLE_TOLERANCE {
+            // Squaring shrinks boxes near zero the most, so center the box
+            // on the point of the cycle nearest zero.
+            let period = i - reference_step;
+            let (mut nearest, mut next) = (orbit, orbit);
+            for _ in 1 .. period {
+                next = next * next + center;
+                if next.norm_sqr() < nearest.norm_sqr() {
+                    nearest = next;
+                }
+            }
+            if [1, 2, 4, 8].iter().any(|&times| attracting_cycle(nearest, c, period * times)) {
+                return Class::Interior;
+            }
+            returned = true;
+        }
+        if i == power * 2 {
+            reference = orbit;
+            reference_step = i;
+            power = i;
+            returned = false;
+        }
+    }
+    Class::Unknown
+}
+
+/// Classify each pixel of an image of size `bounds` covering the region
+/// between `upper_left` and `lower_right`, iterating at most `limit` times,
+/// using `threads` threads. The pixels come row by row.
+pub fn classify_pixels(bounds: (usize, usize),
+                       upper_left: Complex<f64>,
+                       lower_right: Complex<f64>,
+                       limit: u32,
+                       threads: usize)
+    -> Vec<Class>
+{
+    use render::pixel_to_point;
+
+    let mut classes = vec![Class::Unknown; bounds.0 * bounds.1];
+    if classes.is_empty() {
+        return classes;
+    }
+    let rows_per_band = bounds.1 / threads.max(1) + 1;
+    std::thread::scope(|spawner| {
+        for (i, band) in classes.chunks_mut(rows_per_band * bounds.0).enumerate() {
+            spawner.spawn(move || {
+                for (j, class) in band.iter_mut().enumerate() {
+                    let (x, y) = (j % bounds.0, i * rows_per_band + j / bounds.0);
+                    // A pixel reaches from its own point to the next one's.
+                    let pixel = Rect::new(pixel_to_point(bounds, (x, y), upper_left, lower_right),
+                                          pixel_to_point(bounds, (x + 1, y + 1),
+                                                         upper_left, lower_right));
+                    *class = classify(&pixel, limit);
+                }
+            });
+        }
+    });
+    classes
+}
+
+#[cfg(test)]
+fn point(re: f64, im: f64) -> Rect {
+    Rect::point(Complex { re, im })
+}
+
+#[test]
+fn test_interval_arithmetic() {
+    let a = Interval::new(-1.0, 2.0);
+    let b = Interval::new(3.0, 4.0);
+    let contains = |i: Interval, lo: f64, hi: f64| i.lo <= lo && hi <= i.hi && i.width() < hi - lo + 1e-12;
+    assert!(contains(a.add(b), 2.0, 6.0));
+    assert!(contains(a.sub(b), -5.0, -1.0));
+    assert!(contains(a.mul(b), -4.0, 8.0));
+    assert!(contains(a.square(), 0.0, 4.0));
+    assert_eq!(a.square().lo, 0.0);
+    assert!(contains(Interval::new(-3.0, -2.0).square(), 4.0, 9.0));
+
+    // Rounding goes outward: 0.1 + 0.2 isn't 0.3 in floating point, but the
+    // interval holds both the true sum and its rounding.
+    let sum = Interval::point(0.1).add(Interval::point(0.2));
+    assert!(sum.lo < 0.1 + 0.2 && 0.1 + 0.2 < sum.hi);
+    assert!(sum.lo <= 0.3 && 0.3 <= sum.hi);
+}
+
+#[test]
+fn test_classify_points() {
+    // The main cardioid and the period-two bulb, with attracting cycles of
+    // periods one and two.
+    assert_eq!(classify(&point(0.0, 0.0), 100), Class::Interior);
+    assert_eq!(classify(&point(-0.1, 0.2), 100), Class::Interior);
+    assert_eq!(classify(&point(-1.0, 0.0), 100), Class::Interior);
+    assert_eq!(classify(&point(-1.1, 0.1), 200), Class::Interior);
+    // A period-three bulb.
+    assert_eq!(classify(&point(-0.12, 0.75), 500), Class::Interior);
+
+    assert_eq!(classify(&point(1.0, 0.0), 100), Class::Exterior);
+    assert_eq!(classify(&point(-0.75, 0.2), 100), Class::Exterior);
+
+    // -2 is in the set, but its orbit 0, -2, 2, 2, ... never settles into
+    // an attracting cycle, and 0.25 is where the cardioid's cycle stops
+    // attracting; neither can be proved either way.
+    assert_eq!(classify(&point(-2.0, 0.0), 1000), Class::Unknown);
+    assert_eq!(classify(&point(0.25, 0.0), 1000), Class::Unknown);
+}
+
+#[test]
+fn test_classify_pixels_agree_with_escape_time() {
+    // Pixels proved exterior escape, and those proved interior never do,
+    // wherever in the pixel we look.
+    let (bounds, upper_left, lower_right) = ((60, 40), Complex { re: -2.2, im: 1.2 },
+                                             Complex { re: 0.8, im: -1.2 });
+    let classes = classify_pixels(bounds, upper_left, lower_right, 500, 3);
+    let mut counts = [0; 3];
+    for (i, &class) in classes.iter().enumerate() {
+        let (x, y) = (i % bounds.0, i / bounds.0);
+        for &(dx, dy) in &[(0.0, 0.0), (0.5, 0.5), (0.99, 0.2)] {
+            let c = Complex {
+                re: upper_left.re + (x as f64 + dx) * 3.0 / 60.0,
+                im: upper_left.im - (y as f64 + dy) * 2.4 / 40.0
+            };
+            match class {
+                Class::Exterior => assert!(::escape_time(c, 5000).is_some(), "{}", c),
+                Class::Interior => assert_eq!(::escape_time(c, 5000), None, "{}", c),
+                Class::Unknown => {}
+            }
+        }
+        counts[class as usize] += 1;
+    }
+    // Most pixels are settled. These are coarse, so many near the boundary,
+    // though wholly in or out, aren't.
+    let [exterior, interior, unknown] = counts;
+    assert!(exterior > 1300 && interior > 150 && unknown < 900, "{:?}", counts);
+}
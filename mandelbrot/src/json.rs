@@ -0,0 +1,239 @@
+/* JSON
+ * ----
+ * Batch manifests and their result logs are JSON, one value per line. We
+ * need only a small reader and a way to quote strings, so here they are,
+ * rather than another dependency.
+ */
+
+use std::fmt::Write;
+
+/// A JSON value. Numbers keep their original text, so that integers and
+/// floats can be told apart.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    /// An object's members, in the order they appeared.
+    Object(Vec<(String, Json)>)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("{} at column {}", message, self.pos + 1))
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos ..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\r', '\n']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return self.error(&format!("expected `{}`", byte as char));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-') | Some(b'0' ..= b'9') => self.number(),
+            Some(_) => self.error("unexpected character"),
+            None => self.error("unexpected end of input")
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.pos ..].starts_with(word) {
+            return self.error("unexpected character");
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let rest = &self.text[self.pos ..];
+        let length = rest.find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(rest.len());
+        let text = &rest[.. length];
+        if text.parse::<f64>().is_err() || text.starts_with('+') {
+            return self.error("malformed number");
+        }
+        self.pos += length;
+        Ok(Json::Number(text.to_string()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut result = String::new();
+        let mut chars = self.text[self.pos ..].char_indices();
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(result);
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, '"'))  => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/'))  => '/',
+                        Some((_, 'b'))  => '\u{8}',
+                        Some((_, 'f'))  => '\u{c}',
+                        Some((_, 'n'))  => '\n',
+                        Some((_, 'r'))  => '\r',
+                        Some((_, 't'))  => '\t',
+                        Some((j, 'u'))  => {
+                            let start = self.pos + j + 1;
+                            let code = self.text.get(start .. start + 4)
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(std::char::from_u32);
+                            match code {
+                                Some(c) => {
+                                    for _ in 0 .. 4 {
+                                        chars.next();
+                                    }
+                                    c
+                                }
+                                None => return self.error("bad \\u escape")
+                            }
+                        }
+                        _ => return self.error("bad escape")
+                    };
+                    result.push(escaped);
+                }
+                c if (c as u32) < 0x20 => return self.error("control character in string"),
+                c => result.push(c)
+            }
+        }
+        self.error("unterminated string")
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return self.error("expected `,` or `]`")
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return self.error("expected `,` or `}`")
+            }
+        }
+    }
+}
+
+/// Parse `text`, which must hold exactly one JSON value.
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != text.len() {
+        return parser.error("unexpected text after value");
+    }
+    Ok(value)
+}
+
+/// Return `s` as a JSON string literal.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => { write!(quoted, "\\u{:04x}", c as u32).unwrap(); }
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse(" {\"a\": [1, -2.5e3, true, null], \"b\": {}, \"c\": \"x\\\"\\u00e9\"} "),
+               Ok(Json::Object(vec![
+                   ("a".to_string(), Json::Array(vec![
+                       Json::Number("1".to_string()),
+                       Json::Number("-2.5e3".to_string()),
+                       Json::Bool(true),
+                       Json::Null
+                   ])),
+                   ("b".to_string(), Json::Object(vec![])),
+                   ("c".to_string(), Json::String("x\"\u{e9}".to_string()))
+               ])));
+    assert_eq!(parse("[]"), Ok(Json::Array(vec![])));
+
+    assert_eq!(parse("{\"a\": 1,}"), Err("expected `\"` at column 9".to_string()));
+    assert!(parse("[1, 2").is_err());
+    assert!(parse("\"abc").is_err());
+    assert!(parse("{} {}").is_err());
+    assert!(parse("+1").is_err());
+    assert!(parse("1.2.3").is_err());
+    assert!(parse("").is_err());
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("plain"), "\"plain\"");
+    assert_eq!(quote("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    assert_eq!(parse(&quote("tab\there \u{e9}")), Ok(Json::String("tab\there \u{e9}".to_string())));
+}
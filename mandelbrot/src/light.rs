@@ -0,0 +1,153 @@
+/* Lighting
+ * --------
+ * The derivative dz/dc that the distance estimate uses also says which way
+ * the escape time falls off at each point: along z / dz, away from the set.
+ * Treating the escape time as the height of a surface, sloping down that
+ * way, gives every escaping pixel a normal vector, and lighting the surface
+ * makes the picture look embossed, as though the set were raised in relief.
+ * "--light" and "render.light" choose how:
+ *
+ *      lambert:ANGLE,HEIGHT    diffuse light only, a matte surface
+ *      phong:ANGLE,HEIGHT      diffuse light plus Blinn-Phong highlights, a
+ *                              glossy one
+ *
+ * The light comes from ANGLE degrees counterclockwise from the real axis,
+ * HEIGHT degrees above the plane; both may be left out, as in "phong", and
+ * default to 45. The shading scales whatever color the pixel would have had,
+ * and highlights add white on top. Points that never escape have no normal
+ * and are left alone.
+ */
+
+use num::Complex;
+
+/// How the surface reflects light.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Model {
+    Lambert,
+    BlinnPhong
+}
+
+/// A light shining on the surface the derivative describes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub model: Model,
+    /// The direction the light comes from, in degrees counterclockwise from
+    /// the real axis.
+    pub angle: f64,
+    /// The light's elevation above the plane, in degrees.
+    pub height: f64
+}
+
+/// The angle and height of a light whose spec leaves them out.
+const DEFAULT_ANGLE: f64 = 45.0;
+const DEFAULT_HEIGHT: f64 = 45.0;
+
+/// How much light reaches surfaces facing away from the light.
+const AMBIENT: f64 = 0.2;
+
+/// How sharp Blinn-Phong highlights are; larger is smaller and sharper.
+const SHININESS: f64 = 20.0;
+
+/// How bright Blinn-Phong highlights are at their peak.
+const SPECULAR: f64 = 0.5;
+
+type Vector = [f64; 3];
+
+fn dot(a: Vector, b: Vector) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: Vector) -> Vector {
+    let length = dot(v, v).sqrt();
+    v.map(|x| x / length)
+}
+
+impl Light {
+    /// Parse a light written as described above.
+    pub fn parse(s: &str) -> Option<Light> {
+        let (model, numbers) = match s.split_once(':') {
+            Some((model, numbers)) => (model.trim(), Some(numbers)),
+            None => (s.trim(), None)
+        };
+        let model = match model {
+            "lambert" => Model::Lambert,
+            "phong"   => Model::BlinnPhong,
+            _         => return None
+        };
+        let (angle, height) = match numbers {
+            None => (DEFAULT_ANGLE, DEFAULT_HEIGHT),
+            Some(numbers) => {
+                let numbers: Vec<f64> = numbers.split(',').map(|n| n.trim().parse().ok())
+                    .collect::<Option<_>>()?;
+                match numbers[..] {
+                    [angle] => (angle, DEFAULT_HEIGHT),
+                    [angle, height] => (angle, height),
+                    _ => return None
+                }
+            }
+        };
+        if !(angle.is_finite() && height > 0.0 && height <= 90.0) {
+            return None;
+        }
+        Some(Light { model, angle, height })
+    }
+
+    /// Return the unit vector pointing toward the light.
+    fn direction(&self) -> Vector {
+        let (sin_angle, cos_angle) = self.angle.to_radians().sin_cos();
+        let (sin_height, cos_height) = self.height.to_radians().sin_cos();
+        [cos_angle * cos_height, sin_angle * cos_height, sin_height]
+    }
+
+    /// Return how much a surface sloping down toward `normal`, as steeply as
+    /// `normal` is long, scales its color by, and how much highlight it
+    /// adds, as fractions of full brightness. `Sample::normal` is always one
+    /// long, for a slope of 45 degrees.
+    pub fn shade(&self, normal: Complex<f64>) -> (f64, f64) {
+        let n = normalize([normal.re, normal.im, 1.0]);
+        let light = self.direction();
+        let diffuse = AMBIENT + (1.0 - AMBIENT) * dot(n, light).max(0.0);
+        let specular = match self.model {
+            Model::Lambert => 0.0,
+            Model::BlinnPhong => {
+                // The viewer looks straight down, so the halfway vector lies
+                // between the light and the vertical.
+                let halfway = normalize([light[0], light[1], light[2] + 1.0]);
+                SPECULAR * dot(n, halfway).max(0.0).powf(SHININESS)
+            }
+        };
+        (diffuse, specular)
+    }
+}
+
+#[test]
+fn test_light_parse() {
+    let light = |model, angle, height| Some(Light { model, angle, height });
+    assert_eq!(Light::parse("lambert"), light(Model::Lambert, 45.0, 45.0));
+    assert_eq!(Light::parse("phong:120"), light(Model::BlinnPhong, 120.0, 45.0));
+    assert_eq!(Light::parse("phong: -30, 60"), light(Model::BlinnPhong, -30.0, 60.0));
+    for bad in ["", "gouraud", "lambert:", "lambert:x", "phong:0,0", "phong:0,91", "phong:1,2,3"] {
+        assert_eq!(Light::parse(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn test_light_shade() {
+    let light = Light::parse("lambert:0,45").unwrap();
+    let toward = Complex { re: 1.0, im: 0.0 };
+    // A slope facing the light is lit fully; one facing away, only by the
+    // ambient light; one facing sideways, in between.
+    let (lit, _) = light.shade(toward);
+    assert!((lit - 1.0).abs() < 1e-12, "{}", lit);
+    assert!((light.shade(-toward).0 - AMBIENT).abs() < 1e-12);
+    let (side, _) = light.shade(Complex { re: 0.0, im: 1.0 });
+    assert!(side > AMBIENT && side < 1.0, "{}", side);
+
+    // Highlights are brightest where the surface faces halfway between the
+    // light and the viewer, and fade away from there.
+    let phong = Light::parse("phong:0,45").unwrap();
+    let (_, peak) = phong.shade(Complex { re: (22.5f64).to_radians().tan(), im: 0.0 });
+    assert!((peak - SPECULAR).abs() < 1e-12, "{}", peak);
+    assert!(phong.shade(toward).1 < peak && phong.shade(-toward).1 < 0.01);
+    assert_eq!(light.shade(toward).1, 0.0);
+}
@@ -62,6 +62,42 @@
 extern crate num;
 use num::Complex;
 
+mod batch;
+mod bmp;
+mod checkpoint;
+mod config;
+mod deflate;
+mod distributed;
+mod dither;
+mod dump;
+mod dzi;
+mod fixed;
+mod font;
+mod fractal;
+mod image;
+mod interval;
+mod json;
+mod light;
+mod metadata;
+mod mosaic;
+mod npy;
+mod orbit;
+mod overlay;
+mod palette;
+mod parsing;
+mod png;
+mod pnm;
+mod progressive;
+mod render;
+mod server;
+mod sixel;
+mod text;
+mod tiff;
+mod tiles;
+mod trap;
+mod tui;
+mod view;
+
 #[allow(dead_code)]
 fn complex_square_add_loop(c: Complex<f64>) {
 	let mut z = Complex { re: 0.0, im: 0.0};
@@ -128,7 +164,7 @@ fn complex_square_add_loop(c: Complex<f64>) {
  */
 
 fn escape_time(c: Complex<f64>, limit: u32) -> Option<u32> {
-    let muz z = Complex { re: 0.0, im: 0.0 };
+    let mut z = Complex { re: 0.0, im: 0.0 };
     for i in 0..limit {
         z = z * z + c;
         if z.norm_sqr() > 4.0 {
@@ -182,6 +218,841 @@ fn escape_time(c: Complex<f64>, limit: u32) -> Option<u32> {
  * See here parsing.rs for Parsin Command Line Arguments
  */
 
+/* Writing Image Files
+ * -------------------
+ * Once the pixels are rendered, "write_image" saves them as a grayscale PNG
+ * file, using the small encoder in png.rs.
+ */
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Write the buffer `pixels`, whose dimensions are given by `bounds`, to the
+/// file named `filename`.
+fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize))
+    -> Result<(), std::io::Error>
+{
+    let output = File::create(filename)?;
+    let mut out = BufWriter::new(output);
+    png::write_png(&mut out, pixels, bounds)?;
+    out.flush()
+}
+
+/* The Main Program
+ * ----------------
+ * "main" parses the command line, renders the image in parallel and writes
+ * it out, a band of rows at a time so that even huge images fit in memory.
+ * Options such as "--palette" and "--julia" adjust the render the same way
+ * the settings of a job file do, and "mandelbrot render JOB" takes its
+ * settings from a job file instead; see config.rs. Either way,
+ * "--dump-config" prints the settings as a job file rather than rendering.
+ * "--center" and "--zoom" move the view, fitting it to the image's shape,
+ * and "--rotate" turns it; see view.rs. The output file's extension picks
+ * its format unless "--format" names one, and "--depth 16" writes 16-bit
+ * samples; see image.rs.
+ * Images rendered this way carry their job file, and "mandelbrot reproduce
+ * IMAGE" renders them again, to IMAGE-reproduced.png unless "--output" says
+ * otherwise; see metadata.rs.
+ * "mandelbrot batch MANIFEST" renders many jobs at once, logging how each
+ * went to MANIFEST.log; see batch.rs.
+ * With "--sixel", it shows the image in the terminal instead of writing
+ * FILE; see sixel.rs.
+ * With "--progressive", it renders coarse-to-fine instead, rewriting
+ * FILE after each pass so that a viewer can show the image as it sharpens.
+ * With "--checkpoint", it saves its progress to FILE.checkpoint every so
+ * often, and "--resume" picks up from that file after an interruption.
+ * The first argument may instead name a subcommand:
+ *
+ *      mandelbrot tui [OPTIONS]
+ *      mandelbrot text [--columns N] [--rows N] [--ramp ascii|unicode]
+ *                      [OPTIONS]
+ *      mandelbrot serve [--port N] [--threads N]
+ *      mandelbrot dzi BASE PIXELS UPPERLEFT LOWERRIGHT [--tile-size N]
+ *                     [--overlap N] [--threads N]
+ *      mandelbrot coordinator FILE PIXELS UPPERLEFT LOWERRIGHT [--port N]
+ *                     [--tile-size N] [--timeout SECS]
+ *      mandelbrot worker HOST:PORT [--threads N]
+ *      mandelbrot dump FILE PIXELS UPPERLEFT LOWERRIGHT [--limit N]
+ *                     [--fields LIST] [--threads N]
+ *      mandelbrot recolor DUMP FILE [--palette SPEC] [--cycle N]
+ *                     [--inside RRGGBB]
+ *      mandelbrot npy FILE PIXELS UPPERLEFT LOWERRIGHT [--limit N] [--smooth]
+ *                     [--threads N]
+ *      mandelbrot classify FILE PIXELS UPPERLEFT LOWERRIGHT [--limit N]
+ *                     [--threads N]
+ *      mandelbrot mosaic FILE CELLS UPPERLEFT LOWERRIGHT [--cell-size WxH]
+ *                     [--cell-limit N] [--cell-radius R] [--threads N]
+ *                     [OPTIONS]
+ *
+ * The first explores the set in the terminal, taking the render options to
+ * start from, and prints the options for wherever it ends up; see tui.rs.
+ * "text" prints the set as characters, 80 columns wide unless "--columns"
+ * says otherwise; see text.rs.
+ * The next serves tiles to a browser; see server.rs. The next writes a
+ * Deep Zoom pyramid, BASE.dzi and BASE_files/; see dzi.rs. The last two
+ * spread a render across machines; see distributed.rs. "dump" saves the
+ * iteration data for every pixel, and "recolor" turns a dump into a color
+ * image without iterating again; see dump.rs and palette.rs. "npy" writes
+ * the escape times as a NumPy array; see npy.rs. "classify" writes a
+ * grayscale image of what it could prove about each pixel, white for
+ * exterior, black for interior and gray for unknown, and prints how many
+ * of each; see interval.rs. "mosaic" fills a grid of cells laid out over
+ * the view with the Julia set for each cell's center; see mosaic.rs.
+ */
+
+fn usage() -> ! {
+    eprintln!("Usage: mandelbrot FILE PIXELS UPPERLEFT LOWERRIGHT [OPTIONS]");
+    eprintln!("       mandelbrot render JOB [OPTIONS]");
+    eprintln!("       mandelbrot batch MANIFEST [--log FILE] [--threads N]");
+    eprintln!("       mandelbrot reproduce IMAGE [OPTIONS]");
+    eprintln!("       mandelbrot tui [OPTIONS]");
+    eprintln!("       mandelbrot text [--columns N] [--rows N] [--ramp ascii|unicode] \
+               [OPTIONS]");
+    eprintln!("       mandelbrot serve [--port N] [--threads N]");
+    eprintln!("       mandelbrot dzi BASE PIXELS UPPERLEFT LOWERRIGHT \
+               [--tile-size N] [--overlap N] [--threads N]");
+    eprintln!("       mandelbrot coordinator FILE PIXELS UPPERLEFT LOWERRIGHT \
+               [--port N] [--tile-size N] [--timeout SECS]");
+    eprintln!("       mandelbrot worker HOST:PORT [--threads N]");
+    eprintln!("       mandelbrot dump FILE PIXELS UPPERLEFT LOWERRIGHT \
+               [--limit N] [--fields LIST] [--threads N]");
+    eprintln!("       mandelbrot recolor DUMP FILE [--palette SPEC] [--cycle N] \
+               [--inside RRGGBB]");
+    eprintln!("       mandelbrot npy FILE PIXELS UPPERLEFT LOWERRIGHT \
+               [--limit N] [--smooth] [--threads N]");
+    eprintln!("       mandelbrot classify FILE PIXELS UPPERLEFT LOWERRIGHT \
+               [--limit N] [--threads N]");
+    eprintln!("       mandelbrot mosaic FILE CELLS UPPERLEFT LOWERRIGHT \
+               [--cell-size WxH] [--cell-limit N] [--cell-radius R] [--threads N] [OPTIONS]");
+    eprintln!("Render options: --limit N  --antialias N  --palette SPEC  --cycle N  --trap SPEC");
+    eprintln!("                --coloring escape|stripe[:DENSITY]|triangle  --light SPEC");
+    eprintln!("                --julia RE,IM  --format NAME  --output FILE  --threads N");
+    eprintln!("                --center RE,IM  --zoom N  --rotate DEGREES  --depth 8|16");
+    eprintln!("                --dither none|floyd-steinberg|bayer  --overlay axes,ticks,grid");
+    eprintln!("                --title TEXT  --precision double|fixed");
+    eprintln!("                --dump-config  --sixel  --progressive | --checkpoint | --resume");
+    eprintln!("Example: mandelbrot mandel.png 1000x750 -1.20,0.35 -1,0.20");
+    std::process::exit(1);
+}
+
+/// How often a checkpointed render saves its progress.
+const CHECKPOINT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+fn available_threads() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+fn serve(args: &[String]) {
+    let mut port = 8080;
+    let mut threads = available_threads();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--port"    => port = value.parse().unwrap_or_else(|_| usage()),
+            "--threads" => threads = value.parse().unwrap_or_else(|_| usage()),
+            _           => usage()
+        }
+    }
+
+    let server = server::Server::bind(("127.0.0.1", port), threads)
+        .expect("error binding to port");
+    println!("Serving on http://{}/", server.local_addr().expect("error getting address"));
+    server.run().expect("error accepting connections");
+}
+
+fn export_dzi(args: &[String]) {
+    if args.len() < 4 {
+        usage();
+    }
+    let bounds = parsing::parse_pair(&args[1], 'x')
+        .expect("error parsing image dimensions");
+    let upper_left = parsing::parse_complex(&args[2])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[3])
+        .expect("error parsing lower right corner point");
+
+    let mut pyramid = dzi::Pyramid { bounds, tile_size: 254, overlap: 1 };
+    let mut threads = available_threads();
+
+    let mut flags = args[4..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--tile-size" => pyramid.tile_size = value.parse().unwrap_or_else(|_| usage()),
+            "--overlap"   => pyramid.overlap = value.parse().unwrap_or_else(|_| usage()),
+            "--threads"   => threads = value.parse().unwrap_or_else(|_| usage()),
+            _             => usage()
+        }
+    }
+    if pyramid.tile_size == 0 {
+        usage();
+    }
+
+    dzi::export(std::path::Path::new(&args[0]), &pyramid,
+                upper_left, lower_right, threads)
+        .expect("error writing Deep Zoom image");
+}
+
+fn coordinate(args: &[String]) {
+    if args.len() < 4 {
+        usage();
+    }
+    let bounds = parsing::parse_pair(&args[1], 'x')
+        .expect("error parsing image dimensions");
+    let upper_left = parsing::parse_complex(&args[2])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[3])
+        .expect("error parsing lower right corner point");
+
+    let mut port = 7878;
+    let mut tile_size = 128;
+    let mut timeout = 60;
+
+    let mut flags = args[4..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--port"      => port = value.parse().unwrap_or_else(|_| usage()),
+            "--tile-size" => tile_size = value.parse().unwrap_or_else(|_| usage()),
+            "--timeout"   => timeout = value.parse().unwrap_or_else(|_| usage()),
+            _             => usage()
+        }
+    }
+    if tile_size == 0 {
+        usage();
+    }
+    if tile_size > distributed::MAX_TILE_SIZE {
+        eprintln!("mandelbrot: --tile-size can be at most {}", distributed::MAX_TILE_SIZE);
+        std::process::exit(1);
+    }
+
+    let coordinator = distributed::Coordinator::bind(
+        ("0.0.0.0", port), bounds, upper_left, lower_right, tile_size,
+        std::time::Duration::from_secs(timeout))
+        .expect("error binding to port");
+    println!("Waiting for workers on {}",
+             coordinator.local_addr().expect("error getting address"));
+    let pixels = coordinator.run().expect("error accepting workers");
+
+    write_image(&args[0], &pixels, bounds)
+        .expect("error writing image file");
+}
+
+fn work(args: &[String]) {
+    if args.is_empty() {
+        usage();
+    }
+    let mut threads = available_threads();
+
+    let mut flags = args[1..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--threads" => threads = value.parse().unwrap_or_else(|_| usage()),
+            _           => usage()
+        }
+    }
+
+    let tiles = distributed::run_worker(args[0].as_str(), threads)
+        .expect("error talking to coordinator");
+    println!("rendered {} tiles", tiles);
+}
+
+fn dump(args: &[String]) {
+    if args.len() < 4 {
+        usage();
+    }
+    let bounds = parsing::parse_pair(&args[1], 'x')
+        .expect("error parsing image dimensions");
+    let upper_left = parsing::parse_complex(&args[2])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[3])
+        .expect("error parsing lower right corner point");
+
+    let mut limit = 1000;
+    let mut fields = dump::Fields::ALL;
+    let mut threads = available_threads();
+
+    let mut flags = args[4..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--limit"   => limit = value.parse().unwrap_or_else(|_| usage()),
+            "--fields"  => fields = dump::Fields::parse(value).unwrap_or_else(|| usage()),
+            "--threads" => threads = value.parse().unwrap_or_else(|_| usage()),
+            _           => usage()
+        }
+    }
+
+    dump::Dump::render(bounds, upper_left, lower_right, limit, fields, threads)
+        .save(std::path::Path::new(&args[0]))
+        .expect("error writing dump");
+}
+
+fn recolor(args: &[String]) {
+    if args.len() < 2 {
+        usage();
+    }
+    let mut palette = palette::Palette::parse("ultra").unwrap();
+    let mut cycle = None;
+    let mut inside = [0, 0, 0];
+
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--palette" => palette = palette::Palette::parse(value).unwrap_or_else(|| usage()),
+            "--cycle"   => cycle = Some(value.parse().unwrap_or_else(|_| usage())),
+            "--inside"  => inside = palette::parse_rgb(value).unwrap_or_else(|| usage()),
+            _           => usage()
+        }
+    }
+
+    let dump = dump::Dump::load(std::path::Path::new(&args[0]))
+        .expect("error reading dump");
+    let rgb = dump.recolor(&palette, cycle, inside);
+
+    let mut out = BufWriter::new(File::create(&args[1]).expect("error creating PNG file"));
+    png::write_png_rgb(&mut out, &rgb, dump.bounds)
+        .and_then(|()| out.flush())
+        .expect("error writing image file");
+}
+
+fn export_npy(args: &[String]) {
+    if args.len() < 4 {
+        usage();
+    }
+    let bounds = parsing::parse_pair(&args[1], 'x')
+        .expect("error parsing image dimensions");
+    let upper_left = parsing::parse_complex(&args[2])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[3])
+        .expect("error parsing lower right corner point");
+
+    let mut limit = 1000;
+    let mut smooth = false;
+    let mut threads = available_threads();
+
+    let mut flags = args[4..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--smooth"  => smooth = true,
+            "--limit"   => {
+                let value = flags.next().unwrap_or_else(|| usage());
+                limit = value.parse().unwrap_or_else(|_| usage());
+            }
+            "--threads" => {
+                let value = flags.next().unwrap_or_else(|| usage());
+                threads = value.parse().unwrap_or_else(|_| usage());
+            }
+            _           => usage()
+        }
+    }
+
+    let fields = dump::Fields { smooth, z: false, distance: false };
+    let samples = dump::Dump::render(bounds, upper_left, lower_right, limit, fields, threads)
+        .samples;
+
+    let mut out = BufWriter::new(File::create(&args[0]).expect("error creating .npy file"));
+    let written = if smooth {
+        let values: Vec<Option<f64>> =
+            samples.iter().map(|s| s.count.map(|_| s.smooth)).collect();
+        npy::write_smooth(&mut out, &values, bounds)
+    } else {
+        let counts: Vec<Option<u32>> = samples.iter().map(|s| s.count).collect();
+        npy::write_counts(&mut out, &counts, bounds)
+    };
+    written.expect("error writing .npy file");
+}
+
+fn classify(args: &[String]) {
+    if args.len() < 4 {
+        usage();
+    }
+    let bounds = parsing::parse_pair(&args[1], 'x')
+        .expect("error parsing image dimensions");
+    let upper_left = parsing::parse_complex(&args[2])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[3])
+        .expect("error parsing lower right corner point");
+
+    let mut limit = 1000;
+    let mut threads = available_threads();
+
+    let mut flags = args[4..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--limit"   => limit = value.parse().unwrap_or_else(|_| usage()),
+            "--threads" => threads = value.parse().unwrap_or_else(|_| usage()),
+            _           => usage()
+        }
+    }
+
+    use interval::Class;
+    let classes = interval::classify_pixels(bounds, upper_left, lower_right, limit, threads);
+    let pixels: Vec<u8> = classes.iter().map(|&class| match class {
+        Class::Exterior => 255,
+        Class::Interior => 0,
+        Class::Unknown  => 128
+    }).collect();
+    write_image(&args[0], &pixels, bounds)
+        .expect("error writing image file");
+
+    let count = |class| classes.iter().filter(|&&c| c == class).count();
+    println!("exterior: {}  interior: {}  unknown: {}",
+             count(Class::Exterior), count(Class::Interior), count(Class::Unknown));
+}
+
+/// Parse the arguments of the "mosaic" command, returning the configuration
+/// to lay the mosaic over, the mosaic, and the number of threads to use.
+fn mosaic_job(args: &[String]) -> (config::Config, mosaic::Mosaic, usize) {
+    if args.len() < 4 {
+        usage();
+    }
+    let cells = parsing::parse_pair(&args[1], 'x')
+        .expect("error parsing mosaic dimensions");
+    let upper_left = parsing::parse_complex(&args[2])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[3])
+        .expect("error parsing lower right corner point");
+
+    let mut config = config::Config::new((0, 0), upper_left, lower_right, &args[0]);
+    let mut cell_size = mosaic::DEFAULT_CELL_SIZE;
+    let mut cell_limit = None;
+    let mut cell_radius = mosaic::DEFAULT_CELL_RADIUS;
+    let mut threads = available_threads();
+    let mut options = Vec::new();
+
+    let mut flags = args[4..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--cell-size"   => cell_size = parsing::parse_pair(value, 'x').unwrap_or_else(|| usage()),
+            "--cell-limit"  => match value.parse() {
+                Ok(limit) if limit > 0 => cell_limit = Some(limit),
+                _ => usage()
+            },
+            "--cell-radius" => match value.parse::<f64>() {
+                Ok(radius) if radius > 0.0 && radius.is_finite() => cell_radius = radius,
+                _ => usage()
+            },
+            "--threads"     => threads = value.parse().unwrap_or_else(|_| usage()),
+            // Every cell is a Julia set already.
+            "--julia"       => usage(),
+            _               => options.push((flag, value))
+        }
+    }
+
+    // Options like "--center" fit the view to the image, so it needs its
+    // size first; the cells' limit may come from "--limit".
+    let mut mosaic = mosaic::Mosaic { cells, cell_size, cell_radius, cell_limit: 0 };
+    config.bounds = mosaic.bounds();
+    for (flag, value) in options {
+        if !set_render_option(&mut config, flag, value) {
+            usage();
+        }
+    }
+    mosaic.cell_limit = cell_limit.unwrap_or(config.limit);
+    if let Err(message) = config.check_format().and_then(|_| config.check_precision()) {
+        eprintln!("mandelbrot: {}", message);
+        std::process::exit(1);
+    }
+    (config, mosaic, threads)
+}
+
+fn render_mosaic(args: &[String]) {
+    let (config, mosaic, threads) = mosaic_job(args);
+
+    // The image isn't the render of any one job, so it carries none.
+    let out = BufWriter::new(File::create(&config.output).expect("error creating image file"));
+    let software = metadata::software();
+    image::create(out, config.format(), config.bounds, config.channels(), config.depth,
+                  &[(metadata::SOFTWARE_KEY, &software)])
+        .and_then(|mut writer| {
+            mosaic.render_bands(&config, threads, |band| writer.write_rows(band))?;
+            writer.finish()
+        })
+        .expect("error writing image file");
+}
+
+/// Apply one of the command-line options that correspond to a setting in a
+/// job file. Return false if `flag` isn't one of them, or `value` isn't valid
+/// for it.
+fn set_render_option(config: &mut config::Config, flag: &str, value: &str) -> bool {
+    match flag {
+        "--limit" => match value.parse() {
+            Ok(limit) if limit > 0 => config.limit = limit,
+            _ => return false
+        },
+        "--antialias" => match value.parse() {
+            Ok(n) if (1 ..= config::MAX_ANTIALIAS).contains(&n) => config.antialias = n,
+            _ => return false
+        },
+        "--palette" => match palette::Palette::parse(value) {
+            Some(_) => config.palette = Some(value.to_string()),
+            None => return false
+        },
+        "--cycle" => match value.parse() {
+            Ok(cycle) if cycle > 0.0 => config.cycle = Some(cycle),
+            _ => return false
+        },
+        "--trap" => match trap::Trap::parse(value) {
+            Some(_) => {
+                config.trap = Some(value.to_string());
+                config.coloring = config::Coloring::Escape;
+            }
+            None => return false
+        },
+        "--light" => match light::Light::parse(value) {
+            Some(_) => config.light = Some(value.to_string()),
+            None => return false
+        },
+        "--coloring" => match config::Coloring::parse(value) {
+            Some(coloring) => {
+                config.coloring = coloring;
+                config.trap = None;
+            }
+            None => return false
+        },
+        "--julia" => match parsing::parse_complex(value) {
+            Some(c) => config.fractal = fractal::Fractal::Julia(c),
+            None => return false
+        },
+        "--format" => match config::Format::parse(value) {
+            Some(format) => config.format = Some(format),
+            None => return false
+        },
+        "--precision" => match fixed::Precision::parse(value) {
+            Some(precision) => config.precision = precision,
+            None => return false
+        },
+        "--overlay" => match overlay::Overlay::parse(value) {
+            Some(_) => config.overlay = Some(value.to_string()),
+            None => return false
+        },
+        "--title" => config.title = Some(value.to_string()),
+        "--dither" => match dither::Dither::parse(value) {
+            Some(dither) => config.dither = dither,
+            None => return false
+        },
+        "--depth" => match value {
+            "8" => config.depth = 8,
+            "16" => config.depth = 16,
+            _ => return false
+        },
+        "--output" => config.output = value.to_string(),
+        "--center" => match parsing::parse_complex(value) {
+            Some(center) => {
+                let view = view::View { center, ..config.view() };
+                config.set_view(view);
+            }
+            None => return false
+        },
+        "--zoom" => match value.parse::<f64>() {
+            Ok(zoom) if zoom > 0.0 && zoom.is_finite() => {
+                let current = config.view();
+                let view = view::View::with_zoom(current.center, zoom);
+                config.set_view(view::View { rotation: current.rotation, ..view });
+            }
+            _ => return false
+        },
+        "--rotate" => match value.parse::<f64>() {
+            Ok(degrees) if degrees.is_finite() => config.rotation = degrees,
+            _ => return false
+        },
+        _ => return false
+    }
+    true
+}
+
+/// Write the pixels rendered for `config` to its output file.
+fn write_output(config: &config::Config, pixels: &[u8]) -> Result<(), std::io::Error> {
+    let out = BufWriter::new(File::create(&config.output)?);
+    metadata::write_image(out, config, pixels)
+}
+
+/// Render the image `config` describes to its output file using `threads`
+/// threads, writing each band of rows as soon as it's done.
+fn render_output(config: &config::Config, threads: usize) -> Result<(), std::io::Error> {
+    let out = BufWriter::new(File::create(&config.output)?);
+    let mut writer = metadata::image_writer(out, config)?;
+    render::render_config_bands(config, threads, |band| writer.write_rows(band))?;
+    writer.finish()
+}
+
+/// Render the image `config` describes, as modified by the options in `flags`.
+fn run_render(mut config: config::Config, flags: &[String]) {
+    let mut progressive = false;
+    let mut checkpointed = false;
+    let mut resume = false;
+    let mut dump_config = false;
+    let mut sixel = false;
+    let mut threads = available_threads();
+
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--progressive" => progressive = true,
+            "--checkpoint"  => checkpointed = true,
+            "--resume"      => resume = true,
+            "--dump-config" => dump_config = true,
+            "--sixel"       => sixel = true,
+            "--threads"     => {
+                let value = flags.next().unwrap_or_else(|| usage());
+                threads = value.parse().unwrap_or_else(|_| usage());
+            }
+            _               => {
+                let value = flags.next().unwrap_or_else(|| usage());
+                if !set_render_option(&mut config, flag, value) {
+                    usage();
+                }
+            }
+        }
+    }
+
+    if let Err(message) = config.check_format().and_then(|_| config.check_precision()) {
+        eprintln!("mandelbrot: {}", message);
+        std::process::exit(1);
+    }
+    if dump_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+    if [progressive, checkpointed || resume, sixel].iter().filter(|&&mode| mode).count() > 1 {
+        usage();
+    }
+    if sixel {
+        // The terminal gets the image instead of a file.
+        let config = config::Config { depth: 8, ..config };
+        let pixels = render::render_config(&config, threads);
+        let mut out = std::io::stdout();
+        write!(out, "{}", sixel::encode(&pixels, config.bounds, config.channels(), config.dither))
+            .and_then(|_| out.flush())
+            .expect("error writing to the terminal");
+        return;
+    }
+
+    let output = config.output.clone();
+    if checkpointed || resume {
+        let path = std::path::PathBuf::from(format!("{}.checkpoint", output));
+        let mut render = if resume {
+            checkpoint::Checkpoint::resume(&path, &config)
+                .unwrap_or_else(|err| {
+                    eprintln!("mandelbrot: can't resume from {}: {}", path.display(), err);
+                    std::process::exit(1);
+                })
+        } else {
+            checkpoint::Checkpoint::new(config.clone())
+        };
+        render.render(&path, threads, CHECKPOINT_INTERVAL, |_| true)
+            .expect("error saving checkpoint");
+        write_output(&config, &render.image())
+            .expect("error writing image file");
+        if path.exists() {
+            std::fs::remove_file(&path).expect("error removing checkpoint");
+        }
+    } else if progressive {
+        progressive::render_progressive(&config, threads, |step, preview| {
+            write_output(&config, preview)
+                .expect("error writing image file");
+            println!("wrote {} at 1/{} resolution", output, step);
+        });
+    } else {
+        render_output(&config, threads)
+            .expect("error writing image file");
+    }
+}
+
+fn render_job(args: &[String]) {
+    if args.is_empty() {
+        usage();
+    }
+    let text = std::fs::read_to_string(&args[0]).unwrap_or_else(|err| {
+        eprintln!("mandelbrot: can't read {}: {}", args[0], err);
+        std::process::exit(1);
+    });
+    let config = config::Config::parse(&text).unwrap_or_else(|err| {
+        eprintln!("mandelbrot: {}: {}", args[0], err);
+        std::process::exit(1);
+    });
+    run_render(config, &args[1..]);
+}
+
+fn run_batch(args: &[String]) {
+    if args.is_empty() {
+        usage();
+    }
+    let mut log_path = format!("{}.log", args[0]);
+    let mut threads = available_threads();
+
+    let mut flags = args[1..].iter();
+    while let Some(flag) = flags.next() {
+        let value = flags.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--log"     => log_path = value.clone(),
+            "--threads" => threads = value.parse().unwrap_or_else(|_| usage()),
+            _           => usage()
+        }
+    }
+
+    let manifest = std::fs::read_to_string(&args[0]).unwrap_or_else(|err| {
+        eprintln!("mandelbrot: can't read {}: {}", args[0], err);
+        std::process::exit(1);
+    });
+    let mut log = File::create(&log_path).expect("error creating results log");
+    let outcomes = batch::run(&manifest, threads, &mut log)
+        .expect("error writing results log");
+
+    let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+    for outcome in outcomes.iter().filter(|o| o.error.is_some()) {
+        eprintln!("mandelbrot: job failed: {}", outcome.error.as_ref().unwrap());
+    }
+    println!("{} jobs, {} failed; results in {}", outcomes.len(), failed, log_path);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn reproduce(args: &[String]) {
+    if args.is_empty() {
+        usage();
+    }
+    let file = std::fs::read(&args[0]).unwrap_or_else(|err| {
+        eprintln!("mandelbrot: can't read {}: {}", args[0], err);
+        std::process::exit(1);
+    });
+    let provenance = metadata::read(&file).unwrap_or_else(|err| {
+        eprintln!("mandelbrot: {}: {}", args[0], err);
+        std::process::exit(1);
+    });
+    if provenance.software.as_ref() != Some(&metadata::software()) {
+        eprintln!("mandelbrot: warning: {} was made by {}; the pixels may differ",
+                  args[0], provenance.software.as_deref().unwrap_or("an unknown version"));
+    }
+
+    let mut config = provenance.config;
+    let path = std::path::Path::new(&args[0]);
+    config.output = path.with_file_name(format!(
+        "{}-reproduced.png",
+        path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default()
+    )).to_string_lossy().into_owned();
+    run_render(config, &args[1..]);
+}
+
+/// Return the configuration the "tui" and "text" commands start from: the
+/// whole Mandelbrot set, at a size they replace.
+fn whole_set() -> config::Config {
+    let view = view::View::new(Complex { re: -0.5, im: 0.0 }, view::BASE_RADIUS);
+    let bounds = (80, 48);
+    let (upper_left, lower_right) = view.corners(bounds);
+    config::Config::new(bounds, upper_left, lower_right, "unused.png")
+}
+
+fn view_in_terminal(args: &[String]) {
+    let mut config = whole_set();
+    let mut threads = available_threads();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        if flag == "--threads" {
+            threads = value.parse().unwrap_or_else(|_| usage());
+        } else if !set_render_option(&mut config, flag, value) {
+            usage();
+        }
+    }
+
+    match tui::run(config, threads) {
+        Ok(options) => println!("{}", options),
+        Err(err) => {
+            eprintln!("mandelbrot: tui: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_text(args: &[String]) {
+    let mut config = whole_set();
+    let mut columns = 80;
+    let mut rows = None;
+    let mut ramp = text::Ramp::Ascii;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--columns" => columns = value.parse().unwrap_or_else(|_| usage()),
+            "--rows"    => rows = Some(value.parse().unwrap_or_else(|_| usage())),
+            "--ramp"    => ramp = text::Ramp::parse(value).unwrap_or_else(|| usage()),
+            _           => if !set_render_option(&mut config, flag, value) {
+                usage();
+            }
+        }
+    }
+    if columns == 0 || rows == Some(0) {
+        usage();
+    }
+
+    // By default, as many rows as the view's shape calls for.
+    let rows = rows.unwrap_or_else(|| {
+        let (width, height) = (config.lower_right.re - config.upper_left.re,
+                               config.upper_left.im - config.lower_right.im);
+        ((columns as f64 * height / width / 2.0).round() as usize).max(1)
+    });
+    text::fit(&mut config, columns, rows);
+    print!("{}", text::render_text(&config, ramp));
+}
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(|s| s.as_str()) {
+        Some("serve")       => return serve(&args[2..]),
+        Some("dzi")         => return export_dzi(&args[2..]),
+        Some("coordinator") => return coordinate(&args[2..]),
+        Some("worker")      => return work(&args[2..]),
+        Some("dump")        => return dump(&args[2..]),
+        Some("recolor")     => return recolor(&args[2..]),
+        Some("npy")         => return export_npy(&args[2..]),
+        Some("classify")    => return classify(&args[2..]),
+        Some("mosaic")      => return render_mosaic(&args[2..]),
+        Some("render")      => return render_job(&args[2..]),
+        Some("batch")       => return run_batch(&args[2..]),
+        Some("reproduce")   => return reproduce(&args[2..]),
+        Some("tui")         => return view_in_terminal(&args[2..]),
+        Some("text")        => return print_text(&args[2..]),
+        _                   => {}
+    }
+
+    if args.len() < 5 {
+        usage();
+    }
+
+    let bounds = parsing::parse_pair(&args[2], 'x')
+        .expect("error parsing image dimensions");
+    let upper_left = parsing::parse_complex(&args[3])
+        .expect("error parsing upper left corner point");
+    let lower_right = parsing::parse_complex(&args[4])
+        .expect("error parsing lower right corner point");
+
+    let config = config::Config::new(bounds, upper_left, lower_right, &args[1]);
+    run_render(config, &args[5..]);
+}
+
+#[test]
+fn test_mosaic_job_view_options() {
+    let args: Vec<String> = ["m.png", "4x3", "-2,1.2", "1,-1.2", "--cell-size", "8x8",
+                             "--center", "0,0.5", "--limit", "50"]
+        .iter().map(|s| s.to_string()).collect();
+    let (config, mosaic, _) = mosaic_job(&args);
+    assert_eq!(config.bounds, mosaic.bounds());
+    assert_eq!((mosaic.bounds(), mosaic.cell_limit), ((32, 24), 50));
+
+    // The cells sit symmetrically about the new center.
+    let (first, last) = (mosaic.cell_point(&config, (0, 0)), mosaic.cell_point(&config, (3, 2)));
+    assert!(((first + last) / 2.0 - Complex { re: 0.0, im: 0.5 }).norm() < 1e-12);
+    assert!(first.re < last.re && first.im > last.im);
 }
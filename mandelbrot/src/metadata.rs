@@ -0,0 +1,98 @@
+/* Reproducible Images
+ * -------------------
+ * Every PNG image we render from a "Config" carries the job file that made
+ * it, in a text chunk, along with the version of the program:
+ *
+ *      Software            mandelbrot 0.1.0
+ *      mandelbrot job      [fractal]
+ *                          type = "mandelbrot"
+ *                          ...
+ *
+ * "Config::to_toml" prints every number so that it reads back exactly, so
+ * "mandelbrot reproduce IMAGE" can parse the job back out and render the
+ * same pixels again, however far the image was zoomed. The other formats
+ * have no place for text, and go without.
+ */
+
+use std::io::{self, Seek, Write};
+
+use config::Config;
+use image::{self, ImageWriter};
+use png;
+
+/// The keyword of the text chunk holding the job file.
+pub const JOB_KEY: &str = "mandelbrot job";
+
+/// The keyword of the text chunk naming the program that wrote the image.
+pub const SOFTWARE_KEY: &str = "Software";
+
+/// Return the name and version of this program, as PNG's "Software" chunk
+/// and TIFF's Software tag give it.
+pub fn software() -> String {
+    format!("mandelbrot {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Start writing the image `config` describes to `out`, in the format and
+/// depth it asks for, carrying `config` as its job file if the format
+/// allows. Its rows are to follow.
+pub fn image_writer<'a, W: Write + Seek + 'a>(out: W, config: &Config)
+    -> io::Result<Box<dyn ImageWriter + 'a>>
+{
+    let (software, job) = (software(), config.to_toml());
+    image::create(out, config.format(), config.bounds, config.channels(), config.depth,
+                  &[(SOFTWARE_KEY, &software), (JOB_KEY, &job)])
+}
+
+/// Write `pixels`, rendered from `config`, to `out`, as `image_writer` does.
+pub fn write_image<W: Write + Seek>(out: W, config: &Config, pixels: &[u8]) -> io::Result<()> {
+    let mut writer = image_writer(out, config)?;
+    writer.write_rows(pixels)?;
+    writer.finish()
+}
+
+/// What an image says about how it was made.
+#[derive(Debug, PartialEq)]
+pub struct Provenance {
+    pub config: Config,
+    /// The program that wrote the image, if it said.
+    pub software: Option<String>
+}
+
+/// Read back the job that made the PNG image `file`.
+pub fn read(file: &[u8]) -> Result<Provenance, String> {
+    let text = png::read_text(file).map_err(|err| err.to_string())?;
+    let find = |key: &str| text.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+
+    let job = find(JOB_KEY)
+        .ok_or("image has no mandelbrot job; it wasn't rendered by this program")?;
+    let config = Config::parse(&job).map_err(|err| format!("embedded job: {}", err))?;
+    Ok(Provenance { config, software: find(SOFTWARE_KEY) })
+}
+
+#[test]
+fn test_reproduce() {
+    use fractal::Fractal;
+    use num::Complex;
+    use render::render_config;
+    use std::io::Cursor;
+
+    let mut config = Config::new((30, 20), Complex { re: -0.7436438870371587, im: 0.1318259042053119 },
+                                 Complex { re: -0.7436438870371586, im: 0.1318259042053118 },
+                                 "deep.png");
+    config.fractal = Fractal::Julia(Complex { re: 0.1 / 3.0, im: -0.8 });
+    config.palette = Some("fire".to_string());
+    config.rotation = 10.0;
+    let pixels = render_config(&config, 2);
+
+    let mut file = Cursor::new(Vec::new());
+    write_image(&mut file, &config, &pixels).unwrap();
+    let provenance = read(file.get_ref()).unwrap();
+    assert_eq!(provenance.config, config);
+    assert_eq!(provenance.software, Some(software()));
+    assert_eq!(render_config(&provenance.config, 3), pixels);
+
+    let mut plain = Vec::new();
+    png::write_png(&mut plain, &[0; 4], (2, 2)).unwrap();
+    assert_eq!(read(&plain).unwrap_err(),
+               "image has no mandelbrot job; it wasn't rendered by this program");
+}
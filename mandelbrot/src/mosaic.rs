@@ -0,0 +1,158 @@
+/* Julia Mosaics
+ * -------------
+ * Each point "c" has its own Julia set, and the Mandelbrot set is a map of
+ * them: the points inside it have connected Julia sets, and those outside,
+ * scattered dust. A mosaic shows the map and its territory at once. It
+ * divides the image into a grid of cells, laid out like the pixels of a
+ * Mandelbrot render of the same view, and fills each cell with the Julia
+ * set for the "c" at that cell's center:
+ *
+ *      mandelbrot mosaic FILE CELLS UPPERLEFT LOWERRIGHT [--cell-size WxH]
+ *                     [--cell-limit N] [--cell-radius R] [--threads N]
+ *                     [OPTIONS]
+ *
+ * Each cell is an ordinary Julia render, taking its palette, coloring,
+ * anti-aliasing and so on from the render options, at "--cell-size" pixels
+ * (64x64 unless given) and iterating up to "--cell-limit" times (or
+ * "--limit", unless given). Every cell shows the same region of its plane,
+ * "--cell-radius" about the origin; the default, 1.5, frames most Julia sets
+ * snugly. A few cells across already outline the Mandelbrot set; the cells
+ * near its boundary are where the Julia sets are most intricate.
+ *
+ * The image is rendered and handed on a row of cells at a time, so, like
+ * any other render, it never has to be in memory whole. Overlays and titles
+ * are drawn over the whole mosaic, marking the "c" plane, not in each cell.
+ */
+
+use config::Config;
+use fractal::Fractal;
+use num::Complex;
+use overlay;
+use render::render_config;
+use view::View;
+
+/// The radius of the region of its plane a cell shows, unless told otherwise.
+pub const DEFAULT_CELL_RADIUS: f64 = 1.5;
+
+/// The size of each cell in pixels, unless told otherwise.
+pub const DEFAULT_CELL_SIZE: (usize, usize) = (64, 64);
+
+/// How to lay out a mosaic of Julia sets over a `Config`'s view.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mosaic {
+    /// The number of cells across and down.
+    pub cells: (usize, usize),
+    /// The size of each cell, in pixels.
+    pub cell_size: (usize, usize),
+    /// The iteration limit for each cell's Julia set.
+    pub cell_limit: u32,
+    /// The radius of the region about the origin each cell shows.
+    pub cell_radius: f64
+}
+
+impl Mosaic {
+    /// Return the size of the whole image, in pixels.
+    pub fn bounds(&self) -> (usize, usize) {
+        (self.cells.0 * self.cell_size.0, self.cells.1 * self.cell_size.1)
+    }
+
+    /// Return the point whose Julia set the cell at `cell`, a column and row,
+    /// shows, in a mosaic over `config`'s view.
+    pub fn cell_point(&self, config: &Config, cell: (usize, usize)) -> Complex<f64> {
+        // Cell centers are the odd pixels of a grid twice as fine.
+        let fine = (self.cells.0 * 2, self.cells.1 * 2);
+        config.pixel_to_point(fine, (cell.0 * 2 + 1, cell.1 * 2 + 1))
+    }
+
+    /// Return the configuration for rendering the cell at `cell`.
+    pub fn cell_config(&self, config: &Config, cell: (usize, usize)) -> Config {
+        let mut cell_config = Config {
+            fractal: Fractal::Julia(self.cell_point(config, cell)),
+            bounds: self.cell_size,
+            limit: self.cell_limit,
+            overlay: None,
+            title: None,
+            ..config.clone()
+        };
+        cell_config.set_view(View::new(Complex { re: 0.0, im: 0.0 }, self.cell_radius));
+        cell_config
+    }
+
+    /// Render the mosaic over `config`'s view, with its render settings, a
+    /// row of cells at a time, using `threads` threads, and pass each row
+    /// to `sink` in order, as `render::render_config_bands` does.
+    pub fn render_bands<F, E>(&self, config: &Config, threads: usize, mut sink: F) -> Result<(), E>
+        where F: FnMut(&[u8]) -> Result<(), E>
+    {
+        let (cell_width, cell_height) = self.cell_size;
+        let cell_row_bytes = cell_width * config.pixel_bytes();
+        let row_bytes = cell_row_bytes * self.cells.0;
+        let mut band = vec![0; row_bytes * cell_height];
+
+        for row in 0 .. self.cells.1 {
+            for column in 0 .. self.cells.0 {
+                let pixels = render_config(&self.cell_config(config, (column, row)), threads);
+                for (y, line) in pixels.chunks(cell_row_bytes).enumerate() {
+                    let start = y * row_bytes + column * cell_row_bytes;
+                    band[start .. start + cell_row_bytes].copy_from_slice(line);
+                }
+            }
+            overlay::draw(config, &mut band, row * cell_height);
+            sink(&band)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_mosaic() -> (Config, Mosaic) {
+    let config = Config::new((0, 0), Complex { re: -2.0, im: 1.2 },
+                             Complex { re: 1.0, im: -1.2 }, "unused.png");
+    let mosaic = Mosaic { cells: (5, 4), cell_size: (12, 10), cell_limit: 50, cell_radius: 1.5 };
+    (config, mosaic)
+}
+
+#[test]
+fn test_mosaic_cells() {
+    let (config, mosaic) = test_mosaic();
+    assert_eq!(mosaic.bounds(), (60, 40));
+
+    // The cells sit where the pixels of a Mandelbrot render would.
+    let close = |a: Complex<f64>, b: Complex<f64>| (a - b).norm() < 1e-12;
+    assert!(close(mosaic.cell_point(&config, (0, 0)), Complex { re: -1.7, im: 0.9 }));
+    assert!(close(mosaic.cell_point(&config, (4, 3)), Complex { re: 0.7, im: -0.9 }));
+
+    // Each cell is a Julia render of its own, square about the origin.
+    let cell = mosaic.cell_config(&config, (2, 1));
+    assert_eq!(cell.fractal, Fractal::Julia(mosaic.cell_point(&config, (2, 1))));
+    assert_eq!((cell.bounds, cell.limit), ((12, 10), 50));
+    assert!(close(cell.upper_left, Complex { re: -1.8, im: 1.5 }));
+    assert!(close(cell.lower_right, Complex { re: 1.8, im: -1.5 }));
+}
+
+#[test]
+fn test_mosaic_render() {
+    // The image holds each cell's own render in its place.
+    let (mut config, mosaic) = test_mosaic();
+    config.palette = Some("fire".to_string());
+    let (width, _) = mosaic.bounds();
+    let mut image = Vec::new();
+    mosaic.render_bands(&config, 2, |band| {
+        image.extend_from_slice(band);
+        Ok::<(), ()>(())
+    }).unwrap();
+    assert_eq!(image.len(), 60 * 40 * 3);
+
+    for &(column, row) in &[(0, 0), (2, 1), (4, 3)] {
+        let cell = render_config(&mosaic.cell_config(&config, (column, row)), 1);
+        for y in 0 .. 10 {
+            let start = ((row * 10 + y) * width + column * 12) * 3;
+            assert_eq!(&image[start .. start + 36], &cell[y * 36 .. y * 36 + 36]);
+        }
+    }
+    // Cells inside the set have connected Julia sets, with black centers;
+    // the rest hold only a little dust, if any.
+    let pixel = |x: usize, y: usize| &image[(y * width + x) * 3 .. (y * width + x) * 3 + 3];
+    assert_eq!(pixel(2 * 12 + 6, 10 + 5), [0, 0, 0]);
+    assert_ne!(pixel(6, 5), [0, 0, 0]);
+}
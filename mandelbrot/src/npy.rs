@@ -0,0 +1,232 @@
+/* NumPy Arrays
+ * ------------
+ * ".npy" is NumPy's own format for a single array, which "numpy.load" reads
+ * directly. A version 1.0 file is laid out like this:
+ *
+ *      \x93NUMPY           six bytes of magic
+ *      1, 0                the format version
+ *      HEADER_LEN          two bytes, little-endian
+ *      HEADER              a Python dict literal giving the element type,
+ *                          the order of the elements and the shape, e.g.
+ *                          {'descr': '<u4', 'fortran_order': False, 'shape': (750, 1000), }
+ *                          padded with spaces and a newline so that the data
+ *                          starts on a multiple of 64 bytes
+ *      data                the elements, row by row
+ *
+ * We write escape-time grids with the image's rows as the first axis, so
+ * "a[y, x]" is the pixel at column x of row y. Escape counts are stored as
+ * '<u4', with "NEVER" for points that didn't escape, which a notebook can hide
+ * with "numpy.ma.masked_equal(a, 0xffffffff)". Smooth escape times are
+ * stored as '<f8', with NaN for points that didn't escape.
+ */
+
+use std::io::{self, Write};
+#[cfg(test)]
+use std::io::Read;
+
+use dump::NEVER;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// The elements of an array we can read back.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum Data {
+    U32(Vec<u32>),
+    F64(Vec<f64>)
+}
+
+/// A two-dimensional array.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub struct Array {
+    /// The number of rows and columns.
+    pub shape: (usize, usize),
+    pub data: Data
+}
+
+fn write_header<W: Write>(out: &mut W, descr: &str, shape: (usize, usize))
+    -> io::Result<()>
+{
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}",
+                             descr, shape.0, shape.1);
+    // Pad so that magic, version, length and header fill a multiple of 64
+    // bytes, the last of which is a newline.
+    let unpadded = MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())
+}
+
+/// Write `counts`, an escape-time grid of width and height `bounds`, to `out`
+/// as an array of '<u4'.
+pub fn write_counts<W: Write>(out: &mut W, counts: &[Option<u32>], bounds: (usize, usize))
+    -> io::Result<()>
+{
+    assert!(counts.len() == bounds.0 * bounds.1);
+    write_header(out, "<u4", (bounds.1, bounds.0))?;
+    for count in counts {
+        out.write_all(&count.unwrap_or(NEVER).to_le_bytes())?;
+    }
+    out.flush()
+}
+
+/// Write `values`, a grid of smooth escape times of width and height `bounds`,
+/// to `out` as an array of '<f8'.
+pub fn write_smooth<W: Write>(out: &mut W, values: &[Option<f64>], bounds: (usize, usize))
+    -> io::Result<()>
+{
+    assert!(values.len() == bounds.0 * bounds.1);
+    write_header(out, "<f8", (bounds.1, bounds.0))?;
+    for value in values {
+        out.write_all(&value.unwrap_or(f64::NAN).to_le_bytes())?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Return the value of `key` in a header dict, up to the next comma outside
+/// of parentheses.
+#[cfg(test)]
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let rest = header[start ..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[.. end].trim())
+}
+
+/// Read a two-dimensional '<u4' or '<f8' array in C order from `input`.
+#[cfg(test)]
+pub fn read<R: Read>(input: &mut R) -> io::Result<Array> {
+    let mut preamble = [0; 10];
+    input.read_exact(&mut preamble)?;
+    if &preamble[.. 6] != MAGIC || preamble[6] != 1 {
+        return Err(invalid("not a version 1 .npy file"));
+    }
+    let mut header = vec![0; u16::from_le_bytes([preamble[8], preamble[9]]) as usize];
+    input.read_exact(&mut header)?;
+    let header = String::from_utf8(header).map_err(|_| invalid("header is not text"))?;
+
+    if header_value(&header, "fortran_order") != Some("False") {
+        return Err(invalid("only C-ordered arrays are supported"));
+    }
+    let shape = header_value(&header, "shape")
+        .and_then(|s| s.strip_prefix('(')?.strip_suffix(')'))
+        .and_then(|s| {
+            let mut dims = s.split(',').map(|d| d.trim()).filter(|d| !d.is_empty());
+            let rows = dims.next()?.parse().ok()?;
+            let columns = dims.next()?.parse().ok()?;
+            if dims.next().is_some() { None } else { Some((rows, columns)) }
+        })
+        .ok_or_else(|| invalid("only two-dimensional arrays are supported"))?;
+
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let (rows, columns): (usize, usize) = shape;
+    let elements = rows.checked_mul(columns).ok_or_else(|| invalid("shape is too large"))?;
+    let size = |bytes_per_element: usize| elements.checked_mul(bytes_per_element);
+
+    let data = match header_value(&header, "descr") {
+        Some("'<u4'") if size(4) == Some(bytes.len()) => {
+            Data::U32(bytes.chunks(4)
+                      .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                      .collect())
+        }
+        Some("'<f8'") if size(8) == Some(bytes.len()) => {
+            Data::F64(bytes.chunks(8)
+                      .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3],
+                                                   b[4], b[5], b[6], b[7]]))
+                      .collect())
+        }
+        Some("'<u4'") | Some("'<f8'") => return Err(invalid("wrong amount of data")),
+        _ => return Err(invalid("only '<u4' and '<f8' arrays are supported"))
+    };
+    Ok(Array { shape, data })
+}
+
+#[test]
+fn test_header_layout() {
+    let mut file = Vec::new();
+    write_counts(&mut file, &[Some(1), None, Some(3), Some(4), Some(5), Some(6)], (3, 2))
+        .unwrap();
+
+    assert_eq!(&file[.. 8], b"\x93NUMPY\x01\x00");
+    let header_len = u16::from_le_bytes([file[8], file[9]]) as usize;
+    assert_eq!((10 + header_len) % 64, 0);
+    let header = std::str::from_utf8(&file[10 .. 10 + header_len]).unwrap();
+    assert!(header.starts_with("{'descr': '<u4', 'fortran_order': False, 'shape': (2, 3), }"));
+    assert!(header.ends_with(" \n"));
+    assert_eq!(file.len(), 10 + header_len + 6 * 4);
+    assert_eq!(&file[10 + header_len + 4 .. 10 + header_len + 8], &[0xff; 4]);
+}
+
+#[test]
+fn test_round_trip() {
+    let counts = [Some(0), Some(7), None, Some(254)];
+    let mut file = Vec::new();
+    write_counts(&mut file, &counts, (2, 2)).unwrap();
+    assert_eq!(read(&mut &file[..]).unwrap(),
+               Array { shape: (2, 2), data: Data::U32(vec![0, 7, NEVER, 254]) });
+
+    let smooth = [Some(0.5), None, Some(3.25)];
+    let mut file = Vec::new();
+    write_smooth(&mut file, &smooth, (1, 3)).unwrap();
+    let array = read(&mut &file[..]).unwrap();
+    assert_eq!(array.shape, (3, 1));
+    match array.data {
+        Data::F64(values) => {
+            assert_eq!(values[0], 0.5);
+            assert!(values[1].is_nan());
+            assert_eq!(values[2], 3.25);
+        }
+        other => panic!("expected f64 data, got {:?}", other)
+    }
+
+    file.pop();
+    assert!(read(&mut &file[..]).is_err());
+}
+
+#[test]
+fn test_read_numpy_header() {
+    // A header as NumPy itself writes it, for a (2, 1) array of u4.
+    let header = "{'descr': '<u4', 'fortran_order': False, 'shape': (2, 1), }";
+    let mut file = MAGIC.to_vec();
+    file.extend_from_slice(&[1, 0]);
+    let padded = format!("{:<117}\n", header);
+    file.extend_from_slice(&(padded.len() as u16).to_le_bytes());
+    file.extend_from_slice(padded.as_bytes());
+    file.extend_from_slice(&[9, 0, 0, 0, 10, 0, 0, 0]);
+
+    assert_eq!(read(&mut &file[..]).unwrap(),
+               Array { shape: (2, 1), data: Data::U32(vec![9, 10]) });
+
+    let at = file.windows(5).position(|w| w == b"False").unwrap();
+    file[at .. at + 5].copy_from_slice(b"True ");
+    assert!(read(&mut &file[..]).is_err());
+}
+
+#[test]
+fn test_read_rejects_huge_shape() {
+    // A shape whose element count overflows is an error, not a panic.
+    for shape in ["(4294967296, 4294967296)", "(4611686018427387904, 1)"] {
+        let header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}\n",
+                             shape);
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&[1, 0]);
+        file.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        file.extend_from_slice(header.as_bytes());
+        assert_eq!(read(&mut &file[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
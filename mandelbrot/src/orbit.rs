@@ -0,0 +1,347 @@
+/* More than an Escape Time
+ * ------------------------
+ * "escape_time" throws away everything about a point's orbit except how long
+ * it took to leave the circle of radius two. Coloring schemes need a little
+ * more, so "sample" runs the same loop but also keeps:
+ *
+ *      smooth      a fractional escape time, which varies continuously
+ *                  across the plane instead of jumping by whole iterations,
+ *                  so colors don't form bands
+ *      z           where the orbit was when it escaped
+ *      distance    an estimate of the distance from the point to the set,
+ *                  from the derivative dz/dc carried along with z
+ *      normal      the direction of z / dz, away from the set, for lighting;
+ *                  see light.rs
+ *      trap        how close the orbit came to an orbit trap, if one is
+ *                  given; see trap.rs
+ *      stripe,     averages of quantities along the orbit, if asked for;
+ *      triangle    see "Orbit Averages" below
+ *
+ * The derivative obeys dz' = 2 z dz + 1, starting from zero, since each step
+ * computes z' = z^2 + c. Once z escapes, the distance to the set is roughly
+ *
+ *      2 |z| ln |z| / |dz|
+ *
+ * A Julia set fixes "c" and starts the orbit at the point being plotted
+ * instead, so "julia_sample" runs the same loop from a different start.
+ */
+
+use num::Complex;
+use trap::Trap;
+
+/// Everything we keep about the orbit of one point.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// The iteration at which the orbit escaped, as `escape_time` returns it.
+    pub count: Option<u32>,
+    /// The fractional escape time, or the limit if the orbit never escaped.
+    pub smooth: f64,
+    /// The last value of z computed.
+    pub z: Complex<f64>,
+    /// The estimated distance to the set, or zero if the orbit never escaped.
+    pub distance: f64,
+    /// The direction of z / dz as a unit complex number, or zero if the orbit
+    /// never escaped.
+    pub normal: Complex<f64>,
+    /// The least distance from the orbit to the trap, escaped or not, or
+    /// infinity if there was no trap.
+    pub trap: f64,
+    /// The stripe average, between zero and one, or zero if it wasn't asked
+    /// for or the orbit never escaped.
+    pub stripe: f64,
+    /// The triangle inequality average, likewise.
+    pub triangle: f64
+}
+
+impl Sample {
+    /// Return the sample for an orbit of which we know only `count`, the
+    /// iteration at which it escaped, if it did before `limit`.
+    pub fn from_count(count: Option<u32>, limit: u32) -> Sample {
+        Sample {
+            count,
+            smooth: count.map_or(limit as f64, |count| count as f64),
+            z: Complex { re: 0.0, im: 0.0 },
+            distance: 0.0,
+            normal: Complex { re: 0.0, im: 0.0 },
+            trap: f64::INFINITY,
+            stripe: 0.0,
+            triangle: 0.0
+        }
+    }
+}
+
+/// What to measure about an orbit beyond the basics, each of which costs a
+/// little more work per iteration. The default measures nothing extra.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Measures<'a> {
+    pub trap: Option<&'a Trap>,
+    /// The stripe density, if the stripe average is wanted.
+    pub stripe: Option<f64>,
+    pub triangle: bool
+}
+
+/// Iterate `z = z * z + c` at most `limit` times, as `escape_time` does, and
+/// return what we learned about the orbit, including whatever `measures`
+/// asks for.
+pub fn sample(c: Complex<f64>, limit: u32, measures: &Measures) -> Sample {
+    iterate(Complex { re: 0.0, im: 0.0 }, c, Complex { re: 0.0, im: 0.0 }, 1.0, limit, measures)
+}
+
+/// Like `sample`, but for the Julia set of `c`: start the orbit at `z`
+/// instead of zero. The distance estimate then comes from the derivative with
+/// respect to the starting point, which begins at one and has no "+ 1".
+pub fn julia_sample(z: Complex<f64>, c: Complex<f64>, limit: u32, measures: &Measures)
+    -> Sample
+{
+    iterate(z, c, Complex { re: 1.0, im: 0.0 }, 0.0, limit, measures)
+}
+
+/* Orbit Averages
+ * --------------
+ * Stripe average and triangle inequality average coloring both take the mean
+ * of some quantity over every step of the orbit:
+ *
+ *      stripe      (sin(density * arg z) + 1) / 2, which sweeps through
+ *                  "density" stripes as z goes once around the origin
+ *      triangle    where |z^2 + c| falls between the least and greatest
+ *                  values the triangle inequality allows, ||z^2| - |c|| and
+ *                  |z^2| + |c|, as a fraction of the way from one to the other
+ *
+ * Each mean jumps wherever the number of terms does. To hide the seam, we
+ * blend between the mean without the last term and the mean with it, using
+ * how far past the bailout radius the last step carried z: just inside the
+ * seam that gives the full mean, and just outside it, where there's one
+ * more term, the mean without it, which is the same thing. The terms near
+ * the escape radius of two are still far from their limits, so the blend
+ * leaves faint seams there; instead we keep iterating, for the averages
+ * only, until z passes AVERAGE_BAILOUT, where the orbit has settled down.
+ *
+ * Not every escaped orbit gets that far: a Julia set's "c" can have fixed
+ * points and cycles outside the circle of radius two, like 3 for c = -6,
+ * which an orbit may land on and never leave. So the extra steps stop after
+ * AVERAGE_STEPS, and such orbits get the full mean, unblended.
+ */
+
+/// How far the orbit runs, once it escapes, before the averages stop.
+const AVERAGE_BAILOUT: f64 = 1000.0;
+
+/// The most steps the orbit runs past its escape to reach AVERAGE_BAILOUT.
+/// From radius two, even the slowest escaping orbit gets there in a few
+/// dozen.
+const AVERAGE_STEPS: u32 = 64;
+
+/// A running mean of some quantity along an orbit.
+#[derive(Default)]
+struct Average {
+    sum: f64,
+    last: f64,
+    terms: u32
+}
+
+impl Average {
+    fn add(&mut self, term: f64) {
+        self.sum += term;
+        self.last = term;
+        self.terms += 1;
+    }
+
+    /// Return the mean blended with the mean without its last term, giving
+    /// `fraction` of the weight to the full mean.
+    fn blend(&self, fraction: f64) -> f64 {
+        if self.terms == 0 {
+            return 0.0;
+        }
+        let full = self.sum / self.terms as f64;
+        if self.terms == 1 {
+            return full;
+        }
+        let before = (self.sum - self.last) / (self.terms - 1) as f64;
+        before + (full - before) * fraction
+    }
+}
+
+/// Return `z` scaled to length one, or zero if it has no direction.
+fn unit(z: Complex<f64>) -> Complex<f64> {
+    let length = z.norm();
+    if length > 0.0 && length.is_finite() {
+        z / length
+    } else {
+        Complex { re: 0.0, im: 0.0 }
+    }
+}
+
+fn iterate(mut z: Complex<f64>,
+           c: Complex<f64>,
+           mut dz: Complex<f64>,
+           dz_step: f64,
+           limit: u32,
+           measures: &Measures)
+    -> Sample
+{
+    let mut nearest = f64::INFINITY;
+    let (mut stripe, mut triangle) = (Average::default(), Average::default());
+    let c_modulus = c.norm();
+    let mut average = |z: Complex<f64>, squared: Complex<f64>| {
+        if let Some(density) = measures.stripe {
+            stripe.add(0.5 * (density * z.arg()).sin() + 0.5);
+        }
+        if measures.triangle {
+            let (low, high) = ((squared.norm() - c_modulus).abs(), squared.norm() + c_modulus);
+            if high > low {
+                triangle.add((z.norm() - low) / (high - low));
+            }
+        }
+    };
+    for i in 0 .. limit {
+        dz = z * dz * 2.0 + dz_step;
+        let squared = z * z;
+        z = squared + c;
+        if let Some(trap) = measures.trap {
+            nearest = nearest.min(trap.distance(z));
+        }
+        average(z, squared);
+        if z.norm_sqr() > 4.0 {
+            let modulus = z.norm();
+            let mut sample = Sample {
+                count: Some(i),
+                smooth: i as f64 + 1.0 - modulus.log2().log2(),
+                z,
+                distance: 2.0 * modulus * modulus.ln() / dz.norm(),
+                normal: unit(z / dz),
+                trap: nearest,
+                stripe: 0.0,
+                triangle: 0.0
+            };
+            if measures.stripe.is_some() || measures.triangle {
+                let mut last = z;
+                let mut steps = 0;
+                while last.norm() <= AVERAGE_BAILOUT && steps < AVERAGE_STEPS {
+                    let squared = last * last;
+                    last = squared + c;
+                    average(last, squared);
+                    steps += 1;
+                }
+                // Zero just past the bailout, one where the last step only
+                // just reached it, or didn't reach it at all.
+                let fraction = if last.norm() <= AVERAGE_BAILOUT {
+                    1.0
+                } else {
+                    1.0 - (last.norm().ln() / AVERAGE_BAILOUT.ln()).log2()
+                };
+                let fraction = fraction.clamp(0.0, 1.0);
+                sample.stripe = stripe.blend(fraction);
+                sample.triangle = triangle.blend(fraction);
+            }
+            return sample;
+        }
+    }
+    Sample {
+        count: None, smooth: limit as f64, z, distance: 0.0,
+        normal: Complex { re: 0.0, im: 0.0 }, trap: nearest, stripe: 0.0, triangle: 0.0
+    }
+}
+
+#[test]
+fn test_sample_agrees_with_escape_time() {
+    for y in 0 .. 40 {
+        for x in 0 .. 60 {
+            let c = Complex { re: -2.2 + x as f64 * 0.05, im: 1.2 - y as f64 * 0.06 };
+            let s = sample(c, 100, &Measures::default());
+            assert_eq!(s.count, ::escape_time(c, 100));
+            match s.count {
+                Some(count) => {
+                    assert!(s.smooth > count as f64 - 1.0 && s.smooth <= count as f64 + 1.0);
+                    assert!(s.distance > 0.0);
+                }
+                None => assert_eq!(s.smooth, 100.0)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sample_distance_estimate() {
+    // The set's rightmost point is 0.25, so the distance from 1.0 is 0.75.
+    // The estimate is within a factor of four of the true distance.
+    let s = sample(Complex { re: 1.0, im: 0.0 }, 100, &Measures::default());
+    assert!(s.distance > 0.75 / 4.0 && s.distance < 0.75 * 4.0, "{}", s.distance);
+}
+
+#[test]
+fn test_sample_normal() {
+    // On the real axis, either side of the set, the way out is along it.
+    let none = Measures::default();
+    let right = sample(Complex { re: 1.0, im: 0.0 }, 100, &none).normal;
+    let left = sample(Complex { re: -2.5, im: 0.0 }, 100, &none).normal;
+    assert!((right - Complex { re: 1.0, im: 0.0 }).norm() < 1e-12, "{}", right);
+    assert!((left - Complex { re: -1.0, im: 0.0 }).norm() < 1e-12, "{}", left);
+    assert_eq!(sample(Complex { re: -1.0, im: 0.0 }, 100, &none).normal.norm(), 0.0);
+}
+
+#[test]
+fn test_julia_sample() {
+    // With c = 0 the Julia set is the unit disk: points inside never escape,
+    // and the distance from 2 to the circle is estimated within a factor of
+    // four.
+    let (zero, none) = (Complex { re: 0.0, im: 0.0 }, Measures::default());
+    assert_eq!(julia_sample(Complex { re: 0.5, im: 0.5 }, zero, 100, &none).count, None);
+    let outside = julia_sample(Complex { re: 2.0, im: 0.0 }, zero, 100, &none);
+    assert_eq!(outside.count, Some(0));
+    assert!(outside.distance > 0.25 && outside.distance < 4.0, "{}", outside.distance);
+
+    // Starting at zero, the Julia orbit of c is the Mandelbrot orbit.
+    let c = Complex { re: -0.8, im: 0.156 };
+    assert_eq!(julia_sample(zero, c, 200, &none).count, sample(c, 200, &none).count);
+}
+
+#[test]
+fn test_sample_trap() {
+    // The orbit of -1 cycles 0, -1, 0, -1, ..., passing through the origin.
+    let minus_one = Complex { re: -1.0, im: 0.0 };
+    let origin = Trap::Point(Complex { re: 0.0, im: 0.0 });
+    let measure = |trap| Measures { trap: Some(trap), ..Measures::default() };
+    let s = sample(minus_one, 50, &measure(&origin));
+    assert_eq!(s.count, None);
+    assert_eq!(s.trap, 0.0);
+    let circle = Trap::Circle(Complex { re: 0.0, im: 0.0 }, 0.25);
+    assert_eq!(sample(minus_one, 50, &measure(&circle)).trap, 0.25);
+    assert_eq!(sample(minus_one, 50, &Measures::default()).trap, f64::INFINITY);
+
+    // Escaping orbits are measured up to the point they escape.
+    let s = sample(Complex { re: 1.0, im: 0.0 }, 50, &measure(&origin));
+    assert_eq!((s.count, s.trap), (Some(2), 1.0));
+}
+
+#[test]
+fn test_orbit_averages() {
+    let measures = Measures { stripe: Some(5.0), triangle: true, ..Measures::default() };
+    let s = sample(Complex { re: 0.4, im: 0.5 }, 100, &measures);
+    assert!(s.count.is_some());
+    assert!((0.0 ..= 1.0).contains(&s.stripe) && (0.0 ..= 1.0).contains(&s.triangle));
+
+    // With no stripes, every term is one half.
+    let flat = Measures { stripe: Some(0.0), ..Measures::default() };
+    assert_eq!(sample(Complex { re: 0.4, im: 0.5 }, 100, &flat).stripe, 0.5);
+
+    // Blending hides the seams where the number of terms jumps: along a line
+    // of points close enough together that the averages barely change
+    // between neighbors, they don't change much at a seam either.
+    let mut previous: Option<Sample> = None;
+    for i in 0 .. 4000 {
+        let c = Complex { re: -0.75, im: 0.1 + i as f64 * 1e-6 };
+        let s = sample(c, 1000, &measures);
+        if let Some(p) = previous {
+            assert!((p.stripe - s.stripe).abs() < 0.01, "stripe at {}", c);
+            assert!((p.triangle - s.triangle).abs() < 0.01, "triangle at {}", c);
+        }
+        previous = Some(s);
+    }
+
+    // An orbit stuck on the fixed point 3 of c = -6 escapes, but never
+    // reaches the averages' bailout; every term is the same.
+    let s = julia_sample(Complex { re: 3.0, im: 0.0 }, Complex { re: -6.0, im: 0.0 }, 100,
+                         &measures);
+    assert_eq!(s.count, Some(0));
+    assert_eq!(s.stripe, 0.5);
+    assert_eq!(s.triangle, 0.0);
+}
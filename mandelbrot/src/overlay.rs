@@ -0,0 +1,379 @@
+/* Overlays
+ * --------
+ * For slides and papers, an image can show where on the plane it is.
+ * "--overlay" and "image.overlay" take a comma-separated list of what to
+ * draw over the render:
+ *
+ *      axes    the real and imaginary axes, where they cross the image
+ *      ticks   tick marks along the axes, labeled with their coordinates;
+ *              when the view isn't turned, an axis out of view has its
+ *              ticks along the nearest edge of the image instead
+ *      grid    faint lines across the image through every tick
+ *
+ * and "--title" and "image.title" set a line of text across the top. All of
+ * it is white, the text outlined in black so that it stands out on any
+ * background, in the font from font.rs, scaled up on larger images.
+ *
+ * Everything is placed with "View::point_to_position", the inverse of
+ * "pixel_to_point", so the axes and grid follow the view wherever it's
+ * centered and however it's turned. Ticks fall on round numbers: the step
+ * between them is one, two or five times a power of ten, the smallest that
+ * leaves room between the labels.
+ *
+ * The renderer draws the overlay on each band of rows as it finishes them,
+ * so "draw" plots only what falls within the band it's given.
+ */
+
+use config::Config;
+use font;
+use num::Complex;
+
+/// Which overlays to draw.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Overlay {
+    pub axes: bool,
+    pub ticks: bool,
+    pub grid: bool
+}
+
+impl Overlay {
+    /// Parse a list of overlays written as described above.
+    pub fn parse(s: &str) -> Option<Overlay> {
+        let mut overlay = Overlay::default();
+        for name in s.split(',') {
+            match name.trim() {
+                "axes"  => overlay.axes = true,
+                "ticks" => overlay.ticks = true,
+                "grid"  => overlay.grid = true,
+                _       => return None
+            }
+        }
+        Some(overlay)
+    }
+}
+
+/// How long tick marks reach out from their axis, and how far past that
+/// their labels sit, in pixels before scaling.
+const TICK_LENGTH: f64 = 4.0;
+const LABEL_GAP: f64 = 2.0;
+
+/// How opaque grid lines are; the axes and text are solid.
+const GRID_ALPHA: f64 = 0.35;
+
+/// A position in the image, as a column and row that may be fractional.
+type Position = (f64, f64);
+
+/// A band of rows of an image, to draw on.
+struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    bounds: (usize, usize),
+    /// The image row the band starts at, and the number of rows it holds.
+    top: usize,
+    rows: usize,
+    channels: usize,
+    wide: bool
+}
+
+impl<'a> Canvas<'a> {
+    /// Blend the pixel at `(x, y)` of the whole image, if it's in the band,
+    /// toward `level`, from 0 for black to 1 for white, by `alpha`.
+    fn plot(&mut self, x: isize, y: isize, level: f64, alpha: f64) {
+        if x < 0 || y < self.top as isize || x >= self.bounds.0 as isize ||
+            y >= (self.top + self.rows) as isize
+        {
+            return;
+        }
+        let max = if self.wide { 65535.0 } else { 255.0 };
+        let pixel = ((y as usize - self.top) * self.bounds.0 + x as usize) * self.channels;
+        for k in pixel .. pixel + self.channels {
+            let blend = |old: f64| (old * (1.0 - alpha) + level * max * alpha).round();
+            if self.wide {
+                let old = u16::from_be_bytes([self.pixels[k * 2], self.pixels[k * 2 + 1]]);
+                let new = blend(old as f64) as u16;
+                self.pixels[k * 2 .. k * 2 + 2].copy_from_slice(&new.to_be_bytes());
+            } else {
+                self.pixels[k] = blend(self.pixels[k] as f64) as u8;
+            }
+        }
+    }
+
+    /// Draw a white line from `a` to `b`, blended in by `alpha`.
+    fn line(&mut self, a: Position, b: Position, alpha: f64) {
+        // Each position is drawn in the pixel whose sample point is nearest.
+        let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).ceil().max(1.0);
+        for i in 0 ..= steps as usize {
+            let t = i as f64 / steps;
+            self.plot((a.0 + (b.0 - a.0) * t).round() as isize,
+                      (a.1 + (b.1 - a.1) * t).round() as isize, 1.0, alpha);
+        }
+    }
+
+    /// Write `text` in white outlined in black, at `scale`, with its
+    /// upper-left corner at `(x, y)`.
+    fn text(&mut self, text: &str, x: isize, y: isize, scale: usize) {
+        let reach = scale as isize;
+        font::each_pixel(text, scale, |dx, dy| {
+            for oy in -reach ..= reach {
+                for ox in -reach ..= reach {
+                    self.plot(x + dx as isize + ox, y + dy as isize + oy, 0.0, 1.0);
+                }
+            }
+        });
+        font::each_pixel(text, scale, |dx, dy| {
+            self.plot(x + dx as isize, y + dy as isize, 1.0, 1.0)
+        });
+    }
+}
+
+/// Return the position in `config`'s image, as a column and row that may be
+/// fractional, that shows `point`.
+fn position(config: &Config, point: Complex<f64>) -> Position {
+    // The view places points in the image its corners are fitted to; if the
+    // corners are a different shape, stretch to match.
+    let (width, height) = (config.bounds.0 as f64, config.bounds.1 as f64);
+    let view = config.view();
+    let (upper_left, lower_right) = view.corners(config.bounds);
+    let stretch = ((lower_right.re - upper_left.re) /
+                   (config.lower_right.re - config.upper_left.re),
+                   (upper_left.im - lower_right.im) /
+                   (config.upper_left.im - config.lower_right.im));
+    let (x, y) = view.point_to_position(config.bounds, point);
+    (width / 2.0 + (x - width / 2.0) * stretch.0, height / 2.0 + (y - height / 2.0) * stretch.1)
+}
+
+/// Return the step between ticks for `config`'s image, and the number of
+/// decimal places its labels need: the smallest round step that leaves room
+/// for labels `scale` times the font's size.
+fn tick_step(config: &Config, low: f64, high: f64, scale: usize) -> (f64, usize) {
+    let origin = config.pixel_to_point(config.bounds, (0, 0));
+    let pixel = (config.pixel_to_point(config.bounds, (1, 0)) - origin).norm()
+        .max((config.pixel_to_point(config.bounds, (0, 1)) - origin).norm());
+
+    // Start with room for a short label, and widen until the longest fits.
+    let mut room = 4;
+    loop {
+        let (gap, _) = font::text_size(&"0".repeat(room + 2), scale);
+        let wanted = gap as f64 * pixel;
+        let power = 10f64.powf(wanted.log10().floor());
+        let step = [1.0, 2.0, 5.0, 10.0].iter().map(|m| m * power).find(|&s| s >= wanted)
+            .unwrap();
+        let decimals = (-step.log10().floor()).max(0.0) as usize;
+        let longest = [low, high].iter().map(|x| label(*x, step, decimals).len()).max().unwrap();
+        if longest <= room || room > 24 {
+            return (step, decimals);
+        }
+        room = longest;
+    }
+}
+
+/// Return the label for the tick at `x`, `step` from its neighbors.
+fn label(x: f64, step: f64, decimals: usize) -> String {
+    if (x / step).round() == 0.0 {
+        return "0".to_string();
+    }
+    format!("{:.*}", decimals, x)
+}
+
+/// Draw the overlays and title `config` asks for on `pixels`, which holds
+/// whole rows of its rendered image starting at row `top`.
+pub fn draw(config: &Config, pixels: &mut [u8], top: usize) {
+    let overlay = config.overlay.as_ref()
+        .map(|spec| Overlay::parse(spec).expect("invalid overlay in configuration"))
+        .unwrap_or_default();
+    if overlay == Overlay::default() && config.title.is_none() {
+        return;
+    }
+    let (width, height) = config.bounds;
+    let mut canvas = Canvas {
+        rows: pixels.len() / (width * config.pixel_bytes()).max(1),
+        pixels,
+        bounds: config.bounds,
+        top,
+        channels: config.channels(),
+        wide: config.depth == 16
+    };
+    let scale = 1 + width.min(height) / 600;
+    let position = |re: f64, im: f64| position(config, Complex { re, im });
+
+    // The part of the plane the image shows, turned or not, lies within
+    // these bounds.
+    let corners = [(0, 0), (width, 0), (0, height), (width, height)]
+        .map(|pixel| config.pixel_to_point(config.bounds, pixel));
+    let low = corners.iter()
+        .fold(corners[0], |a, c| Complex { re: a.re.min(c.re), im: a.im.min(c.im) });
+    let high = corners.iter()
+        .fold(corners[0], |a, c| Complex { re: a.re.max(c.re), im: a.im.max(c.im) });
+
+    let (step, decimals) = tick_step(config, low.re.min(low.im), high.re.max(high.im), scale);
+    let ticks = |low: f64, high: f64| {
+        ((low / step).ceil() as i64 ..= (high / step).floor() as i64).map(|k| k as f64 * step)
+    };
+
+    if overlay.grid {
+        for re in ticks(low.re, high.re) {
+            canvas.line(position(re, low.im), position(re, high.im), GRID_ALPHA);
+        }
+        for im in ticks(low.im, high.im) {
+            canvas.line(position(low.re, im), position(high.re, im), GRID_ALPHA);
+        }
+    }
+    if overlay.axes {
+        if low.im <= 0.0 && 0.0 <= high.im {
+            canvas.line(position(low.re, 0.0), position(high.re, 0.0), 1.0);
+        }
+        if low.re <= 0.0 && 0.0 <= high.re {
+            canvas.line(position(0.0, low.im), position(0.0, high.im), 1.0);
+        }
+    }
+    if overlay.ticks {
+        // Along each axis, the unit direction it runs in the image, and the
+        // one its ticks and labels reach out in: down from the real axis,
+        // and right from the imaginary one, unless the view is turned.
+        let axes = [(ticks(low.re, high.re).collect::<Vec<_>>(), Complex { re: 1.0, im: 0.0 }),
+                    (ticks(low.im, high.im).collect(), Complex { re: 0.0, im: 1.0 })];
+        for (values, unit) in axes.iter() {
+            let origin = position(0.0, 0.0);
+            let along = position(unit.re, unit.im);
+            let (dx, dy) = (along.0 - origin.0, along.1 - origin.1);
+            let length = (dx * dx + dy * dy).sqrt();
+            let direction = (dx / length, dy / length);
+            let normal = (-direction.1, direction.0);
+
+            for &value in values {
+                if unit.im != 0.0 && value == 0.0 {
+                    // The real axis labels the origin.
+                    continue;
+                }
+                let point = position(value * unit.re, value * unit.im);
+                let anchor = (point.0.clamp(0.0, (width - 1) as f64),
+                              point.1.clamp(0.0, (height - 1) as f64));
+                let moved = (anchor.0 - point.0) * direction.0 + (anchor.1 - point.1) * direction.1;
+                if moved.abs() > 0.5 {
+                    // Clamping slid it along the axis: it's off the image.
+                    continue;
+                }
+
+                let mut text = label(value, step, decimals);
+                if unit.im != 0.0 {
+                    text.push('i');
+                }
+                let (text_width, text_height) = font::text_size(&text, scale);
+                let reach = TICK_LENGTH * scale as f64;
+                let extent = (normal.0.abs() * text_width as f64 +
+                              normal.1.abs() * text_height as f64) / 2.0;
+                let distance = reach + LABEL_GAP * scale as f64 + extent;
+                // Labels that would leave the image go on the other side.
+                let center = |side: f64| (anchor.0 + normal.0 * distance * side,
+                                          anchor.1 + normal.1 * distance * side);
+                let (cx, cy) = center(1.0);
+                let (half_width, half_height) = (text_width as f64 / 2.0, text_height as f64 / 2.0);
+                let outside = cx < half_width || cx + half_width > width as f64 ||
+                    cy < half_height || cy + half_height > height as f64;
+                let side = if outside { -1.0 } else { 1.0 };
+
+                canvas.line((anchor.0 - normal.0 * reach, anchor.1 - normal.1 * reach),
+                            (anchor.0 + normal.0 * reach, anchor.1 + normal.1 * reach), 1.0);
+                // Labels by the corners slide back inside.
+                let (cx, cy) = center(side);
+                let left = (cx - text_width as f64 / 2.0)
+                    .min(width.saturating_sub(text_width) as f64).max(0.0);
+                let top = (cy - text_height as f64 / 2.0)
+                    .min(height.saturating_sub(text_height) as f64).max(0.0);
+                canvas.text(&text, left.round() as isize, top.round() as isize, scale);
+            }
+        }
+    }
+    if let Some(ref title) = config.title {
+        let scale = scale * 2;
+        let (text_width, _) = font::text_size(title, scale);
+        let x = (width as isize - text_width as isize) / 2;
+        canvas.text(title, x, (font::GLYPH_HEIGHT * scale / 2) as isize, scale);
+    }
+}
+
+#[cfg(test)]
+fn test_config() -> Config {
+    Config::new((200, 100), Complex { re: -2.0, im: 1.0 }, Complex { re: 2.0, im: -1.0 },
+                "unused.png")
+}
+
+#[test]
+fn test_overlay_parse() {
+    assert_eq!(Overlay::parse("axes"), Some(Overlay { axes: true, ticks: false, grid: false }));
+    assert_eq!(Overlay::parse("grid, ticks,axes"),
+               Some(Overlay { axes: true, ticks: true, grid: true }));
+    for bad in ["", "axes,", "labels"] {
+        assert_eq!(Overlay::parse(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn test_tick_step() {
+    let config = test_config();
+    // Pixels are 0.02 apart, and labels like "-1.5" need about thirty.
+    assert_eq!(tick_step(&config, -2.0, 2.0, 1), (1.0, 0));
+    assert_eq!(label(-1.0, 1.0, 0), "-1");
+    assert_eq!(label(1e-17, 0.2, 1), "0");
+    assert_eq!(label(-0.4, 0.2, 1), "-0.4");
+
+    let zoomed = Config::new((200, 100), Complex { re: -0.7502, im: 0.1001 },
+                             Complex { re: -0.7498, im: 0.0999 }, "unused.png");
+    let (step, decimals) = tick_step(&zoomed, -0.7502, 0.1001, 1);
+    assert_eq!(decimals, 4);
+    assert!(step == 1e-4 || step == 2e-4 || step == 5e-4, "{}", step);
+}
+
+#[test]
+fn test_position() {
+    // Positions map back to the pixels that show them, however the view is
+    // stretched and turned.
+    let mut config = test_config();
+    config.lower_right = Complex { re: 1.0, im: -0.5 };
+    for &rotation in &[0.0, 30.0, -135.0] {
+        config.rotation = rotation;
+        for &pixel in &[(0, 0), (13, 7), (200, 100)] {
+            let (x, y) = position(&config, config.pixel_to_point(config.bounds, pixel));
+            assert!((x - pixel.0 as f64).abs() < 1e-9 && (y - pixel.1 as f64).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_draw_axes_and_grid() {
+    // On a black image, the axes cross at the center, solid white, and the
+    // grid lines are fainter.
+    let mut config = test_config();
+    config.overlay = Some("axes,grid".to_string());
+    let mut pixels = vec![0; 200 * 100];
+    draw(&config, &mut pixels, 0);
+    let at = |x: usize, y: usize| pixels[y * 200 + x];
+    assert!((0 .. 200).all(|x| at(x, 50) == 255));
+    assert!((0 .. 100).all(|y| at(100, y) == 255));
+    let faint = (GRID_ALPHA * 255.0).round() as u8;
+    assert!((1 .. 100).filter(|&y| y != 50).all(|y| at(150, y) == faint));
+    assert_eq!(at(125, 25), 0);
+
+    // Drawing in bands comes out the same.
+    let mut banded = vec![0; 200 * 100];
+    for (i, band) in banded.chunks_mut(200 * 30).enumerate() {
+        draw(&config, band, i * 30);
+    }
+    assert_eq!(banded, pixels);
+}
+
+#[test]
+fn test_draw_ticks_and_title() {
+    let mut config = test_config();
+    config.overlay = Some("ticks".to_string());
+    config.title = Some("Hi".to_string());
+    config.palette = Some("fire".to_string());
+    let mut pixels = vec![0; 200 * 100 * 3];
+    draw(&config, &mut pixels, 0);
+    let white = |x: usize, y: usize| pixels[(y * 200 + x) * 3 .. (y * 200 + x) * 3 + 3] == [255; 3];
+
+    // A tick mark crosses the real axis at 1, with its label below it.
+    assert!((46 .. 55).all(|y| white(150, y)));
+    assert!((150 - 3 .. 150 + 3).any(|x| (56 .. 64).any(|y| white(x, y))));
+    // The title sits at the top, centered.
+    assert!((3 .. 17).any(|y| white(100 - 10, y)) && !white(5, 5));
+}
@@ -0,0 +1,323 @@
+/* Palettes
+ * --------
+ * A palette turns a number between 0 and 1 into a color, by blending between
+ * a list of colors spaced evenly along the way. On the command line a palette
+ * is either one of the names in "NAMED", or a comma-separated list of at least
+ * two colors in hexadecimal:
+ *
+ *      000764,206bcb,edffff,ffaa00,000200
+ *
+ * Either may be preceded by the color space to blend in, and a colon, as in
+ * "oklab:ultra":
+ *
+ *      srgb        the sRGB values themselves; the default
+ *      linear      light intensities, undoing sRGB's gamma curve
+ *      oklab       Björn Ottosson's Oklab, in which equal steps look about
+ *                  equally different
+ *      oklch       Oklab as lightness, chroma and hue, blending the hue the
+ *                  short way around the color wheel
+ *
+ * Blending in sRGB is what the program always did, but halfway between two
+ * saturated colors it gives a dim, muddy one; halfway from red to blue is a
+ * dark purple. The other spaces keep the middle of a gradient as bright and,
+ * for oklch, as colorful as its ends.
+ */
+
+/// A color, as red, green and blue intensities.
+pub type Rgb = [u8; 3];
+
+/// The palettes that can be asked for by name.
+pub const NAMED: &[(&str, &str)] = &[
+    ("gray",   "000000,ffffff"),
+    ("ultra",  "000764,206bcb,edffff,ffaa00,000200"),
+    ("fire",   "000000,800000,ff4000,ffc000,ffffff"),
+    ("ocean",  "000010,003366,0099cc,99ffff,ffffff")
+];
+
+/// A color space to blend a palette's colors in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Space {
+    Srgb,
+    Linear,
+    Oklab,
+    Oklch
+}
+
+/// A gradient through a list of colors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    space: Space,
+    /// The colors as coordinates in `space`, where they're blended.
+    points: Vec<[f64; 3]>
+}
+
+/// Parse a color written as six hexadecimal digits, like `"ffaa00"`.
+pub fn parse_rgb(s: &str) -> Option<Rgb> {
+    if s.len() != 6 || !s.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&s[i .. i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/* Color Spaces
+ * ------------
+ * sRGB values are not proportional to light: each is a gamma-encoded
+ * intensity, which the standard's piecewise curve turns into a linear one.
+ * Oklab starts from linear light, mixes it into three cone responses, takes
+ * their cube roots, and mixes those into lightness "L" and two opponent
+ * color axes, "a" (green to red) and "b" (blue to yellow). Oklch writes a
+ * and b in polar form, as chroma and hue in degrees. The matrices are
+ * Ottosson's, from https://bottosson.github.io/posts/oklab/.
+ */
+
+/// Convert a gamma-encoded sRGB intensity between 0 and 1 to linear light.
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Convert a linear light intensity between 0 and 1 to gamma-encoded sRGB.
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn linear_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+     1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+     0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s]
+}
+
+fn oklab_to_linear([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [ 4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+     -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+     -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s]
+}
+
+fn oklab_to_oklch([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    [lightness, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.0)]
+}
+
+fn oklch_to_oklab([lightness, chroma, hue]: [f64; 3]) -> [f64; 3] {
+    let (sin, cos) = hue.to_radians().sin_cos();
+    [lightness, chroma * cos, chroma * sin]
+}
+
+/// Below this chroma, a color is gray, and its hue is only rounding error.
+const GRAY_CHROMA: f64 = 1e-4;
+
+impl Space {
+    pub fn parse(s: &str) -> Option<Space> {
+        match s {
+            "srgb"   => Some(Space::Srgb),
+            "linear" => Some(Space::Linear),
+            "oklab"  => Some(Space::Oklab),
+            "oklch"  => Some(Space::Oklch),
+            _        => None
+        }
+    }
+
+    /// Return the coordinates of `rgb` in this space.
+    fn coordinates(&self, rgb: Rgb) -> [f64; 3] {
+        let linear = || rgb.map(|c| srgb_to_linear(c as f64 / 255.0));
+        match *self {
+            Space::Srgb   => rgb.map(|c| c as f64),
+            Space::Linear => linear(),
+            Space::Oklab  => linear_to_oklab(linear()),
+            Space::Oklch  => oklab_to_oklch(linear_to_oklab(linear()))
+        }
+    }
+
+    /// Return the sRGB intensities, from 0 to 255, of the point `p` in this
+    /// space. Points outside what sRGB can show are clipped to it.
+    fn rgb(&self, p: [f64; 3]) -> [f64; 3] {
+        let linear = match *self {
+            Space::Srgb   => return p,
+            Space::Linear => p,
+            Space::Oklab  => oklab_to_linear(p),
+            Space::Oklch  => oklab_to_linear(oklch_to_oklab(p))
+        };
+        linear.map(|c| linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0)
+    }
+
+    /// Return the point `fraction` of the way from `a` to `b`.
+    fn blend(&self, a: [f64; 3], b: [f64; 3], fraction: f64) -> [f64; 3] {
+        let mut p = [0.0; 3];
+        for i in 0 .. 3 {
+            p[i] = a[i] + (b[i] - a[i]) * fraction;
+        }
+        if *self == Space::Oklch {
+            // Go around the hue circle the short way. A gray has no hue to
+            // speak of, so take the other color's.
+            let (mut from, mut to) = (a[2], b[2]);
+            if a[1] < GRAY_CHROMA {
+                from = to;
+            } else if b[1] < GRAY_CHROMA {
+                to = from;
+            }
+            let turn = (to - from + 180.0).rem_euclid(360.0) - 180.0;
+            p[2] = (from + turn * fraction).rem_euclid(360.0);
+        }
+        p
+    }
+}
+
+impl Palette {
+    /// Return a palette blending through `colors`, which must hold at least
+    /// two colors, in `space`.
+    pub fn new(colors: Vec<Rgb>, space: Space) -> Option<Palette> {
+        if colors.len() < 2 {
+            return None;
+        }
+        let points = colors.iter().map(|&rgb| space.coordinates(rgb)).collect();
+        Some(Palette { space, points })
+    }
+
+    /// Parse a palette name or a list of colors, either perhaps preceded by
+    /// a color space, as described above.
+    pub fn parse(s: &str) -> Option<Palette> {
+        let (space, s) = match s.split_once(':') {
+            Some((space, s)) => (Space::parse(space.trim())?, s.trim()),
+            None => (Space::Srgb, s)
+        };
+        let spec = NAMED.iter()
+            .find(|&&(name, _)| name == s)
+            .map(|&(_, colors)| colors)
+            .unwrap_or(s);
+        let colors: Option<Vec<Rgb>> = spec.split(',').map(|c| parse_rgb(c.trim())).collect();
+        Palette::new(colors?, space)
+    }
+
+    /// Return the color at position `t` along the palette, where 0 is the
+    /// first color and 1 the last. Values outside that range are clamped.
+    pub fn color(&self, t: f64) -> Rgb {
+        self.mix(t).map(|c| c.round() as u8)
+    }
+
+    /// Like `color`, but without rounding the blend to whole intensities, for
+    /// images with more than eight bits per sample.
+    pub fn mix(&self, t: f64) -> [f64; 3] {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let scaled = t * (self.points.len() - 1) as f64;
+        let index = (scaled.floor() as usize).min(self.points.len() - 2);
+        let fraction = scaled - index as f64;
+
+        let p = self.space.blend(self.points[index], self.points[index + 1], fraction);
+        self.space.rgb(p)
+    }
+}
+
+/// Return the position along a palette at which to color a point that
+/// escaped after `time` iterations, out of at most `limit`. If `cycle` is
+/// given, the palette repeats every `cycle` iterations; otherwise it
+/// stretches once from zero to the limit.
+pub fn position(time: f64, limit: u32, cycle: Option<f64>) -> f64 {
+    let time = time.max(0.0);
+    match cycle {
+        Some(cycle) => (time / cycle).fract(),
+        None => time / limit.max(1) as f64
+    }
+}
+
+#[test]
+fn test_parse_rgb() {
+    assert_eq!(parse_rgb("ffaa00"), Some([255, 170, 0]));
+    assert_eq!(parse_rgb("FFAA0"), None);
+    assert_eq!(parse_rgb("ffaa0g"), None);
+    assert_eq!(parse_rgb("ffaa00ff"), None);
+}
+
+#[test]
+fn test_palette_parse() {
+    assert_eq!(Palette::parse("gray"),
+               Palette::new(vec![[0, 0, 0], [255, 255, 255]], Space::Srgb));
+    assert_eq!(Palette::parse("ff0000, 0000ff"),
+               Palette::new(vec![[255, 0, 0], [0, 0, 255]], Space::Srgb));
+    assert!(Palette::parse("ultra").is_some());
+    assert_eq!(Palette::parse("ff0000"), None);
+    assert_eq!(Palette::parse("sunset"), None);
+    assert_eq!(Palette::parse("oklab:gray"),
+               Palette::new(vec![[0, 0, 0], [255, 255, 255]], Space::Oklab));
+    assert_eq!(Palette::parse("linear: ff0000,0000ff"),
+               Palette::new(vec![[255, 0, 0], [0, 0, 255]], Space::Linear));
+    assert_eq!(Palette::parse("hsv:gray"), None);
+    assert_eq!(Palette::parse("oklch:"), None);
+}
+
+#[test]
+fn test_palette_color() {
+    let palette = Palette::parse("000000,ff0000,ffffff").unwrap();
+    assert_eq!(palette.color(0.0), [0, 0, 0]);
+    assert_eq!(palette.color(0.25), [128, 0, 0]);
+    assert_eq!(palette.color(0.5), [255, 0, 0]);
+    assert_eq!(palette.color(0.75), [255, 128, 128]);
+    assert_eq!(palette.color(1.0), [255, 255, 255]);
+    assert_eq!(palette.color(7.0), [255, 255, 255]);
+    assert_eq!(palette.color(-1.0), [0, 0, 0]);
+    assert_eq!(palette.mix(0.25), [127.5, 0.0, 0.0]);
+}
+
+#[test]
+fn test_position() {
+    assert_eq!(position(50.0, 200, None), 0.25);
+    assert_eq!(position(50.0, 200, Some(16.0)), 0.125);
+    assert_eq!(position(-0.5, 200, Some(16.0)), 0.0);
+}
+
+#[test]
+fn test_color_space_conversions() {
+    let close = |a: [f64; 3], b: [f64; 3]| (0 .. 3).all(|i| (a[i] - b[i]).abs() < 1e-5);
+
+    // sRGB's curve, at its midpoint and where its two pieces meet.
+    assert!((srgb_to_linear(0.5) - 0.2140411).abs() < 1e-7);
+    assert!((srgb_to_linear(0.04045) - 0.0031308).abs() < 1e-7);
+    assert!((linear_to_srgb(0.2140411) - 0.5).abs() < 1e-7);
+
+    // The reference Oklab and Oklch values CSS Color 4 gives for the sRGB
+    // primaries and white.
+    let oklab = |rgb| Space::Oklab.coordinates(rgb);
+    let oklch = |rgb| Space::Oklch.coordinates(rgb);
+    assert!(close(oklab([255, 0, 0]), [0.62796, 0.22486, 0.12585]));
+    assert!(close(oklab([0, 255, 0]), [0.86644, -0.23389, 0.17950]));
+    assert!(close(oklab([0, 0, 255]), [0.45201, -0.03246, -0.31153]));
+    assert!(close(oklab([255, 255, 255]), [1.0, 0.0, 0.0]));
+    assert!(close(oklch([255, 0, 0]), [0.62796, 0.25768, 29.23389]));
+    assert!(close(oklch([0, 0, 255]), [0.45201, 0.31321, 264.05202]));
+
+    // Every space takes every color back where it came from, to well within
+    // an intensity step.
+    for space in [Space::Srgb, Space::Linear, Space::Oklab, Space::Oklch] {
+        for rgb in [[0, 0, 0], [255, 170, 0], [32, 107, 203], [255, 255, 255]] {
+            let back = space.rgb(space.coordinates(rgb));
+            assert!((0 .. 3).all(|i| (back[i] - rgb[i] as f64).abs() < 1e-3),
+                    "{:?} {:?} {:?}", space, rgb, back);
+        }
+    }
+}
+
+#[test]
+fn test_palette_spaces() {
+    let mid = |spec: &str| Palette::parse(spec).unwrap().color(0.5);
+    // sRGB's muddy purple between red and blue, against brighter ones.
+    assert_eq!(mid("ff0000,0000ff"), [128, 0, 128]);
+    assert_eq!(mid("linear:ff0000,0000ff"), [188, 0, 188]);
+    assert_eq!(mid("oklab:ff0000,0000ff"), [140, 83, 162]);
+    assert_eq!(mid("oklch:ff0000,0000ff"), [186, 0, 194]);
+    // Halfway between black and white looks it in Oklab.
+    assert_eq!(mid("oklab:gray"), [99, 99, 99]);
+    assert_eq!(mid("oklch:gray"), [99, 99, 99]);
+
+    // Oklch takes the short way around the hue circle, from red at 29
+    // degrees to yellow at 110 through orange, not the long way through
+    // green and blue...
+    let hue = |spec: &str| Space::Oklch.coordinates(mid(spec))[2];
+    let orange = hue("oklch:ff0000,ffff00");
+    assert!(orange > 40.0 && orange < 100.0, "{}", orange);
+    // ...and blending with white keeps the other color's hue.
+    assert!((hue("oklch:ff0000,ffffff") - 29.2).abs() < 2.0, "{}", hue("oklch:ff0000,ffffff"));
+}
@@ -7,7 +7,8 @@
  * parse them:
  */
 
-use std::std::FromStr;
+use std::str::FromStr;
+use num::Complex;
 
 /// Parse the string `s` as a coordinate pair, like `"400x600"` or `"1.0, 0.5"`.
 /// 
@@ -17,7 +18,7 @@ use std::std::FromStr;
 ///
 /// If `s` has the proper form, return `Some<(x, y)>`. If it doesn't parse
 /// correctly, return `None`.
-fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
+pub fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
     match s.find(separator) {
         None        => None,
         Some(index) => {
@@ -40,10 +41,21 @@ fn test_parse_pair() {
     assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Some((0.5, 1.5)));
 }
 
+/// Parse a pair of floating-point numbers separated by a comma as a complex
+/// number.
+pub fn parse_complex(s: &str) -> Option<Complex<f64>> {
+    parse_pair(s, ',').map(|(re, im)| Complex { re, im })
+}
+
+#[test]
+fn test_parse_complex() {
+    assert_eq!(parse_complex("1.25,-0.0625"), Some(Complex { re: 1.25, im: -0.0625 }));
+    assert_eq!(parse_complex(",-0.0625"), None);
+}
 
 /* The definition of "parse_pair" is a generic function:
  *
- *      fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
+ *      pub fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
  *
  * You can read the clase <T: FromStr> alaoud as: "For any type T that implements the
  * FromStr trait...". This effectively lets us define an entire family of functions at
@@ -0,0 +1,538 @@
+/* Writing PNG Files
+ * -----------------
+ * A PNG file is an eight-byte signature followed by a sequence of chunks. Each
+ * chunk is a big-endian length, a four-letter type, the data, and a CRC-32 of
+ * the type and data. We only need four of them:
+ *
+ *      IHDR    width, height, bit depth and color type
+ *      iTXt    a keyword and a UTF-8 text, as many as we like
+ *      IDAT    the pixel rows, each prefixed with a filter byte, compressed
+ *              as a zlib stream
+ *      IEND    an empty chunk marking the end of the file
+ *
+ * The IDAT data is a zlib stream: a two-byte header, the rows compressed with
+ * deflate (see deflate.rs), and an Adler-32 checksum of the uncompressed rows.
+ */
+
+use std::io::{self, Write};
+use std::str;
+
+#[cfg(test)]
+use deflate::inflate;
+use deflate::Deflater;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// Return the CRC-32 of `bytes`, continuing from a previous value `crc`.
+/// Start with a `crc` of zero.
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in bytes {
+        c = CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(0, b""), 0);
+    assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+}
+
+/// Return the Adler-32 checksum of `bytes`, continuing from a previous value
+/// `adler`. Start with an `adler` of one.
+pub fn adler32(adler: u32, bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_adler32() {
+    assert_eq!(adler32(1, b""), 1);
+    assert_eq!(adler32(1, b"Wikipedia"), 0x11e6_0398);
+}
+
+/// Write one chunk of type `kind` holding `data` to `out`.
+pub fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8])
+    -> io::Result<()>
+{
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(crc32(0, kind), data).to_be_bytes())
+}
+
+/* Writing Rows as They Come
+ * -------------------------
+ * "PngWriter" takes the image a few rows at a time, so a huge image never has
+ * to be in memory all at once: it holds only the previous row, the
+ * compressor's window, and compressed data waiting to fill an IDAT chunk.
+ *
+ * Before compressing a row, PNG lets us "filter" it, replacing each byte with
+ * its difference from the byte to the left ("Sub"), above ("Up"), their
+ * average ("Average"), or whichever of left, above and upper-left best
+ * predicts it ("Paeth"). Smooth images turn into runs of small numbers that
+ * compress well. We try all five filters on each row and keep the one whose
+ * bytes, taken as signed, add up smallest in magnitude, the usual guess at
+ * which will compress best.
+ */
+
+/// How much compressed data to gather before writing an IDAT chunk.
+const IDAT_SIZE: usize = 65536;
+
+/// The PNG color types we write.
+const GRAYSCALE: u8 = 0;
+const RGB: u8 = 2;
+
+/// Writes a PNG image to `W` a few rows at a time.
+pub struct PngWriter<W: Write> {
+    out: W,
+    bounds: (usize, usize),
+    /// The number of bytes in a row, and in a pixel.
+    row_bytes: usize,
+    pixel_bytes: usize,
+    rows_written: usize,
+    previous: Vec<u8>,
+    deflater: Deflater,
+    idat: Vec<u8>,
+    adler: u32
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Start writing an image of size `bounds` to `out`. Each pixel has
+    /// `channels` samples, one for grayscale or three for RGB, of `depth`
+    /// bits each, which must be 8 or 16; 16-bit samples are big-endian. The
+    /// image carries the `(keyword, text)` pairs in `text`, whose keywords
+    /// must be 1 to 79 printable Latin-1 characters.
+    pub fn new(mut out: W,
+               bounds: (usize, usize),
+               channels: usize,
+               depth: u8,
+               text: &[(&str, &str)])
+        -> io::Result<PngWriter<W>>
+    {
+        assert!(channels == 1 || channels == 3);
+        assert!(depth == 8 || depth == 16);
+        let color_type = if channels == 1 { GRAYSCALE } else { RGB };
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(bounds.0 as u32).to_be_bytes());
+        header.extend_from_slice(&(bounds.1 as u32).to_be_bytes());
+        header.extend_from_slice(&[depth, color_type, 0, 0, 0]);
+
+        out.write_all(&SIGNATURE)?;
+        write_chunk(&mut out, b"IHDR", &header)?;
+        for &(keyword, text) in text {
+            // Keyword, no compression, no language tag, no translated keyword.
+            let mut data = Vec::with_capacity(keyword.len() + text.len() + 5);
+            data.extend_from_slice(keyword.as_bytes());
+            data.extend_from_slice(&[0, 0, 0, 0, 0]);
+            data.extend_from_slice(text.as_bytes());
+            write_chunk(&mut out, b"iTXt", &data)?;
+        }
+
+        let pixel_bytes = channels * depth as usize / 8;
+        Ok(PngWriter {
+            out, bounds,
+            row_bytes: bounds.0 * pixel_bytes,
+            pixel_bytes,
+            rows_written: 0,
+            previous: vec![0; bounds.0 * pixel_bytes],
+            deflater: Deflater::new(),
+            // The zlib header: deflate with a 32 KiB window, default level.
+            idat: vec![0x78, 0x9c],
+            adler: 1
+        })
+    }
+
+    /// Write the next rows of the image, which `rows` holds whole.
+    pub fn write_rows(&mut self, rows: &[u8]) -> io::Result<()> {
+        if self.row_bytes == 0 {
+            return Ok(());
+        }
+        assert!(rows.len().is_multiple_of(self.row_bytes));
+        assert!(self.rows_written + rows.len() / self.row_bytes <= self.bounds.1);
+
+        let mut best = Vec::with_capacity(self.row_bytes + 1);
+        let mut candidate = Vec::with_capacity(self.row_bytes + 1);
+        for row in rows.chunks(self.row_bytes) {
+            let mut best_score = u64::MAX;
+            for filter in NONE ..= PAETH {
+                filter_row(filter, row, &self.previous, self.pixel_bytes, &mut candidate);
+                let score = candidate[1 ..].iter()
+                    .map(|&b| (b as i8).unsigned_abs() as u64)
+                    .sum();
+                if score < best_score {
+                    best_score = score;
+                    std::mem::swap(&mut best, &mut candidate);
+                }
+            }
+            self.adler = adler32(self.adler, &best);
+            self.deflater.write(&best);
+            self.previous.copy_from_slice(row);
+            self.rows_written += 1;
+        }
+
+        self.idat.extend(self.deflater.take_output());
+        if self.idat.len() >= IDAT_SIZE {
+            write_chunk(&mut self.out, b"IDAT", &self.idat)?;
+            self.idat.clear();
+        }
+        Ok(())
+    }
+
+    /// Finish the image, whose rows must all have been written, and return
+    /// the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        assert!(self.row_bytes == 0 || self.rows_written == self.bounds.1);
+        self.idat.extend(self.deflater.finish());
+        self.idat.extend_from_slice(&self.adler.to_be_bytes());
+        write_chunk(&mut self.out, b"IDAT", &self.idat)?;
+        write_chunk(&mut self.out, b"IEND", &[])?;
+        Ok(self.out)
+    }
+}
+
+/// The filter types.
+const NONE: u8 = 0;
+const SUB: u8 = 1;
+const UP: u8 = 2;
+const AVERAGE: u8 = 3;
+const PAETH: u8 = 4;
+
+/// Return whichever of `a` (left), `b` (above) and `c` (upper left) is
+/// closest to `a + b - c`.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Return what filter type `filter` predicts byte `i` of a row will be,
+/// given the bytes of the row before it in `row`, the previous row, and the
+/// number of bytes per pixel.
+fn predict(filter: u8, row: &[u8], previous: &[u8], pixel_bytes: usize, i: usize) -> u8 {
+    let a = if i >= pixel_bytes { row[i - pixel_bytes] } else { 0 };
+    let b = previous[i];
+    let c = if i >= pixel_bytes { previous[i - pixel_bytes] } else { 0 };
+    match filter {
+        SUB     => a,
+        UP      => b,
+        AVERAGE => ((a as u16 + b as u16) / 2) as u8,
+        PAETH   => paeth(a, b, c),
+        _       => 0
+    }
+}
+
+/// Set `out` to `row` filtered with filter type `filter`, preceded by the
+/// filter type byte.
+fn filter_row(filter: u8, row: &[u8], previous: &[u8], pixel_bytes: usize, out: &mut Vec<u8>) {
+    out.clear();
+    out.push(filter);
+    for i in 0 .. row.len() {
+        out.push(row[i].wrapping_sub(predict(filter, row, previous, pixel_bytes, i)));
+    }
+}
+
+/// Write the buffer `pixels`, whose dimensions are given by `bounds`, to `out`
+/// as an 8-bit grayscale PNG image.
+pub fn write_png<W: Write>(out: &mut W, pixels: &[u8], bounds: (usize, usize))
+    -> io::Result<()>
+{
+    write_png_with_text(out, pixels, bounds, 1, &[])
+}
+
+/// Write the buffer `pixels`, which holds red, green and blue bytes for each
+/// pixel, to `out` as an 8-bit RGB PNG image.
+pub fn write_png_rgb<W: Write>(out: &mut W, pixels: &[u8], bounds: (usize, usize))
+    -> io::Result<()>
+{
+    write_png_with_text(out, pixels, bounds, 3, &[])
+}
+
+/// Write the buffer `pixels`, which holds `channels` bytes per pixel (one for
+/// grayscale or three for RGB), to `out` as an 8-bit PNG image carrying the
+/// `(keyword, text)` pairs in `text`.
+pub fn write_png_with_text<W: Write>(out: &mut W,
+                                     pixels: &[u8],
+                                     bounds: (usize, usize),
+                                     channels: usize,
+                                     text: &[(&str, &str)])
+    -> io::Result<()>
+{
+    assert!(pixels.len() == bounds.0 * bounds.1 * channels);
+    let mut writer = PngWriter::new(out, bounds, channels, 8, text)?;
+    writer.write_rows(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/* Reading
+ * -------
+ * We read back the text chunks to reproduce an image, and the tests read
+ * back the pixels to check what we wrote. Reading handles what "PngWriter"
+ * writes: 8- or 16-bit grayscale or RGB, without interlacing.
+ */
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Return the type and data of each chunk of the PNG file `file`, up to but
+/// not including IEND, checking their CRCs.
+fn chunks(file: &[u8]) -> io::Result<Vec<(&[u8], &[u8])>> {
+    if !file.starts_with(&SIGNATURE) {
+        return Err(invalid("not a PNG file"));
+    }
+    let mut chunks = Vec::new();
+    let mut rest = &file[SIGNATURE.len() ..];
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < length + 12 {
+            break;
+        }
+        let (kind, data) = (&rest[4 .. 8], &rest[8 .. 8 + length]);
+        let crc = &rest[8 + length .. 12 + length];
+        if crc32(crc32(0, kind), data).to_be_bytes() != crc {
+            return Err(invalid("bad chunk checksum"));
+        }
+        if kind == b"IEND" {
+            return Ok(chunks);
+        }
+        chunks.push((kind, data));
+        rest = &rest[12 + length ..];
+    }
+    Err(invalid("PNG file is truncated"))
+}
+
+/// A decoded image.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub bounds: (usize, usize),
+    pub channels: usize,
+    pub depth: u8,
+    /// The samples, row by row; 16-bit samples are big-endian.
+    pub pixels: Vec<u8>
+}
+
+/// Decode the PNG file `file`.
+#[cfg(test)]
+pub fn read(file: &[u8]) -> io::Result<Image> {
+    let chunks = chunks(file)?;
+    let header = match chunks.first() {
+        Some(&(kind, header)) if kind == b"IHDR" && header.len() == 13 => header,
+        _ => return Err(invalid("PNG file has no header"))
+    };
+    let bounds = (u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize,
+                  u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize);
+    let channels = match header[9] {
+        GRAYSCALE => 1,
+        RGB => 3,
+        _ => return Err(invalid("unsupported PNG color type"))
+    };
+    let depth = header[8];
+    if depth != 8 && depth != 16 {
+        return Err(invalid("unsupported PNG bit depth"));
+    }
+    if header[12] != 0 {
+        return Err(invalid("interlaced PNG files are not supported"));
+    }
+
+    let stream: Vec<u8> = chunks.iter()
+        .filter(|&&(kind, _)| kind == b"IDAT")
+        .flat_map(|&(_, data)| data.iter().cloned())
+        .collect();
+    if stream.len() < 6 || stream[0] & 0x0f != 8 {
+        return Err(invalid("bad zlib stream"));
+    }
+    let raw = inflate(&stream[2 .. stream.len() - 4]).map_err(|message| invalid(&message))?;
+    if adler32(1, &raw).to_be_bytes() != stream[stream.len() - 4 ..] {
+        return Err(invalid("bad zlib checksum"));
+    }
+
+    let pixel_bytes = channels * depth as usize / 8;
+    let row_bytes = bounds.0 * pixel_bytes;
+    if raw.len() != if row_bytes == 0 { 0 } else { (row_bytes + 1) * bounds.1 } {
+        return Err(invalid("wrong amount of image data"));
+    }
+    let mut pixels = Vec::with_capacity(row_bytes * bounds.1);
+    let mut previous = vec![0; row_bytes];
+    for line in raw.chunks(row_bytes + 1) {
+        let (filter, filtered) = (line[0], &line[1 ..]);
+        if filter > PAETH {
+            return Err(invalid("unknown filter type"));
+        }
+        let mut row = Vec::with_capacity(row_bytes);
+        for (i, &byte) in filtered.iter().enumerate() {
+            let prediction = predict(filter, &row, &previous, pixel_bytes, i);
+            row.push(byte.wrapping_add(prediction));
+        }
+        pixels.extend_from_slice(&row);
+        previous = row;
+    }
+    Ok(Image { bounds, channels, depth, pixels })
+}
+
+/// Return the `(keyword, text)` pairs of the uncompressed text chunks, both
+/// tEXt and iTXt, in the PNG file `file`.
+pub fn read_text(file: &[u8]) -> io::Result<Vec<(String, String)>> {
+    // Keywords, and tEXt text, are Latin-1, which maps straight onto the
+    // first 256 Unicode characters.
+    let latin1 = |bytes: &[u8]| -> String { bytes.iter().map(|&b| b as char).collect() };
+    let split = |data: &[u8]| data.iter().position(|&b| b == 0);
+
+    let mut text = Vec::new();
+    for (kind, data) in chunks(file)? {
+        if kind == b"tEXt" {
+            let end = split(data).ok_or_else(|| invalid("bad tEXt chunk"))?;
+            text.push((latin1(&data[.. end]), latin1(&data[end + 1 ..])));
+        } else if kind == b"iTXt" {
+            let bad = || invalid("bad iTXt chunk");
+            let end = split(data).ok_or_else(bad)?;
+            if data.get(end + 1) != Some(&0) {
+                continue;   // compressed; we never write those
+            }
+            // Skip the compression method, language tag and translated keyword.
+            let mut rest = data.get(end + 3 ..).ok_or_else(bad)?;
+            for _ in 0 .. 2 {
+                rest = &rest[split(rest).ok_or_else(bad)? + 1 ..];
+            }
+            let value = str::from_utf8(rest).map_err(|_| invalid("iTXt chunk is not UTF-8"))?;
+            text.push((latin1(&data[.. end]), value.to_string()));
+        }
+    }
+    Ok(text)
+}
+
+#[test]
+fn test_text_round_trip() {
+    let mut file = Vec::new();
+    let text = [("Software", "mandelbrot"), ("Comment", "zo\u{e9}\n[view]")];
+    write_png_with_text(&mut file, &[1, 2, 3, 4, 5, 6], (1, 2), 3, &text).unwrap();
+    assert_eq!(&file[16..29], &[0, 0, 0, 1, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+    assert_eq!(read_text(&file).unwrap(),
+               vec![("Software".to_string(), "mandelbrot".to_string()),
+                    ("Comment".to_string(), "zo\u{e9}\n[view]".to_string())]);
+
+    // Plain tEXt chunks, as other programs write them, are read too.
+    let mut other = file[.. 33].to_vec();
+    write_chunk(&mut other, b"tEXt", b"Title\0caf\xe9").unwrap();
+    other.extend_from_slice(&file[33 ..]);
+    assert_eq!(read_text(&other).unwrap()[0], ("Title".to_string(), "caf\u{e9}".to_string()));
+
+    let mut corrupt = file.clone();
+    corrupt[45] ^= 1;
+    assert!(read_text(&corrupt).is_err());
+    assert!(read_text(&file[.. file.len() - 12]).is_err());
+    assert!(read_text(b"GIF89a").is_err());
+}
+
+#[test]
+fn test_write_png() {
+    let mut file = Vec::new();
+    write_png(&mut file, &[0, 64, 128, 255, 1, 2], (3, 2)).unwrap();
+
+    assert_eq!(&file[..8], &SIGNATURE);
+    assert_eq!(&file[8..16], b"\0\0\0\x0dIHDR");
+    assert_eq!(&file[16..29], &[0, 0, 0, 3, 0, 0, 0, 2, 8, 0, 0, 0, 0]);
+    assert_eq!(&file[file.len() - 12..],
+               &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+    assert_eq!(read(&file).unwrap(),
+               Image { bounds: (3, 2), channels: 1, depth: 8, pixels: vec![0, 64, 128, 255, 1, 2] });
+}
+
+#[test]
+fn test_write_png_rgb() {
+    let mut file = Vec::new();
+    write_png_rgb(&mut file, &[255, 0, 0, 0, 255, 0], (2, 1)).unwrap();
+
+    assert_eq!(&file[16..29], &[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+    assert_eq!(read(&file).unwrap().pixels, [255, 0, 0, 0, 255, 0]);
+}
+
+#[test]
+fn test_filters() {
+    let (row, previous) = ([10, 20, 30, 200, 210, 5], [1, 2, 3, 250, 9, 100]);
+    for filter in NONE ..= PAETH {
+        let mut filtered = Vec::new();
+        filter_row(filter, &row, &previous, 3, &mut filtered);
+        assert_eq!(filtered[0], filter);
+        let mut unfiltered = Vec::new();
+        for (i, &byte) in filtered[1 ..].iter().enumerate() {
+            let prediction = predict(filter, &unfiltered, &previous, 3, i);
+            unfiltered.push(byte.wrapping_add(prediction));
+        }
+        assert_eq!(unfiltered, row);
+    }
+    assert_eq!(paeth(10, 20, 15), 15);
+    assert_eq!(paeth(10, 20, 10), 20);
+}
+
+#[test]
+fn test_streaming_16_bit() {
+    // A pattern of 16-bit RGB samples, written a band of rows at a time.
+    let bounds = (300, 200);
+    let mut pixels = Vec::new();
+    for y in 0 .. bounds.1 {
+        for x in 0 .. bounds.0 {
+            for sample in [x * 200 + y, y * 300, (x ^ y) * 97] {
+                pixels.extend_from_slice(&(sample as u16).to_be_bytes());
+            }
+        }
+    }
+    let mut writer = PngWriter::new(Vec::new(), bounds, 3, 16, &[]).unwrap();
+    for band in pixels.chunks(bounds.0 * 6 * 7) {
+        writer.write_rows(band).unwrap();
+    }
+    let file = writer.finish().unwrap();
+    assert!(file.len() < pixels.len() / 2);
+    assert_eq!(read(&file).unwrap(),
+               Image { bounds, channels: 3, depth: 16, pixels: pixels.clone() });
+
+    let gray: Vec<u8> = pixels.chunks(6).flat_map(|p| [p[2], p[3]]).collect();
+    let mut writer = PngWriter::new(Vec::new(), bounds, 1, 16, &[]).unwrap();
+    writer.write_rows(&gray).unwrap();
+    assert_eq!(read(&writer.finish().unwrap()).unwrap().pixels, gray);
+}
+
+#[test]
+fn test_compression() {
+    // A flat image is almost all repeats.
+    let mut file = Vec::new();
+    write_png(&mut file, &vec![77; 1000 * 1000], (1000, 1000)).unwrap();
+    assert!(file.len() < 10_000);
+    assert_eq!(read(&file).unwrap().pixels, vec![77; 1000 * 1000]);
+
+    let mut empty = Vec::new();
+    write_png(&mut empty, &[], (0, 0)).unwrap();
+    assert_eq!(read(&empty).unwrap().pixels, []);
+}
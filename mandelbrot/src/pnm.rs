@@ -0,0 +1,120 @@
+/* PGM and PPM Files
+ * -----------------
+ * The Netpbm formats are about as simple as image files get: a short text
+ * header giving the kind of image, its size, and the largest sample value,
+ * then the samples themselves, row by row, with no compression at all.
+ *
+ *      P5              grayscale (PGM); P6 is RGB (PPM)
+ *      640 480         width and height
+ *      255             the largest sample; 65535 for 16-bit samples
+ *
+ * A single whitespace character separates the header from the samples,
+ * which are big-endian when they take two bytes. Since the samples come in
+ * the order we render them, rows can be written as soon as they're ready.
+ */
+
+use std::io::{self, Write};
+
+use image::ImageWriter;
+#[cfg(test)]
+use png::Image;
+
+/// Writes a PGM or PPM image to `W` a few rows at a time.
+pub struct PnmWriter<W: Write> {
+    out: W
+}
+
+impl<W: Write> PnmWriter<W> {
+    /// Start writing an image of size `bounds` to `out`, as PGM if `channels`
+    /// is one or PPM if it is three, with samples of `depth` bits, 8 or 16.
+    pub fn new(mut out: W, bounds: (usize, usize), channels: usize, depth: u8)
+        -> io::Result<PnmWriter<W>>
+    {
+        let magic = if channels == 1 { "P5" } else { "P6" };
+        let max = if depth == 16 { 65535 } else { 255 };
+        write!(out, "{}\n{} {}\n{}\n", magic, bounds.0, bounds.1, max)?;
+        Ok(PnmWriter { out })
+    }
+}
+
+impl<W: Write> ImageWriter for PnmWriter<W> {
+    fn write_rows(&mut self, rows: &[u8]) -> io::Result<()> {
+        self.out.write_all(rows)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Decode the binary PGM or PPM file `file`.
+#[cfg(test)]
+pub fn read(file: &[u8]) -> io::Result<Image> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    // Split off the four header fields, skipping comments.
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while fields.len() < 4 {
+        match file.get(position) {
+            None => return Err(invalid("PNM header is truncated")),
+            Some(b'#') => {
+                while file.get(position).is_some_and(|&b| b != b'\n') {
+                    position += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => position += 1,
+            Some(_) => {
+                let start = position;
+                while file.get(position).is_some_and(|b| !b.is_ascii_whitespace()) {
+                    position += 1;
+                }
+                fields.push(String::from_utf8_lossy(&file[start .. position]).into_owned());
+            }
+        }
+    }
+    let number = |field: &str| field.parse::<usize>().map_err(|_| invalid("bad PNM header"));
+
+    let channels = match fields[0].as_str() {
+        "P5" => 1,
+        "P6" => 3,
+        _ => return Err(invalid("not a binary PGM or PPM file"))
+    };
+    let bounds = (number(&fields[1])?, number(&fields[2])?);
+    let depth = match number(&fields[3])? {
+        1 ..= 255 => 8,
+        256 ..= 65535 => 16,
+        _ => return Err(invalid("bad PNM maximum value"))
+    };
+    let samples = &file[position + 1 ..];
+    if samples.len() != bounds.0 * bounds.1 * channels * depth as usize / 8 {
+        return Err(invalid("wrong amount of image data"));
+    }
+    Ok(Image { bounds, channels, depth, pixels: samples.to_vec() })
+}
+
+#[test]
+fn test_round_trip() {
+    let gray = [0, 64, 128, 255, 1, 2];
+    let mut file = Vec::new();
+    let mut writer = Box::new(PnmWriter::new(&mut file, (3, 2), 1, 8).unwrap());
+    writer.write_rows(&gray[.. 3]).unwrap();
+    writer.write_rows(&gray[3 ..]).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(&file[.. 11], b"P5\n3 2\n255\n");
+    assert_eq!(read(&file).unwrap(),
+               Image { bounds: (3, 2), channels: 1, depth: 8, pixels: gray.to_vec() });
+
+    let rgb16: Vec<u8> = (0 .. 2 * 2 * 3 * 2).map(|i| (i * 17 % 256) as u8).collect();
+    let mut file = Vec::new();
+    let mut writer = Box::new(PnmWriter::new(&mut file, (2, 2), 3, 16).unwrap());
+    writer.write_rows(&rgb16).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(&file[.. 13], b"P6\n2 2\n65535\n");
+    assert_eq!(read(&file).unwrap(),
+               Image { bounds: (2, 2), channels: 3, depth: 16, pixels: rgb16 });
+
+    assert!(read(b"P5\n# made by hand\n2 1\n255\n\x01\x02").is_ok());
+    assert!(read(b"P5\n2 1\n255\n\x01").is_err());
+    assert!(read(b"P2\n1 1\n255\n0").is_err());
+}
@@ -0,0 +1,188 @@
+/* Progressive Rendering
+ * ---------------------
+ * A long render shows nothing until it's done. A progressive render instead
+ * makes several passes over the image, each one finer than the last:
+ *
+ *      pass 1: every 8th pixel in each direction, each one painted over
+ *              the 8x8 block it starts
+ *      pass 2: every 4th pixel, in 4x4 blocks
+ *      pass 3: every 2nd pixel, in 2x2 blocks
+ *      pass 4: every pixel
+ *
+ * After each pass the caller gets to look at the whole buffer, blocky as it
+ * may be, to show it or save it as a preview.
+ *
+ * A pixel on the 8-pixel grid is also on the 4-, 2- and 1-pixel grids, so each
+ * pass reuses the values from the passes before it and computes only the new
+ * points. Altogether every pixel is computed exactly once, and the final
+ * image is identical to the one "render_config" produces. Each preview is
+ * colored the same way, and dithered and overlaid afresh, so it looks like
+ * the finished image would at that resolution.
+ */
+
+use std::thread;
+
+use config::Config;
+use render::{finish_samples, sample_config, Shader};
+
+/// The spacing between the pixels computed in each pass. Each entry must
+/// divide the one before it.
+pub const PASSES: [usize; 4] = [8, 4, 2, 1];
+
+/// Render the image `config` describes in several passes, using `threads`
+/// threads, calling `preview` with the pass's pixel spacing and the image
+/// after each one.
+pub fn render_progressive<F>(config: &Config, threads: usize, mut preview: F)
+    where F: FnMut(usize, &[u8])
+{
+    let samples = sample_config(config);
+    let shader = Shader::new(&samples);
+    let (width, height) = config.bounds;
+    let mut pixels = vec![0; width * height * samples.pixel_bytes()];
+    refine(&mut pixels, config.bounds, samples.pixel_bytes(), threads,
+           |column, row, pixel| shader.pixel(column, row, pixel),
+           |step, pixels| preview(step, &finish_samples(config, pixels.to_vec())));
+}
+
+/// Fill `pixels`, `pixel_bytes` bytes to a pixel, in passes, calling `pixel`
+/// to compute the pixel at a given column and row.
+fn refine<P, F>(pixels: &mut [u8],
+                bounds: (usize, usize),
+                pixel_bytes: usize,
+                threads: usize,
+                pixel: P,
+                mut preview: F)
+    where P: Fn(usize, usize, &mut [u8]) + Sync,
+          F: FnMut(usize, &[u8])
+{
+    assert!(pixels.len() == bounds.0 * bounds.1 * pixel_bytes);
+    if pixels.is_empty() {
+        return;
+    }
+
+    let pixel = &pixel;
+    for (pass, &step) in PASSES.iter().enumerate() {
+        let coarser = if pass == 0 { None } else { Some(PASSES[pass - 1]) };
+
+        // Bands are made of whole rows of blocks, so that every band starts
+        // on a row of the grid.
+        let block_rows = bounds.1.div_ceil(step);
+        let block_rows_per_band = block_rows.div_ceil(threads.max(1));
+        let band_rows = block_rows_per_band * step;
+
+        thread::scope(|spawner| {
+            for (i, band) in pixels.chunks_mut(band_rows * bounds.0 * pixel_bytes).enumerate() {
+                let top = i * band_rows;
+                spawner.spawn(move || {
+                    refine_band(band, bounds.0, pixel_bytes, top, step, coarser, pixel);
+                });
+            }
+        });
+
+        preview(step, pixels);
+    }
+}
+
+/// Compute the pixels of `band` that lie on the grid with spacing `step` but
+/// not on the `coarser` grid, if any, and paint each grid pixel's value over
+/// its block. `band` holds rows of `width` pixels of `pixel_bytes` bytes, the
+/// first of which is row `top` of the image.
+fn refine_band<P>(band: &mut [u8],
+                  width: usize,
+                  pixel_bytes: usize,
+                  top: usize,
+                  step: usize,
+                  coarser: Option<usize>,
+                  pixel: &P)
+    where P: Fn(usize, usize, &mut [u8])
+{
+    let row_bytes = width * pixel_bytes;
+    let rows = band.len() / row_bytes;
+    for y in (0 .. rows).step_by(step) {
+        for x in (0 .. width).step_by(step) {
+            let row = top + y;
+            let start = y * row_bytes + x * pixel_bytes;
+            let value = start .. start + pixel_bytes;
+            match coarser {
+                Some(c) if x.is_multiple_of(c) && row.is_multiple_of(c) => {}
+                _ => pixel(x, row, &mut band[value.clone()])
+            }
+            for block_y in y .. (y + step).min(rows) {
+                for block_x in x .. (x + step).min(width) {
+                    let target = block_y * row_bytes + block_x * pixel_bytes;
+                    if target != start {
+                        band.copy_within(value.clone(), target);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_refine_computes_each_pixel_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let bounds = (37, 21);
+    let calls: Vec<AtomicUsize> =
+        (0 .. bounds.0 * bounds.1).map(|_| AtomicUsize::new(0)).collect();
+    let mut steps = Vec::new();
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+
+    refine(&mut pixels, bounds, 1, 3,
+           |column, row, pixel| {
+               calls[row * bounds.0 + column].fetch_add(1, Ordering::Relaxed);
+               pixel[0] = (row * 7 + column) as u8;
+           },
+           |step, preview| {
+               steps.push(step);
+               if step == 8 {
+                   // Every pixel shows the value of its block's corner.
+                   assert_eq!(preview[5 * bounds.0 + 3], 0);
+                   assert_eq!(preview[20 * bounds.0 + 36], (16 * 7 + 32) as u8);
+               }
+           });
+
+    assert_eq!(steps, PASSES);
+    assert!(calls.iter().all(|count| count.load(Ordering::Relaxed) == 1));
+    for (i, &value) in pixels.iter().enumerate() {
+        assert_eq!(value, ((i / bounds.0) * 7 + i % bounds.0) as u8);
+    }
+}
+
+#[test]
+fn test_render_progressive_matches_render() {
+    use dither::Dither;
+    use num::Complex;
+
+    let mut config = Config::new((50, 33), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    let mut fancy = config.clone();
+    fancy.palette = Some("fire".to_string());
+    fancy.limit = 2000;
+    fancy.dither = Dither::FloydSteinberg;
+    fancy.overlay = Some("axes".to_string());
+    for config in [config.clone(), fancy] {
+        let expected = ::render::render_config(&config, 1);
+        let mut passes = 0;
+        let mut last = Vec::new();
+        render_progressive(&config, 4, |step, preview| {
+            assert_eq!(preview.len(), expected.len());
+            passes += 1;
+            if step == 1 {
+                last = preview.to_vec();
+            }
+        });
+        assert_eq!(passes, PASSES.len());
+        assert_eq!(last, expected);
+    }
+
+    // Sixteen-bit samples are painted over blocks whole.
+    config.depth = 16;
+    config.palette = Some("fire".to_string());
+    let mut first = Vec::new();
+    render_progressive(&config, 2, |step, preview| if step == 8 { first = preview.to_vec() });
+    let pixel = |x: usize, y: usize| &first[(y * 50 + x) * 6 .. (y * 50 + x) * 6 + 6];
+    assert_eq!(pixel(7, 7), pixel(0, 0));
+    assert_eq!(pixel(49, 32), pixel(48, 32));
+}
@@ -0,0 +1,592 @@
+/* Mapping from Pixels to Complex Numbers
+ * --------------------------------------
+ * The program needs to work in two related coordinate spaces: each pixel in the
+ * output image corresponds to a point on the complex plane. The relationship
+ * between these two spaces depends on which portion of the Mandelbrot set we're
+ * going to plot, and the resolution of the image requested, as determined by
+ * command-line arguments.
+ */
+
+use num::Complex;
+use config::{Coloring, Config};
+use dither::{Dither, Ditherer};
+use escape_time;
+use light::Light;
+use orbit::{Measures, Sample};
+use overlay;
+use palette::{position, Palette};
+use trap::Trap;
+
+/// Given the row and column of a pixel in the output image, return the
+/// corresponding point on the complex plane.
+///
+/// `bounds` is a pair giving the width and height of the image in pixels.
+/// `pixel` is a (column, row) pair indicating a particular pixel in that image.
+/// The `upper_left` and `lower_right` parameters are points on the complex
+/// plane designating the area our image covers.
+pub fn pixel_to_point(bounds: (usize, usize),
+                      pixel: (usize, usize),
+                      upper_left: Complex<f64>,
+                      lower_right: Complex<f64>)
+    -> Complex<f64>
+{
+    let (width, height) = (lower_right.re - upper_left.re,
+                           upper_left.im - lower_right.im);
+    Complex {
+        re: upper_left.re + pixel.0 as f64 * width  / bounds.0 as f64,
+        im: upper_left.im - pixel.1 as f64 * height / bounds.1 as f64
+        // Why subtraction here? pixel.1 increases as we go down,
+        // but the imaginary component increases as we go up.
+    }
+}
+
+#[test]
+fn test_pixel_to_point() {
+    assert_eq!(pixel_to_point((100, 100), (25, 75),
+                              Complex { re: -1.0, im:  1.0 },
+                              Complex { re:  1.0, im: -1.0 }),
+               Complex { re: -0.5, im: -0.5 });
+}
+
+/* Plotting the Set
+ * ----------------
+ * To plot the Mandelbrot set, for every pixel in the image, we simply apply
+ * "escape_time" to the corresponding point on the complex plane, and color the
+ * pixel depending on the result. Points in the set are black, and the faster a
+ * point escapes, the lighter its pixel.
+ */
+
+/// Return the grayscale value of the pixel showing `point`: black if it is in
+/// the set, and lighter the faster it escapes.
+pub fn shade(point: Complex<f64>) -> u8 {
+    match escape_time(point, 255) {
+        None => 0,
+        Some(count) => 255 - count as u8
+    }
+}
+
+/// Render a rectangle of the Mandelbrot set into a buffer of pixels.
+///
+/// The `bounds` argument gives the width and height of the buffer `pixels`,
+/// which holds one grayscale pixel per byte. The `upper_left` and `lower_right`
+/// arguments specify points on the complex plane corresponding to the upper-
+/// left and lower-right corners of the pixel buffer.
+pub fn render(pixels: &mut [u8],
+              bounds: (usize, usize),
+              upper_left: Complex<f64>,
+              lower_right: Complex<f64>)
+{
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    render_rows(pixels, bounds, 0, upper_left, lower_right);
+}
+
+/// Render the rows of an image of size `bounds` starting at row `top` into
+/// `pixels`, which holds a whole number of rows.
+pub fn render_rows(pixels: &mut [u8],
+                   bounds: (usize, usize),
+                   top: usize,
+                   upper_left: Complex<f64>,
+                   lower_right: Complex<f64>)
+{
+    assert!(pixels.len().is_multiple_of(bounds.0.max(1)));
+    let window = (bounds.0, pixels.len() / bounds.0.max(1));
+    render_window(pixels, bounds, (0, top), window, upper_left, lower_right);
+}
+
+/// Render the rectangle of an image of size `bounds` whose upper-left pixel
+/// is `origin` and whose width and height are given by `window` into
+/// `pixels`.
+///
+/// Every pixel is mapped through `pixel_to_point` using the bounds of the
+/// whole image, so a piece rendered on its own is bit-for-bit identical to
+/// the same pixels of a full render, and the whole image never needs to be in
+/// memory at once.
+pub fn render_window(pixels: &mut [u8],
+                     bounds: (usize, usize),
+                     origin: (usize, usize),
+                     window: (usize, usize),
+                     upper_left: Complex<f64>,
+                     lower_right: Complex<f64>)
+{
+    assert!(pixels.len() == window.0 * window.1);
+    assert!(origin.0 + window.0 <= bounds.0 && origin.1 + window.1 <= bounds.1);
+
+    for (row, line) in pixels.chunks_mut(window.0.max(1)).enumerate() {
+        for (column, pixel) in line.iter_mut().enumerate() {
+            let point = pixel_to_point(bounds,
+                                       (origin.0 + column, origin.1 + row),
+                                       upper_left, lower_right);
+            *pixel = shade(point);
+        }
+    }
+}
+
+#[test]
+fn test_render_window_matches_render() {
+    let bounds = (30, 20);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 0.6, im: -1.2 };
+
+    let mut full = vec![0; bounds.0 * bounds.1];
+    render(&mut full, bounds, upper_left, lower_right);
+
+    let mut window = vec![0; 7 * 5];
+    render_window(&mut window, bounds, (11, 9), (7, 5), upper_left, lower_right);
+    for row in 0 .. 5 {
+        assert_eq!(&window[row * 7 .. row * 7 + 7],
+                   &full[(9 + row) * 30 + 11 .. (9 + row) * 30 + 18]);
+    }
+}
+
+/* Running It in Parallel
+ * ----------------------
+ * Each band of rows can be rendered independently of the others, so we split
+ * the buffer into one horizontal band per thread, and hand each thread the
+ * rows it covers. "thread::scope" guarantees all the threads have finished
+ * before it returns, so they may borrow "pixels".
+ */
+
+/// Like `render_rows`, but split the work into horizontal bands rendered by
+/// `threads` threads at once.
+pub fn render_parallel(pixels: &mut [u8],
+                       bounds: (usize, usize),
+                       top: usize,
+                       upper_left: Complex<f64>,
+                       lower_right: Complex<f64>,
+                       threads: usize)
+{
+    let rows = pixels.len() / bounds.0.max(1);
+    if pixels.is_empty() {
+        return;
+    }
+
+    let rows_per_band = rows / threads.max(1) + 1;
+    let bands = pixels.chunks_mut(rows_per_band * bounds.0);
+
+    std::thread::scope(|spawner| {
+        for (i, band) in bands.enumerate() {
+            let top = top + rows_per_band * i;
+            spawner.spawn(move || {
+                render_rows(band, bounds, top, upper_left, lower_right);
+            });
+        }
+    });
+}
+
+#[test]
+fn test_render_parallel_matches_render() {
+    let bounds = (37, 23);
+    let upper_left = Complex { re: -2.0, im: 1.2 };
+    let lower_right = Complex { re: 0.6, im: -1.2 };
+
+    let mut serial = vec![0; bounds.0 * bounds.1];
+    render(&mut serial, bounds, upper_left, lower_right);
+
+    let mut parallel = vec![0; bounds.0 * bounds.1];
+    render_parallel(&mut parallel, bounds, 0, upper_left, lower_right, 4);
+    assert_eq!(serial, parallel);
+
+    let mut band = vec![0; bounds.0 * 5];
+    render_parallel(&mut band, bounds, 11, upper_left, lower_right, 3);
+    assert_eq!(band, &serial[bounds.0 * 11 .. bounds.0 * 16]);
+}
+
+/* Rendering a Job
+ * ---------------
+ * A job file (see config.rs) asks for more than "render" offers: Julia sets,
+ * any iteration limit, palettes, and anti-aliasing, which takes several
+ * samples spread over each pixel and averages their colors. With none of
+ * those, "render_config" produces exactly what "render" does.
+ *
+ * "render_config_bands" renders a few rows at a time and hands each band on
+ * as it's finished, so that an image can be written out as it's rendered
+ * without ever being in memory whole.
+ *
+ * A dithered image is rendered with 16-bit samples, and each band is then
+ * dithered down to 8 bits before it's handed on; see dither.rs.
+ */
+
+/// How many rows each thread renders per band in `render_config_bands`.
+const ROWS_PER_THREAD: usize = 8;
+
+/// Return the color of a pixel whose orbit is `sample`, as a gray level or
+/// RGB intensities running up to `max`, the largest sample value the image
+/// can hold.
+fn color_sample(config: &Config, palette: Option<&Palette>, sample: &Sample, max: u32)
+    -> [u32; 3]
+{
+    if sample.trap.is_finite() {
+        // Near the trap is bright, or the start of the palette; a distance
+        // of one or more is dark, or its end.
+        let t = sample.trap.min(1.0);
+        return match palette {
+            None => [((1.0 - t) * max as f64).round() as u32, 0, 0],
+            Some(palette) => palette.mix(t).map(|c| (c * max as f64 / 255.0).round() as u32)
+        };
+    }
+    let average = match config.coloring {
+        Coloring::Escape => None,
+        Coloring::Stripe(_) => Some(sample.stripe),
+        Coloring::Triangle => Some(sample.triangle)
+    };
+    match (sample.count, palette, average) {
+        (None, _, _) => [0, 0, 0],
+        (Some(count), None, None) => {
+            [max - (count as u64 * max as u64 / config.limit as u64) as u32, 0, 0]
+        }
+        (Some(_), None, Some(average)) => [(average * max as f64).round() as u32, 0, 0],
+        (Some(_), Some(palette), average) => {
+            let t = average.unwrap_or_else(|| position(sample.smooth, config.limit, config.cycle));
+            palette.mix(t).map(|c| (c * max as f64 / 255.0).round() as u32)
+        }
+    }
+}
+
+/// Return `color`, with intensities running up to `max`, as `light` shows it
+/// on a surface sloping down toward `normal`.
+fn light_color(light: &Light, color: [u32; 3], normal: Complex<f64>, max: u32) -> [u32; 3] {
+    let (diffuse, specular) = light.shade(normal);
+    color.map(|c| ((c as f64 * diffuse + specular * max as f64).round() as u32).min(max))
+}
+
+/// Render the image `config` describes using `threads` threads, returning
+/// `config.pixel_bytes()` bytes per pixel, with 16-bit samples big-endian.
+pub fn render_config(config: &Config, threads: usize) -> Vec<u8> {
+    let samples = sample_config(config);
+    let (width, height) = config.bounds;
+    let mut pixels = vec![0; width * height * samples.pixel_bytes()];
+    render_config_rows(&samples, &mut pixels, 0, threads);
+    finish_samples(config, pixels)
+}
+
+/// Return the configuration whose rows "render_config_rows" renders for the
+/// image `config` describes: `config` itself, unless it's dithered.
+pub fn sample_config(config: &Config) -> Config {
+    undithered(config).unwrap_or_else(|| config.clone())
+}
+
+/// Turn `samples`, the whole image rendered with "sample_config", into the
+/// finished pixels of the image `config` describes.
+pub fn finish_samples(config: &Config, samples: Vec<u8>) -> Vec<u8> {
+    let mut pixels = if undithered(config).is_some() {
+        let mut pixels = vec![0; samples.len() / 2];
+        Ditherer::new(config.dither, config.bounds.0, config.channels())
+            .rows(&samples, &mut pixels);
+        pixels
+    } else {
+        samples
+    };
+    overlay::draw(config, &mut pixels, 0);
+    pixels
+}
+
+/// If `config` asks for dithered 8-bit samples, return the 16-bit
+/// configuration to render before dithering them.
+fn undithered(config: &Config) -> Option<Config> {
+    if config.dither == Dither::None || config.depth != 8 {
+        return None;
+    }
+    Some(Config { depth: 16, dither: Dither::None, ..config.clone() })
+}
+
+/// Render the image `config` describes a band of rows at a time, using
+/// `threads` threads, and pass each band to `sink` in order. Only one band
+/// is held at once.
+pub fn render_config_bands<F, E>(config: &Config, threads: usize, mut sink: F) -> Result<(), E>
+    where F: FnMut(&[u8]) -> Result<(), E>
+{
+    let (width, height) = config.bounds;
+    let band_rows = threads.max(1) * ROWS_PER_THREAD;
+    let row_bytes = width * config.pixel_bytes();
+    let mut band = vec![0; band_rows * row_bytes];
+    let wide = undithered(config);
+    let mut samples = vec![0; if wide.is_some() { band.len() * 2 } else { 0 }];
+    let mut ditherer = Ditherer::new(config.dither, width, config.channels());
+
+    for top in (0 .. height).step_by(band_rows) {
+        let band = &mut band[.. band_rows.min(height - top) * row_bytes];
+        match wide {
+            None => render_config_rows(config, band, top, threads),
+            Some(ref wide) => {
+                let samples = &mut samples[.. band.len() * 2];
+                render_config_rows(wide, samples, top, threads);
+                ditherer.rows(samples, band);
+            }
+        }
+        overlay::draw(config, band, top);
+        sink(band)?;
+    }
+    Ok(())
+}
+
+/// Everything it takes to compute a pixel of the image a `Config` describes.
+pub struct Shader<'a> {
+    config: &'a Config,
+    palette: Option<Palette>,
+    trap: Option<Trap>,
+    light: Option<Light>
+}
+
+impl<'a> Shader<'a> {
+    pub fn new(config: &'a Config) -> Shader<'a> {
+        Shader {
+            config,
+            palette: config.palette.as_ref()
+                .map(|spec| Palette::parse(spec).expect("invalid palette in configuration")),
+            trap: config.trap.as_ref()
+                .map(|spec| Trap::parse(spec).expect("invalid trap in configuration")),
+            light: config.light.as_ref()
+                .map(|spec| Light::parse(spec).expect("invalid light in configuration"))
+        }
+    }
+
+    /// Compute the pixel at `column` and `row` into `pixel`, which holds
+    /// "Config::pixel_bytes" bytes.
+    pub fn pixel(&self, column: usize, row: usize, pixel: &mut [u8]) {
+        let config = self.config;
+        if config.is_plain() {
+            // Just as "render_rows" would.
+            pixel[0] = shade(pixel_to_point(config.bounds, (column, row),
+                                            config.upper_left, config.lower_right));
+            return;
+        }
+
+        let (channels, pixel_bytes) = (config.channels(), config.pixel_bytes());
+        let max = if config.depth == 16 { 65535 } else { 255 };
+        let measures = Measures {
+            trap: self.trap.as_ref(),
+            stripe: match config.coloring { Coloring::Stripe(density) => Some(density), _ => None },
+            triangle: config.coloring == Coloring::Triangle
+        };
+        let n = config.antialias.max(1) as usize;
+        let fine = (config.bounds.0 * n, config.bounds.1 * n);
+        let mut sum = [0u32; 3];
+        for dy in 0 .. n {
+            for dx in 0 .. n {
+                let point = config.pixel_to_point(fine, (column * n + dx, row * n + dy));
+                let sample = config.fractal.sample(point, config.limit, &measures,
+                                                   config.precision);
+                let mut color = color_sample(config, self.palette.as_ref(), &sample, max);
+                if let (Some(light), Some(_)) = (self.light.as_ref(), sample.count) {
+                    color = light_color(light, color, sample.normal, max);
+                }
+                for k in 0 .. 3 {
+                    sum[k] += color[k];
+                }
+            }
+        }
+        let samples = (n * n) as u32;
+        for k in 0 .. channels {
+            let value = (sum[k] + samples / 2) / samples;
+            if pixel_bytes == channels {
+                pixel[k] = value as u8;
+            } else {
+                pixel[k * 2 .. k * 2 + 2].copy_from_slice(&(value as u16).to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Render into `pixels` the rows of the image `config` describes starting at
+/// row `top`, using `threads` threads.
+pub fn render_config_rows(config: &Config, pixels: &mut [u8], top: usize, threads: usize) {
+    let width = config.bounds.0;
+    let pixel_bytes = config.pixel_bytes();
+    if pixels.is_empty() {
+        return;
+    }
+    if config.is_plain() {
+        // The original grayscale render needs none of the generality below.
+        render_parallel(pixels, config.bounds, top, config.upper_left, config.lower_right, threads);
+        return;
+    }
+
+    let shader = Shader::new(config);
+    let rows = pixels.len() / (width * pixel_bytes);
+    let rows_per_band = rows / threads.max(1) + 1;
+
+    std::thread::scope(|spawner| {
+        for (i, band) in pixels.chunks_mut(rows_per_band * width * pixel_bytes).enumerate() {
+            let shader = &shader;
+            spawner.spawn(move || {
+                for (j, pixel) in band.chunks_mut(pixel_bytes).enumerate() {
+                    shader.pixel(j % width, top + i * rows_per_band + j / width, pixel);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_render_config_plain_matches_render() {
+    let config = Config::new((45, 30), Complex { re: -2.0, im: 1.2 },
+                             Complex { re: 0.6, im: -1.2 }, "unused.png");
+    let mut expected = vec![0; 45 * 30];
+    render(&mut expected, config.bounds, config.upper_left, config.lower_right);
+    assert_eq!(render_config(&config, 3), expected);
+}
+
+#[test]
+fn test_render_config_julia_palette_antialias() {
+    use fractal::Fractal;
+
+    let mut config = Config::new((20, 10), Complex { re: -1.6, im: 0.8 },
+                                 Complex { re: 1.6, im: -0.8 }, "unused.png");
+    config.fractal = Fractal::Julia(Complex { re: -0.8, im: 0.156 });
+    config.palette = Some("ff0000,0000ff".to_string());
+    config.antialias = 2;
+    config.limit = 100;
+
+    let pixels = render_config(&config, 2);
+    assert_eq!(pixels.len(), 20 * 10 * 3);
+    // The corners are far outside the Julia set, so they escape at once and
+    // take the start of the palette.
+    assert!(pixels[0] > 240 && pixels[2] < 15, "{:?}", &pixels[.. 3]);
+    // Averaging four samples can land between the palette's colors, but
+    // never adds green.
+    assert!(pixels.chunks(3).all(|p| p[1] == 0));
+}
+
+#[test]
+fn test_render_config_fixed_precision() {
+    use fixed::Precision;
+    use fractal::Fractal;
+
+    // Fixed precision renders the same picture as double, but for the odd
+    // pixel right on the boundary, where rounding decides.
+    let mut config = Config::new((60, 40), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    for fractal in [Fractal::Mandelbrot, Fractal::Julia(Complex { re: -0.8, im: 0.156 })] {
+        config.fractal = fractal;
+        config.precision = Precision::Double;
+        let double = render_config(&config, 2);
+        config.precision = Precision::Fixed;
+        let fixed = render_config(&config, 2);
+        let differ = double.iter().zip(&fixed).filter(|(a, b)| a != b).count();
+        assert!(differ <= 2, "{:?}: {} pixels differ", fractal, differ);
+    }
+
+    // A Julia view wide enough that most of its points square past Fixed's
+    // range.
+    let mut config = Config::new((10, 10), Complex { re: -20.0, im: 20.0 },
+                                 Complex { re: 20.0, im: -20.0 }, "unused.png");
+    config.fractal = Fractal::Julia(Complex { re: 0.0, im: 0.0 });
+    let double = render_config(&config, 2);
+    config.precision = Precision::Fixed;
+    assert_eq!(render_config(&config, 2), double);
+}
+
+#[test]
+fn test_render_config_bands_match_render_config() {
+    let mut config = Config::new((23, 41), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    for palette in [None, Some("fire".to_string())] {
+        config.palette = palette;
+        let mut bands = Vec::new();
+        render_config_bands(&config, 2, |band| -> Result<(), ()> {
+            assert!(band.len() <= 16 * 23 * config.pixel_bytes());
+            bands.extend_from_slice(band);
+            Ok(())
+        }).unwrap();
+        assert_eq!(bands, render_config(&config, 3));
+    }
+}
+
+#[test]
+fn test_render_config_16_bit() {
+    let mut config = Config::new((23, 17), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    for palette in [None, Some("fire".to_string())] {
+        config.palette = palette;
+        config.depth = 8;
+        let narrow = render_config(&config, 2);
+        config.depth = 16;
+        let wide = render_config(&config, 2);
+        assert_eq!(wide.len(), narrow.len() * 2);
+        // Each 16-bit sample should round to the 8-bit one.
+        for (&byte, pair) in narrow.iter().zip(wide.chunks(2)) {
+            let sample = u16::from_be_bytes([pair[0], pair[1]]) as f64;
+            assert!((sample / 257.0 - byte as f64).abs() <= 1.0, "{} vs {}", sample, byte);
+        }
+    }
+}
+
+#[test]
+fn test_render_config_trap() {
+    // A 3x1 image whose middle pixel shows -1, whose orbit passes through
+    // the origin: trapped exactly, so white, though it never escapes.
+    let mut config = Config::new((3, 1), Complex { re: -1.5, im: 0.0 },
+                                 Complex { re: 0.0, im: -0.5 }, "unused.png");
+    assert_eq!(render_config(&config, 1)[1], 0);
+    config.trap = Some("point".to_string());
+    assert_eq!(render_config(&config, 1)[1], 255);
+    config.trap = Some("circle:1".to_string());
+    assert_eq!(render_config(&config, 1)[1], 255);
+}
+
+#[test]
+fn test_render_config_averages() {
+    let mut config = Config::new((40, 30), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    let escape = render_config(&config, 2);
+    for coloring in [Coloring::Stripe(5.0), Coloring::Triangle] {
+        config.coloring = coloring;
+        let pixels = render_config(&config, 2);
+        assert_ne!(pixels, escape);
+        // Points inside the set stay black.
+        for (&p, &e) in pixels.iter().zip(&escape) {
+            if e == 0 {
+                assert_eq!(p, 0);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_render_config_light() {
+    let mut config = Config::new((40, 30), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    let flat = render_config(&config, 2);
+    let mut lit = |spec: &str| {
+        config.light = Some(spec.to_string());
+        render_config(&config, 2)
+    };
+    let (from_left, from_right, glossy) = (lit("lambert:180"), lit("lambert:0"), lit("phong:180"));
+    assert_ne!(from_left, flat);
+    for i in 0 .. flat.len() {
+        // Points inside the set stay black, and highlights only brighten.
+        if flat[i] == 0 {
+            assert_eq!(from_left[i], 0);
+        }
+        assert!(glossy[i] >= from_left[i]);
+    }
+    // Left of the set, the surface slopes down to the left, toward a light
+    // from that side and away from one on the other.
+    let left = 5 * 40;
+    assert!(from_left[left] > from_right[left], "{} {}", from_left[left], from_right[left]);
+}
+
+#[test]
+fn test_render_config_dither() {
+    let mut config = Config::new((37, 29), Complex { re: -2.0, im: 1.2 },
+                                 Complex { re: 0.6, im: -1.2 }, "unused.png");
+    config.palette = Some("ocean".to_string());
+    config.limit = 100;
+    let rounded = render_config(&config, 2);
+    for dither in [Dither::FloydSteinberg, Dither::Bayer] {
+        config.dither = dither;
+        let dithered = render_config(&config, 2);
+        assert_ne!(dithered, rounded);
+        // Each sample is still within a level or two of the rounded one...
+        for (&d, &r) in dithered.iter().zip(&rounded) {
+            assert!((d as i32 - r as i32).abs() <= 2, "{:?}: {} vs {}", dither, d, r);
+        }
+        // ...and dithering carries on from band to band.
+        let mut bands = Vec::new();
+        render_config_bands(&config, 1, |band| -> Result<(), ()> {
+            bands.extend_from_slice(band);
+            Ok(())
+        }).unwrap();
+        assert_eq!(bands, dithered);
+    }
+}
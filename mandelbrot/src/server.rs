@@ -0,0 +1,256 @@
+/* Serving Tiles over HTTP
+ * -----------------------
+ * "mandelbrot serve" runs a small HTTP/1.1 server that answers requests for
+ * tiles, rendering each one when it's asked for, plus a static page that lays
+ * the tiles out and lets us pan and zoom around the set in a browser:
+ *
+ *      GET /                        the viewer page
+ *      GET /tiles/{z}/{x}/{y}.png   one tile, as described in tiles.rs
+ *
+ * Rendering a deep tile can take a while, so connections are handed to a
+ * fixed pool of worker threads through a bounded channel. When every worker
+ * is busy and the queue is full, the accepting thread simply waits, and new
+ * connections queue up in the operating system's listen backlog instead of
+ * spawning more and more threads.
+ */
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use png::write_png;
+use tiles::{Tile, TILE_SIZE};
+
+const VIEWER: &str = include_str!("viewer.html");
+
+/// How many accepted connections may wait for a free worker.
+const QUEUE_PER_WORKER: usize = 4;
+
+/// An HTTP response, ready to be written to a connection.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    fn text(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.as_bytes().to_vec()
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _   => "Internal Server Error"
+        }
+    }
+
+    /// Write the status line, headers and body to `out`.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "HTTP/1.1 {} {}\r\n\
+                     Content-Type: {}\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n",
+               self.status, self.reason(), self.content_type, self.body.len())?;
+        out.write_all(&self.body)?;
+        out.flush()
+    }
+}
+
+/// Parse a path of the form `/tiles/{z}/{x}/{y}.png`.
+pub fn parse_tile_path(path: &str) -> Option<Tile> {
+    let rest = path.strip_prefix("/tiles/")?.strip_suffix(".png")?;
+    let mut parts = rest.split('/');
+    let z = parts.next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Tile::new(z, x, y)
+}
+
+#[test]
+fn test_parse_tile_path() {
+    assert_eq!(parse_tile_path("/tiles/0/0/0.png"), Some(Tile { z: 0, x: 0, y: 0 }));
+    assert_eq!(parse_tile_path("/tiles/3/7/2.png"), Some(Tile { z: 3, x: 7, y: 2 }));
+    assert_eq!(parse_tile_path("/tiles/3/8/2.png"), None);
+    assert_eq!(parse_tile_path("/tiles/3/7/2"),     None);
+    assert_eq!(parse_tile_path("/tiles/3/7.png"),   None);
+    assert_eq!(parse_tile_path("/tiles/3/7/2/1.png"), None);
+    assert_eq!(parse_tile_path("/tiles/a/0/0.png"), None);
+}
+
+/// Produce the response to a request for `path` using `method`.
+pub fn respond(method: &str, path: &str) -> Response {
+    if method != "GET" {
+        return Response::text(405, "only GET is supported\n");
+    }
+
+    // Viewers may add a query string to defeat caching; ignore it.
+    let path = path.split('?').next().unwrap_or(path);
+    if path == "/" || path == "/index.html" {
+        return Response {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: VIEWER.as_bytes().to_vec()
+        };
+    }
+
+    match parse_tile_path(path) {
+        Some(tile) => {
+            let pixels = tile.render();
+            let mut body = Vec::new();
+            match write_png(&mut body, &pixels, (TILE_SIZE, TILE_SIZE)) {
+                Ok(()) => Response { status: 200, content_type: "image/png", body },
+                Err(_) => Response::text(500, "error encoding tile\n")
+            }
+        }
+        None => Response::text(404, "not found\n")
+    }
+}
+
+/// Read one request from `stream` and write the response.
+fn handle_connection(stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers; we don't need any of them.
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut words = request_line.split_whitespace();
+    let response = match (words.next(), words.next(), words.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") =>
+            respond(method, path),
+        _ => Response::text(400, "malformed request\n")
+    };
+
+    let mut out = &stream;
+    response.write_to(&mut out)
+}
+
+/// A tile server listening on a socket, not yet accepting connections.
+pub struct Server {
+    listener: TcpListener,
+    workers: usize
+}
+
+impl Server {
+    /// Listen on `addr`, preparing to serve with `workers` rendering threads.
+    pub fn bind<A: ToSocketAddrs>(addr: A, workers: usize) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            workers: workers.max(1)
+        })
+    }
+
+    /// Return the address the server is listening on. Useful when binding
+    /// to port 0 to let the operating system pick a free port.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept and answer connections forever.
+    pub fn run(self) -> io::Result<()> {
+        let (sender, receiver) =
+            mpsc::sync_channel::<TcpStream>(self.workers * QUEUE_PER_WORKER);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0 .. self.workers {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                // Hold the lock only while waiting for the next connection.
+                let next = receiver.lock().unwrap().recv();
+                match next {
+                    Ok(stream) => {
+                        if let Err(err) = handle_connection(stream) {
+                            eprintln!("mandelbrot serve: {}", err);
+                        }
+                    }
+                    Err(_) => break
+                }
+            });
+        }
+
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("mandelbrot serve: {}", err)
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn get(addr: SocketAddr, request: &str) -> (String, Vec<u8>) {
+    use std::io::Read;
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).unwrap();
+
+    let split = reply.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(reply[..split].to_vec()).unwrap();
+    (head, reply[split + 4..].to_vec())
+}
+
+#[test]
+fn test_server_on_localhost() {
+    let server = Server::bind("127.0.0.1:0", 2).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+
+    let (head, body) = get(addr, "GET /tiles/1/0/1.png HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: image/png"));
+    assert!(head.contains(&format!("Content-Length: {}", body.len())));
+    assert_eq!(&body[..8], b"\x89PNG\r\n\x1a\n");
+
+    let (head, body) = get(addr, "GET / HTTP/1.1\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(String::from_utf8(body).unwrap().contains("/tiles/"));
+
+    let (head, _) = get(addr, "GET /tiles/1/2/0.png HTTP/1.1\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 404 "));
+
+    let (head, _) = get(addr, "POST / HTTP/1.1\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 405 "));
+
+    let (head, _) = get(addr, "nonsense\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 400 "));
+
+    // Several clients at once are all answered by the two workers.
+    let clients: Vec<_> = (0 .. 6).map(|x| {
+        thread::spawn(move || {
+            get(addr, &format!("GET /tiles/3/{}/3.png HTTP/1.1\r\n\r\n", x)).0
+        })
+    }).collect();
+    for client in clients {
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -0,0 +1,297 @@
+/* Sixel Graphics
+ * --------------
+ * Many terminals, xterm and mlterm among them, can show images sent as
+ * "sixels": a stream of printable characters, each of which paints a column
+ * of six pixels in one color. "--sixel" writes the image to the terminal
+ * this way instead of to a file, so it shows up inline even over SSH:
+ *
+ *      ESC P q                 start of the image
+ *      "1;1;W;H                square pixels, W by H
+ *      #3;2;R;G;B              define color 3, each channel out of 100
+ *      #3 ~~~!12@ ...          paint with color 3: '?' plus a six-bit
+ *                              pattern per column; "!12@" repeats '@' 12 times
+ *      $                       back to the start of this band, for the next color
+ *      -                       down to the next band of six rows
+ *      ESC \                   end of the image
+ *
+ * A sixel image can have at most 256 colors, so images with more are
+ * quantized by median cut: start with one box around every color the image
+ * uses, keep splitting the box with the widest spread of some channel at its
+ * median, and stand each final box in for its colors by their average.
+ *
+ * Nearby colors that fall in one box all come out the same, so smooth
+ * gradients show bands. With "--dither", pixels instead take palette colors
+ * chosen so that the differences even out over nearby pixels, as dither.rs
+ * does for 8-bit samples: Floyd-Steinberg passes each pixel's difference on
+ * to its neighbors, and Bayer nudges each pixel by its threshold, scaled to
+ * how far apart the palette's colors are, before taking the nearest one.
+ */
+
+use std::collections::HashMap;
+
+use dither::{bayer_threshold, Dither};
+
+/// The most colors a sixel image can define.
+pub const MAX_COLORS: usize = 256;
+
+type Rgb = [u8; 3];
+
+/// Return `colors` along with the widest spread of any channel among them,
+/// and that channel.
+fn with_spread(colors: Vec<(Rgb, usize)>) -> (Vec<(Rgb, usize)>, u8, usize) {
+    let (spread, k) = (0 .. 3).map(|k| {
+        let (low, high) = colors.iter()
+            .fold((255, 0), |(low, high), &(c, _)| (c[k].min(low), c[k].max(high)));
+        (high.saturating_sub(low), k)
+    }).max().unwrap();
+    (colors, spread, k)
+}
+
+/// Choose at most `max_colors` colors for the RGB or grayscale `pixels`, with
+/// `channels` samples per pixel, returning the palette and each pixel's index
+/// into it. If the image has few enough colors, they're kept exactly.
+pub fn quantize(pixels: &[u8], channels: usize, max_colors: usize) -> (Vec<Rgb>, Vec<u8>) {
+    let rgb = |p: &[u8]| if channels == 1 { [p[0]; 3] } else { [p[0], p[1], p[2]] };
+
+    // Count how often each color appears.
+    let mut counts: HashMap<Rgb, usize> = HashMap::new();
+    for pixel in pixels.chunks(channels) {
+        *counts.entry(rgb(pixel)).or_insert(0) += 1;
+    }
+    let mut colors: Vec<(Rgb, usize)> = counts.into_iter().collect();
+    colors.sort();
+
+    // Split boxes of colors until there are enough or none can be split.
+    let mut boxes = vec![with_spread(colors)];
+    while boxes.len() < max_colors {
+        let i = (0 .. boxes.len()).max_by_key(|&i| boxes[i].1).unwrap();
+        let (mut colors, spread, k) = boxes.swap_remove(i);
+        if spread == 0 {
+            boxes.push((colors, spread, k));
+            break;
+        }
+        colors.sort_by_key(|&(c, _)| c[k]);
+        let total: usize = colors.iter().map(|&(_, n)| n).sum();
+        let mut seen = 0;
+        let median = colors.iter()
+            .position(|&(_, n)| { seen += n; seen * 2 >= total })
+            .unwrap_or(0)
+            .min(colors.len() - 2) + 1;
+        let upper = colors.split_off(median);
+        boxes.push(with_spread(colors));
+        boxes.push(with_spread(upper));
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut index: HashMap<Rgb, u8> = HashMap::new();
+    for (i, (colors, _, _)) in boxes.iter().enumerate() {
+        let total: usize = colors.iter().map(|&(_, n)| n).sum();
+        let mut sum = [0usize; 3];
+        for &(c, n) in colors {
+            for k in 0 .. 3 {
+                sum[k] += c[k] as usize * n;
+            }
+            index.insert(c, i as u8);
+        }
+        palette.push(sum.map(|s| ((s + total / 2) / total.max(1)) as u8));
+    }
+    let indices = pixels.chunks(channels).map(|p| index[&rgb(p)]).collect();
+    (palette, indices)
+}
+
+/// Return the index of the color in `palette` nearest `color`.
+fn nearest(palette: &[Rgb], color: [f64; 3]) -> u8 {
+    let distance = |c: &Rgb| (0 .. 3).map(|k| (c[k] as f64 - color[k]).powi(2)).sum::<f64>();
+    (0 .. palette.len()).min_by(|&i, &j| distance(&palette[i]).total_cmp(&distance(&palette[j])))
+        .unwrap() as u8
+}
+
+/// Choose each pixel's color from `palette` as `method` says, for the RGB or
+/// grayscale `pixels`, `width` pixels wide with `channels` samples per pixel,
+/// given `indices`, the nearest choices that `quantize` made. If the palette
+/// holds every pixel's color exactly, those are returned as they are.
+pub fn dither(pixels: &[u8], width: usize, channels: usize, palette: &[Rgb], indices: Vec<u8>,
+              method: Dither)
+    -> Vec<u8>
+{
+    let rgb = |p: &[u8]| if channels == 1 { [p[0]; 3] } else { [p[0], p[1], p[2]] };
+    let exact = pixels.chunks(channels).zip(&indices).all(|(p, &i)| rgb(p) == palette[i as usize]);
+    if exact || method == Dither::None || palette.is_empty() {
+        return indices;
+    }
+
+    let mut chosen = Vec::with_capacity(indices.len());
+    match method {
+        Dither::None => unreachable!(),
+        Dither::Bayer => {
+            // A threshold of one level moves a pixel by about the distance
+            // between neighboring palette colors.
+            let step = palette.iter().map(|a| {
+                palette.iter().filter(|&b| b != a)
+                    .map(|b| (0 .. 3).map(|k| (a[k] as f64 - b[k] as f64).powi(2)).sum::<f64>())
+                    .fold(f64::INFINITY, f64::min).sqrt()
+            }).filter(|d| d.is_finite()).sum::<f64>() / palette.len() as f64;
+            for (i, pixel) in pixels.chunks(channels).enumerate() {
+                let nudge = bayer_threshold(i % width, i / width) * step;
+                chosen.push(nearest(palette, rgb(pixel).map(|c| c as f64 + nudge)));
+            }
+        }
+        Dither::FloydSteinberg => {
+            let mut error = vec![[0.0; 3]; width];
+            for row in pixels.chunks(width * channels) {
+                let mut below = vec![[0.0; 3]; width];
+                for (x, pixel) in row.chunks(channels).enumerate() {
+                    let wanted = [0, 1, 2].map(|k| rgb(pixel)[k] as f64 + error[x][k]);
+                    let index = nearest(palette, wanted);
+                    chosen.push(index);
+                    for k in 0 .. 3 {
+                        let e = wanted[k] - palette[index as usize][k] as f64;
+                        if x + 1 < width {
+                            error[x + 1][k] += e * 7.0 / 16.0;
+                            below[x + 1][k] += e / 16.0;
+                        }
+                        if x > 0 {
+                            below[x - 1][k] += e * 3.0 / 16.0;
+                        }
+                        below[x][k] += e * 5.0 / 16.0;
+                    }
+                }
+                error = below;
+            }
+        }
+    }
+    chosen
+}
+
+/// Add to `out` the run of `count` copies of the sixel character `c`.
+fn push_run(out: &mut String, c: char, count: usize) {
+    if count > 3 {
+        out.push_str(&format!("!{}{}", count, c));
+    } else {
+        for _ in 0 .. count {
+            out.push(c);
+        }
+    }
+}
+
+/// Encode the RGB or grayscale image `pixels`, of size `bounds` with
+/// `channels` samples per pixel, as a sixel stream, dithering as `method`
+/// says if it has too many colors.
+pub fn encode(pixels: &[u8], bounds: (usize, usize), channels: usize, method: Dither) -> String {
+    let (width, height) = bounds;
+    let (palette, indices) = quantize(pixels, channels, MAX_COLORS);
+    let indices = dither(pixels, width, channels, &palette, indices, method);
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", width, height);
+    let percent = |c: u8| (c as u32 * 100 + 127) / 255;
+    for (i, c) in palette.iter().enumerate() {
+        out.push_str(&format!("#{};2;{};{};{}", i, percent(c[0]), percent(c[1]), percent(c[2])));
+    }
+
+    for top in (0 .. height).step_by(6) {
+        let rows = 6.min(height - top);
+        let mut used = vec![false; palette.len()];
+        for &i in &indices[top * width .. (top + rows) * width] {
+            used[i as usize] = true;
+        }
+        let mut first = true;
+        for color in (0 .. palette.len()).filter(|&i| used[i]) {
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            out.push_str(&format!("#{}", color));
+
+            // Each column's bits say which of its six rows have this color.
+            let mut run = ('?', 0);
+            for x in 0 .. width {
+                let mut bits = 0;
+                for y in 0 .. rows {
+                    if indices[(top + y) * width + x] as usize == color {
+                        bits |= 1 << y;
+                    }
+                }
+                let c = (b'?' + bits) as char;
+                if c != run.0 {
+                    push_run(&mut out, run.0, run.1);
+                    run = (c, 0);
+                }
+                run.1 += 1;
+            }
+            // Nothing after the last mark needs painting.
+            if run.0 != '?' {
+                push_run(&mut out, run.0, run.1);
+            }
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+#[test]
+fn test_encode() {
+    // A 5x7 gray image: black, with a white column and a white bottom row.
+    let mut pixels = vec![0; 5 * 7];
+    for y in 0 .. 7 {
+        pixels[y * 5 + 1] = 255;
+    }
+    for x in 0 .. 5 {
+        pixels[6 * 5 + x] = 255;
+    }
+    assert_eq!(encode(&pixels, (5, 7), 1, Dither::FloydSteinberg),
+               "\x1bPq\"1;1;5;7#0;2;0;0;0#1;2;100;100;100\
+                #0~?~~~$#1?~-\
+                #1!5@-\
+                \x1b\\");
+}
+
+#[test]
+fn test_quantize() {
+    // Few enough colors are kept exactly.
+    let rgb = [10, 20, 30, 200, 100, 0, 10, 20, 30];
+    let (palette, indices) = quantize(&rgb, 3, 256);
+    assert_eq!(palette.len(), 2);
+    assert_eq!(palette[indices[0] as usize], [10, 20, 30]);
+    assert_eq!(palette[indices[1] as usize], [200, 100, 0]);
+    assert_eq!(indices[0], indices[2]);
+
+    // Too many are cut down, but stay close.
+    let pixels: Vec<u8> = (0 .. 64 * 64).flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 128])
+        .collect();
+    let (palette, indices) = quantize(&pixels, 3, 16);
+    assert_eq!(palette.len(), 16);
+    for (pixel, &i) in pixels.chunks(3).zip(&indices) {
+        let color = palette[i as usize];
+        for k in 0 .. 3 {
+            assert!((pixel[k] as i32 - color[k] as i32).abs() <= 32, "{:?} {:?}", pixel, color);
+        }
+    }
+}
+
+#[test]
+fn test_dither() {
+    // A gray ramp, shown with only four grays: the nearest color bands, but
+    // dithering keeps the average of each strip of columns close to its level.
+    let (width, height) = (64, 32);
+    let pixels: Vec<u8> = (0 .. width * height).map(|i| (i % width * 4) as u8).collect();
+    let palette = [[0; 3], [85; 3], [170; 3], [255; 3]];
+    let indices: Vec<u8> = pixels.iter().map(|&p| nearest(&palette, [p as f64; 3])).collect();
+    let strip_error = |chosen: &[u8]| (0 .. width / 8).map(|strip| {
+        let columns = strip * 8 .. strip * 8 + 8;
+        let mean = |level: &dyn Fn(usize) -> f64| columns.clone()
+            .flat_map(|x| (0 .. height).map(move |y| y * width + x))
+            .map(level).sum::<f64>() / (8 * height) as f64;
+        (mean(&|i| palette[chosen[i] as usize][0] as f64) - mean(&|i| pixels[i] as f64)).abs()
+    }).fold(0.0, f64::max);
+
+    assert!(strip_error(&indices) > 25.0);
+    let floyd = dither(&pixels, width, 1, &palette, indices.clone(), Dither::FloydSteinberg);
+    assert!(strip_error(&floyd) < 4.0, "{}", strip_error(&floyd));
+    let bayer = dither(&pixels, width, 1, &palette, indices.clone(), Dither::Bayer);
+    assert!(strip_error(&bayer) < 16.0, "{}", strip_error(&bayer));
+
+    // A palette that holds every color leaves nothing to spread.
+    let (palette, indices) = quantize(&pixels, 1, 256);
+    assert_eq!(dither(&pixels, width, 1, &palette, indices.clone(), Dither::Bayer), indices);
+}
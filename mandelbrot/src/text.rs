@@ -0,0 +1,153 @@
+/* Text Output
+ * -----------
+ * "mandelbrot text" plots the set with characters instead of pixels, for
+ * logs, tests, and terminals with no way to show an image. Each character
+ * stands for one point, and the longer its orbit took to escape, the darker
+ * the character it gets from a "ramp", lightest first:
+ *
+ *      ascii       " .:-=+*#%@"
+ *      unicode     " ░▒▓█", the block shades
+ *
+ * Points that never escape get the darkest character. A character cell is
+ * about twice as tall as it is wide, so each one covers twice as much of the
+ * plane vertically as horizontally, and the set keeps its shape.
+ */
+
+use config::Config;
+use orbit::Measures;
+
+/// The characters to plot with, lightest first.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Ramp {
+    Ascii,
+    Unicode
+}
+
+impl Ramp {
+    pub fn parse(s: &str) -> Option<Ramp> {
+        match s {
+            "ascii"   => Some(Ramp::Ascii),
+            "unicode" => Some(Ramp::Unicode),
+            _         => None
+        }
+    }
+
+    pub fn chars(&self) -> Vec<char> {
+        match *self {
+            Ramp::Ascii   => " .:-=+*#%@".chars().collect(),
+            Ramp::Unicode => " \u{2591}\u{2592}\u{2593}\u{2588}".chars().collect()
+        }
+    }
+}
+
+/// Fit the view `config` shows to `columns` by `rows` characters, keeping
+/// its center and zoom, with each character twice as tall as it is wide.
+pub fn fit(config: &mut Config, columns: usize, rows: usize) {
+    let (upper_left, lower_right) = config.view().corners((columns, rows * 2));
+    config.bounds = (columns, rows);
+    config.upper_left = upper_left;
+    config.lower_right = lower_right;
+}
+
+/// Plot what `config` describes as text, one character per pixel, with a
+/// newline after each row.
+pub fn render_text(config: &Config, ramp: Ramp) -> String {
+    let chars = ramp.chars();
+    let darkest = chars.len() - 1;
+    let (columns, rows) = config.bounds;
+    let mut text = String::with_capacity((columns + 1) * rows);
+    for row in 0 .. rows {
+        for column in 0 .. columns {
+            let point = config.pixel_to_point(config.bounds, (column, row));
+            let sample = config.fractal.sample(point, config.limit, &Measures::default(),
+                                               config.precision);
+            let index = match sample.count {
+                None => darkest,
+                Some(count) => (count as u64 * darkest as u64 / config.limit as u64) as usize
+            };
+            text.push(chars[index.min(darkest)]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+fn test_config(columns: usize, rows: usize, limit: u32) -> Config {
+    use num::Complex;
+    use view::View;
+
+    let view = View::new(Complex { re: -0.75, im: 0.0 }, 1.25);
+    let (upper_left, lower_right) = view.corners((columns, rows * 2));
+    let mut config = Config::new((columns, rows), upper_left, lower_right, "unused.png");
+    config.limit = limit;
+    config
+}
+
+/// Return the lines of an expected plot, each ended with a newline. Written
+/// this way, a failing test shows which rows changed.
+#[cfg(test)]
+fn golden(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[test]
+fn test_render_text_ascii() {
+    let config = test_config(48, 16, 30);
+    assert_eq!(render_text(&config, Ramp::Ascii), golden(&[
+        "                                                ",
+        "                              .:..              ",
+        "                             ..:-:..            ",
+        "                          ....:@@@:...          ",
+        "                      ....#@-@@@@@@@:--.        ",
+        "                  .......*+@@@@@@@@@@@-..       ",
+        "               ..:=--%-::@@@@@@@@@@@@@@=.       ",
+        "             ...::@@@@@@*@@@@@@@@@@@@@@:.       ",
+        "        @@@@@@@@@@@@@@@@@@@@@@@@@@@@@*:..       ",
+        "             ...::@@@@@@*@@@@@@@@@@@@@@:.       ",
+        "               ..:=--%-::@@@@@@@@@@@@@@=.       ",
+        "                  .......*+@@@@@@@@@@@-..       ",
+        "                      ....#@-@@@@@@@:--.        ",
+        "                          ....:@@@:...          ",
+        "                             ..:-:..            ",
+        "                              .:..              "
+    ]));
+}
+
+#[test]
+fn test_render_text_unicode_julia() {
+    use fractal::Fractal;
+    use num::Complex;
+    use view::View;
+
+    let mut config = test_config(32, 10, 40);
+    config.fractal = Fractal::Julia(Complex { re: -0.8, im: 0.156 });
+    config.set_view(View::new(Complex { re: 0.0, im: 0.0 }, 0.9));
+    fit(&mut config, 32, 10);
+    assert_eq!(render_text(&config, Ramp::Unicode), golden(&[
+        "                                ",
+        "               ██               ",
+        "           ███░░▒█░▒            ",
+        "     ███  ░████████▒            ",
+        " ▒▓█▒███░▓▒▒▒▒▒████▓  ████▒█    ",
+        " ▒██░███▒▒▒█░░▒███▒░░█▒▒▒███░██▒",
+        "     █▒████  ▓████▒▒▒▒▒▓░███▒█▓▒",
+        "             ▒████████░  ███    ",
+        "             ▒░█▒░░███          ",
+        "                ██              "
+    ]));
+}
+
+#[test]
+fn test_fit() {
+    let mut config = test_config(10, 5, 30);
+    let view = config.view();
+    fit(&mut config, 40, 8);
+    assert_eq!(config.bounds, (40, 8));
+    assert_eq!(config.view().center, view.center);
+    // Eight rows of characters are sixteen square pixels tall.
+    assert_eq!(config.view().radius, view.radius);
+    assert_eq!(config.lower_right.re - config.upper_left.re,
+               (config.upper_left.im - config.lower_right.im) * 2.5);
+}
+
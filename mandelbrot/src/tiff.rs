@@ -0,0 +1,249 @@
+/* TIFF Files
+ * ----------
+ * A TIFF file starts with a byte-order mark and the offset of its first
+ * "image file directory", a list of tagged fields describing the image. The
+ * fields can point anywhere in the file, so we write the header first, then
+ * the pixels as they arrive, and finally the directory after them:
+ *
+ *      "MM", 42, directory offset      big-endian, like our 16-bit samples
+ *      strips of rows                  uncompressed
+ *      directory                       width, height, bits per sample, ...
+ *      values too long for the directory
+ *
+ * The image is cut into strips of a fixed number of rows, as readers prefer,
+ * but since they're stored one after another, their offsets are easy to
+ * work out. We write just the fields a baseline TIFF reader requires, for
+ * 8- or 16-bit grayscale or RGB, which suits print work.
+ */
+
+use std::io::{self, Write};
+
+use image::ImageWriter;
+use metadata;
+#[cfg(test)]
+use png::Image;
+
+/// How many rows each strip holds.
+const ROWS_PER_STRIP: usize = 16;
+
+/// Field types.
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+/// The tags we write or read.
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP_TAG: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const X_RESOLUTION: u16 = 282;
+const Y_RESOLUTION: u16 = 283;
+const PLANAR_CONFIGURATION: u16 = 284;
+const RESOLUTION_UNIT: u16 = 296;
+const SOFTWARE: u16 = 305;
+
+/// Writes a TIFF image to `W` a few rows at a time.
+pub struct TiffWriter<W: Write> {
+    out: W,
+    bounds: (usize, usize),
+    channels: usize,
+    depth: u8
+}
+
+impl<W: Write> TiffWriter<W> {
+    /// Start writing an image of size `bounds` to `out`, in grayscale if
+    /// `channels` is one or RGB if it is three, with samples of `depth`
+    /// bits, 8 or 16, big-endian.
+    pub fn new(out: W, bounds: (usize, usize), channels: usize, depth: u8)
+        -> io::Result<TiffWriter<W>>
+    {
+        let mut writer = TiffWriter { out, bounds, channels, depth };
+        let offset = writer.directory_offset();
+        if offset > u32::MAX as usize - 4096 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "image is too large for a TIFF file"));
+        }
+        writer.out.write_all(b"MM\0\x2a")?;
+        writer.out.write_all(&(offset as u32).to_be_bytes())?;
+        Ok(writer)
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.bounds.0 * self.channels * self.depth as usize / 8
+    }
+
+    /// Return where the directory goes: after the pixels, on a word boundary.
+    fn directory_offset(&self) -> usize {
+        (8 + self.row_bytes() * self.bounds.1 + 1) & !1
+    }
+
+    /// Return the directory and the values that follow it.
+    fn directory(&self) -> Vec<u8> {
+        let (width, height) = self.bounds;
+        let strip_bytes = self.row_bytes() * ROWS_PER_STRIP;
+        let strips = height.div_ceil(ROWS_PER_STRIP);
+        let offsets: Vec<u32> = (0 .. strips).map(|i| (8 + i * strip_bytes) as u32).collect();
+        let counts: Vec<u32> = (0 .. strips)
+            .map(|i| (ROWS_PER_STRIP.min(height - i * ROWS_PER_STRIP) * self.row_bytes()) as u32)
+            .collect();
+        let shorts = |values: &[u16]| values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let longs = |values: &[u32]| values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let dpi = longs(&[72, 1]);
+        let mut software = metadata::software().into_bytes();
+        software.push(0);
+
+        let fields: Vec<(u16, u16, usize, Vec<u8>)> = vec![
+            (IMAGE_WIDTH, LONG, 1, longs(&[width as u32])),
+            (IMAGE_LENGTH, LONG, 1, longs(&[height as u32])),
+            (BITS_PER_SAMPLE, SHORT, self.channels, shorts(&vec![self.depth as u16; self.channels])),
+            (COMPRESSION, SHORT, 1, shorts(&[1])),
+            (PHOTOMETRIC, SHORT, 1, shorts(&[if self.channels == 1 { 1 } else { 2 }])),
+            (STRIP_OFFSETS, LONG, strips, longs(&offsets)),
+            (SAMPLES_PER_PIXEL, SHORT, 1, shorts(&[self.channels as u16])),
+            (ROWS_PER_STRIP_TAG, LONG, 1, longs(&[ROWS_PER_STRIP as u32])),
+            (STRIP_BYTE_COUNTS, LONG, strips, longs(&counts)),
+            (X_RESOLUTION, RATIONAL, 1, dpi.clone()),
+            (Y_RESOLUTION, RATIONAL, 1, dpi),
+            (PLANAR_CONFIGURATION, SHORT, 1, shorts(&[1])),
+            (RESOLUTION_UNIT, SHORT, 1, shorts(&[2])),     // inches
+            (SOFTWARE, ASCII, software.len(), software)
+        ];
+
+        // Values of more than four bytes go after the directory.
+        let start = self.directory_offset();
+        let mut extra_offset = start + 2 + fields.len() * 12 + 4;
+        let mut directory = (fields.len() as u16).to_be_bytes().to_vec();
+        let mut extra = Vec::new();
+        for (tag, kind, count, mut value) in fields {
+            directory.extend_from_slice(&tag.to_be_bytes());
+            directory.extend_from_slice(&kind.to_be_bytes());
+            directory.extend_from_slice(&(count as u32).to_be_bytes());
+            if value.len() <= 4 {
+                value.resize(4, 0);
+                directory.extend_from_slice(&value);
+            } else {
+                directory.extend_from_slice(&(extra_offset as u32).to_be_bytes());
+                if value.len() % 2 == 1 {
+                    value.push(0);
+                }
+                extra_offset += value.len();
+                extra.extend_from_slice(&value);
+            }
+        }
+        directory.extend_from_slice(&[0; 4]);       // no more directories
+        directory.extend_from_slice(&extra);
+        directory
+    }
+}
+
+impl<W: Write> ImageWriter for TiffWriter<W> {
+    fn write_rows(&mut self, rows: &[u8]) -> io::Result<()> {
+        self.out.write_all(rows)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if (self.row_bytes() * self.bounds.1) % 2 == 1 {
+            self.out.write_all(&[0])?;
+        }
+        let directory = self.directory();
+        self.out.write_all(&directory)?;
+        self.out.flush()
+    }
+}
+
+/// Decode the uncompressed grayscale or RGB TIFF file `file`, of either byte
+/// order.
+#[cfg(test)]
+pub fn read(file: &[u8]) -> io::Result<Image> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let big = match file.get(.. 4) {
+        Some(b"MM\0\x2a") => true,
+        Some(b"II\x2a\0") => false,
+        _ => return Err(invalid("not a TIFF file"))
+    };
+    let u16_at = |i: usize| -> io::Result<u32> {
+        let b = file.get(i .. i + 2).ok_or_else(|| invalid("TIFF file is truncated"))?;
+        let b = [b[0], b[1]];
+        Ok(if big { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) } as u32)
+    };
+    let u32_at = |i: usize| -> io::Result<u32> {
+        let b = file.get(i .. i + 4).ok_or_else(|| invalid("TIFF file is truncated"))?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if big { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    };
+
+    // Gather the SHORT and LONG fields of the first directory.
+    let start = u32_at(4)? as usize;
+    let mut fields: Vec<(u16, Vec<u32>)> = Vec::new();
+    for i in 0 .. u16_at(start)? as usize {
+        let entry = start + 2 + i * 12;
+        let (tag, kind, count) = (u16_at(entry)? as u16, u16_at(entry + 2)? as u16,
+                                  u32_at(entry + 4)? as usize);
+        let size = match kind { SHORT => 2, LONG => 4, _ => continue };
+        let at = if size * count <= 4 { entry + 8 } else { u32_at(entry + 8)? as usize };
+        let values = (0 .. count)
+            .map(|j| if kind == SHORT { u16_at(at + j * 2) } else { u32_at(at + j * 4) })
+            .collect::<io::Result<Vec<u32>>>()?;
+        fields.push((tag, values));
+    }
+    let field = |tag: u16| -> io::Result<&Vec<u32>> {
+        fields.iter().find(|f| f.0 == tag).map(|f| &f.1)
+            .ok_or_else(|| invalid("TIFF file lacks a required field"))
+    };
+
+    let bounds = (field(IMAGE_WIDTH)?[0] as usize, field(IMAGE_LENGTH)?[0] as usize);
+    let channels = field(SAMPLES_PER_PIXEL).map(|v| v[0] as usize).unwrap_or(1);
+    let depth = field(BITS_PER_SAMPLE)?[0];
+    if field(COMPRESSION).map(|v| v[0]).unwrap_or(1) != 1 {
+        return Err(invalid("compressed TIFF files are not supported"));
+    }
+    if !(channels == 1 || channels == 3) || !(depth == 8 || depth == 16) {
+        return Err(invalid("unsupported TIFF image type"));
+    }
+
+    let mut samples = Vec::new();
+    for (&offset, &count) in field(STRIP_OFFSETS)?.iter().zip(field(STRIP_BYTE_COUNTS)?) {
+        let strip = file.get(offset as usize .. (offset + count) as usize)
+            .ok_or_else(|| invalid("TIFF file is truncated"))?;
+        samples.extend_from_slice(strip);
+    }
+    if samples.len() != bounds.0 * bounds.1 * channels * depth as usize / 8 {
+        return Err(invalid("wrong amount of image data"));
+    }
+    if depth == 16 && !big {
+        for pair in samples.chunks_mut(2) {
+            pair.swap(0, 1);
+        }
+    }
+    Ok(Image { bounds, channels, depth: depth as u8, pixels: samples })
+}
+
+#[test]
+fn test_round_trip() {
+    // Enough rows for a short last strip, and an odd number of bytes.
+    let bounds = (5, 37);
+    let gray: Vec<u8> = (0 .. bounds.0 * bounds.1).map(|i| (i * 7) as u8).collect();
+    let mut file = Vec::new();
+    let mut writer = Box::new(TiffWriter::new(&mut file, bounds, 1, 8).unwrap());
+    for band in gray.chunks(bounds.0 * 10) {
+        writer.write_rows(band).unwrap();
+    }
+    writer.finish().unwrap();
+    assert_eq!(&file[.. 4], b"MM\0\x2a");
+    assert_eq!(read(&file).unwrap(), Image { bounds, channels: 1, depth: 8, pixels: gray });
+
+    let rgb16: Vec<u8> = (0 .. bounds.0 * bounds.1 * 6).map(|i| (i * 13 % 251) as u8).collect();
+    let mut file = Vec::new();
+    let mut writer = Box::new(TiffWriter::new(&mut file, bounds, 3, 16).unwrap());
+    writer.write_rows(&rgb16).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(read(&file).unwrap(), Image { bounds, channels: 3, depth: 16, pixels: rgb16 });
+
+    assert!(read(b"GIF89a").is_err());
+}
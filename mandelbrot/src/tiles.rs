@@ -0,0 +1,90 @@
+/* Tiles
+ * -----
+ * Interactive viewers don't ask for one big image; they ask for small square
+ * tiles, addressed the same way web maps address them: at zoom level "z" the
+ * plane is cut into 2^z by 2^z tiles, and "x" and "y" count tiles from the
+ * left and from the top. Zoom level 0 is a single tile showing the whole set.
+ *
+ *      z = 0           z = 1
+ *      +-------+       +---+---+
+ *      | 0,0   |       |0,0|1,0|
+ *      |       |       +---+---+
+ *      +-------+       |0,1|1,1|
+ *                      +---+---+
+ */
+
+use num::Complex;
+use render::render;
+
+/// The width and height of every tile, in pixels.
+pub const TILE_SIZE: usize = 256;
+
+/// The deepest zoom level we serve. Beyond this, neighbouring pixels are too
+/// close together for `f64` to tell them apart.
+pub const MAX_ZOOM: u32 = 32;
+
+/// The upper-left corner of the square that zoom level 0 covers.
+const WORLD_UPPER_LEFT: Complex<f64> = Complex { re: -2.5, im: 2.0 };
+
+/// The length of a side of the square that zoom level 0 covers.
+const WORLD_SIZE: f64 = 4.0;
+
+/// The address of one tile.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32
+}
+
+impl Tile {
+    /// Return the tile at zoom `z`, column `x` and row `y`, or `None` if
+    /// there is no such tile.
+    pub fn new(z: u32, x: u32, y: u32) -> Option<Tile> {
+        if z > MAX_ZOOM || x as u64 >= 1 << z || y as u64 >= 1 << z {
+            return None;
+        }
+        Some(Tile { z, x, y })
+    }
+
+    /// Return the upper-left and lower-right corners of the region of the
+    /// complex plane this tile covers.
+    pub fn corners(&self) -> (Complex<f64>, Complex<f64>) {
+        let side = WORLD_SIZE / (1u64 << self.z) as f64;
+        let upper_left = Complex {
+            re: WORLD_UPPER_LEFT.re + self.x as f64 * side,
+            im: WORLD_UPPER_LEFT.im - self.y as f64 * side
+        };
+        let lower_right = Complex {
+            re: upper_left.re + side,
+            im: upper_left.im - side
+        };
+        (upper_left, lower_right)
+    }
+
+    /// Render this tile into a new `TILE_SIZE` by `TILE_SIZE` grayscale
+    /// buffer.
+    pub fn render(&self) -> Vec<u8> {
+        let mut pixels = vec![0; TILE_SIZE * TILE_SIZE];
+        let (upper_left, lower_right) = self.corners();
+        render(&mut pixels, (TILE_SIZE, TILE_SIZE), upper_left, lower_right);
+        pixels
+    }
+}
+
+#[test]
+fn test_tile_new() {
+    assert_eq!(Tile::new(0, 0, 0), Some(Tile { z: 0, x: 0, y: 0 }));
+    assert_eq!(Tile::new(0, 1, 0), None);
+    assert_eq!(Tile::new(2, 3, 3), Some(Tile { z: 2, x: 3, y: 3 }));
+    assert_eq!(Tile::new(2, 0, 4), None);
+    assert_eq!(Tile::new(MAX_ZOOM + 1, 0, 0), None);
+}
+
+#[test]
+fn test_tile_corners() {
+    assert_eq!(Tile { z: 0, x: 0, y: 0 }.corners(),
+               (Complex { re: -2.5, im: 2.0 }, Complex { re: 1.5, im: -2.0 }));
+    assert_eq!(Tile { z: 1, x: 1, y: 1 }.corners(),
+               (Complex { re: -0.5, im: 0.0 }, Complex { re: 1.5, im: -2.0 }));
+}
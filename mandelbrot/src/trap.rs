@@ -0,0 +1,100 @@
+/* Orbit Traps
+ * -----------
+ * Instead of asking how long a point's orbit took to escape, we can ask how
+ * close it came to some shape, the "trap", and color the point by that
+ * distance. Orbits that pass near the trap light up, drawing copies of the
+ * shape, bent and shrunk, all through the picture, inside the set as well as
+ * out. The shapes, as "--trap" and "render.trap" write them:
+ *
+ *      point:RE,IM             a single point
+ *      line:RE,IM,DEGREES      the line through a point, at an angle
+ *                              counterclockwise from the real axis
+ *      circle:RE,IM,RADIUS     a circle about a point
+ *      cross:RE,IM             the horizontal and vertical lines through a point
+ *
+ * The point may be left out, in which case it's the origin: "circle:0.5" is
+ * the circle of radius one half about zero, and "cross" is the two axes.
+ */
+
+use num::Complex;
+
+/// A shape to measure orbits against.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Trap {
+    Point(Complex<f64>),
+    /// The line through a point, at an angle in degrees.
+    Line(Complex<f64>, f64),
+    /// A circle about a point, with a radius.
+    Circle(Complex<f64>, f64),
+    Cross(Complex<f64>)
+}
+
+impl Trap {
+    /// Parse a trap written as described above.
+    pub fn parse(s: &str) -> Option<Trap> {
+        let (shape, numbers) = match s.split_once(':') {
+            Some((shape, numbers)) => (shape.trim(), numbers),
+            None => (s.trim(), "")
+        };
+        let numbers: Vec<f64> = if numbers.trim().is_empty() {
+            Vec::new()
+        } else {
+            numbers.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?
+        };
+        if numbers.iter().any(|n| !n.is_finite()) {
+            return None;
+        }
+        let origin = Complex { re: 0.0, im: 0.0 };
+        let point = |re: f64, im: f64| Complex { re, im };
+        Some(match (shape, &numbers[..]) {
+            ("point", []) => Trap::Point(origin),
+            ("point", &[re, im]) => Trap::Point(point(re, im)),
+            ("line", &[degrees]) => Trap::Line(origin, degrees),
+            ("line", &[re, im, degrees]) => Trap::Line(point(re, im), degrees),
+            ("circle", &[radius]) if radius > 0.0 => Trap::Circle(origin, radius),
+            ("circle", &[re, im, radius]) if radius > 0.0 => Trap::Circle(point(re, im), radius),
+            ("cross", []) => Trap::Cross(origin),
+            ("cross", &[re, im]) => Trap::Cross(point(re, im)),
+            _ => return None
+        })
+    }
+
+    /// Return the distance from `z` to the trap.
+    pub fn distance(&self, z: Complex<f64>) -> f64 {
+        match *self {
+            Trap::Point(p) => (z - p).norm(),
+            Trap::Line(p, degrees) => {
+                // The part of z - p perpendicular to the line's direction.
+                let (sin, cos) = degrees.to_radians().sin_cos();
+                let d = z - p;
+                (d.im * cos - d.re * sin).abs()
+            }
+            Trap::Circle(p, radius) => ((z - p).norm() - radius).abs(),
+            Trap::Cross(p) => (z.re - p.re).abs().min((z.im - p.im).abs())
+        }
+    }
+}
+
+#[test]
+fn test_trap_parse() {
+    let point = |re, im| Complex { re, im };
+    assert_eq!(Trap::parse("point"), Some(Trap::Point(point(0.0, 0.0))));
+    assert_eq!(Trap::parse("point:-0.5, 0.25"), Some(Trap::Point(point(-0.5, 0.25))));
+    assert_eq!(Trap::parse("line:45"), Some(Trap::Line(point(0.0, 0.0), 45.0)));
+    assert_eq!(Trap::parse("circle:1,2,0.5"), Some(Trap::Circle(point(1.0, 2.0), 0.5)));
+    assert_eq!(Trap::parse("cross:1,-1"), Some(Trap::Cross(point(1.0, -1.0))));
+    for bad in ["", "star", "point:1", "circle:0", "circle:1,2,-3", "line", "cross:a,b"] {
+        assert_eq!(Trap::parse(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn test_trap_distance() {
+    let z = Complex { re: 3.0, im: 4.0 };
+    let origin = Complex { re: 0.0, im: 0.0 };
+    assert_eq!(Trap::Point(origin).distance(z), 5.0);
+    assert_eq!(Trap::Circle(origin, 2.0).distance(z), 3.0);
+    assert_eq!(Trap::Cross(Complex { re: 1.0, im: 1.0 }).distance(z), 2.0);
+    assert_eq!(Trap::Line(origin, 0.0).distance(z), 4.0);
+    assert!((Trap::Line(origin, 90.0).distance(z) - 3.0).abs() < 1e-12);
+}
@@ -0,0 +1,346 @@
+/* Viewing in the Terminal
+ * -----------------------
+ * "mandelbrot tui" draws the set right in the terminal, for machines we can
+ * only reach over SSH. Each character cell shows two pixels, one above the
+ * other: the upper half block "▀" in the top pixel's color, on a background
+ * of the bottom pixel's color, both given as 24-bit "truecolor" escapes:
+ *
+ *      ESC[38;2;R;G;Bm  ESC[48;2;R;G;Bm  ▀
+ *
+ * Since a cell is about twice as tall as it is wide, the pixels come out
+ * roughly square. The keys are:
+ *
+ *      arrows, h j k l     pan an eighth of the screen
+ *      + or =, -           zoom in or out by two
+ *      ], [                double or halve the iteration limit
+ *      p                   next palette, then back to grayscale
+ *      J                   Julia set for the point at the center, and back
+ *      q, Esc              quit
+ *
+ * After every key the view is rendered again at the terminal's current size,
+ * and the bottom line shows the options that render this view with
+ * "mandelbrot FILE PIXELS ... OPTIONS", ready to copy. They're printed again
+ * on the way out.
+ *
+ * To see keys as they're typed we put the terminal in raw mode with "stty",
+ * which also tells us its size, and restore its settings when we're done,
+ * however we leave.
+ */
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use config::{Coloring, Config};
+use fixed::Precision;
+use fractal::Fractal;
+use num::Complex;
+use palette::NAMED;
+use render::render_config;
+use view::View;
+
+/// What a key press asks for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Key {
+    /// Pan by this many eighths of the screen, right and down.
+    Pan(i32, i32),
+    ZoomIn,
+    ZoomOut,
+    MoreIterations,
+    FewerIterations,
+    NextPalette,
+    ToggleJulia,
+    Quit
+}
+
+/// Return the keys in `input`, bytes read from a terminal in raw mode,
+/// ignoring any we don't use.
+pub fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let key = match input[i] {
+            0x1b if input.get(i + 1) == Some(&b'[') && i + 2 < input.len() => {
+                i += 2;
+                match input[i] {
+                    b'A' => Some(Key::Pan(0, -1)),
+                    b'B' => Some(Key::Pan(0, 1)),
+                    b'C' => Some(Key::Pan(1, 0)),
+                    b'D' => Some(Key::Pan(-1, 0)),
+                    _ => None
+                }
+            }
+            0x1b | b'q' | 3 => Some(Key::Quit),     // 3 is ctrl-C, raw
+            b'h' => Some(Key::Pan(-1, 0)),
+            b'j' => Some(Key::Pan(0, 1)),
+            b'k' => Some(Key::Pan(0, -1)),
+            b'l' => Some(Key::Pan(1, 0)),
+            b'+' | b'=' => Some(Key::ZoomIn),
+            b'-' => Some(Key::ZoomOut),
+            b']' => Some(Key::MoreIterations),
+            b'[' => Some(Key::FewerIterations),
+            b'p' => Some(Key::NextPalette),
+            b'J' => Some(Key::ToggleJulia),
+            _ => None
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+/// The iteration limits `]` and `[` stay between.
+const LIMITS: (u32, u32) = (16, 1 << 20);
+
+/// What the viewer is showing.
+pub struct Viewer {
+    pub config: Config,
+    /// The Mandelbrot view to return to when leaving a Julia set.
+    mandelbrot_view: Option<View>
+}
+
+impl Viewer {
+    pub fn new(config: Config) -> Viewer {
+        Viewer { config, mandelbrot_view: None }
+    }
+
+    /// Fit the view to an image of size `bounds`, keeping its center and zoom.
+    pub fn resize(&mut self, bounds: (usize, usize)) {
+        let view = self.config.view();
+        self.config.bounds = bounds;
+        self.config.set_view(view);
+    }
+
+    /// Act on `key`, returning false if it asks to quit.
+    pub fn apply(&mut self, key: Key) -> bool {
+        let config = &mut self.config;
+        let view = config.view();
+        match key {
+            Key::Pan(right, down) => {
+                // Moving the center to another pixel pans along the turned axes.
+                let (width, height) = config.bounds;
+                let column = width as i64 / 2 + right as i64 * width as i64 / 8;
+                let row = height as i64 / 2 + down as i64 * height as i64 / 8;
+                let center = view.pixel_to_point(config.bounds,
+                                                 (column.max(0) as usize, row.max(0) as usize));
+                config.set_view(View { center, ..view });
+            }
+            Key::ZoomIn => config.set_view(View { radius: view.radius / 2.0, ..view }),
+            Key::ZoomOut => config.set_view(View { radius: view.radius * 2.0, ..view }),
+            Key::MoreIterations => {
+                // A limit given beyond the range is kept, never lowered.
+                config.limit = config.limit.saturating_mul(2).min(LIMITS.1).max(config.limit)
+            }
+            Key::FewerIterations => config.limit = (config.limit / 2).max(LIMITS.0),
+            Key::NextPalette => {
+                let names: Vec<&str> = NAMED.iter().map(|&(name, _)| name).collect();
+                let next = match config.palette {
+                    None => Some(names[0]),
+                    Some(ref current) => names.iter()
+                        .position(|name| name == current)
+                        .and_then(|i| names.get(i + 1))
+                        .cloned()
+                };
+                config.palette = next.map(str::to_string);
+            }
+            Key::ToggleJulia => match self.mandelbrot_view.take() {
+                Some(previous) => {
+                    config.fractal = Fractal::Mandelbrot;
+                    config.set_view(previous);
+                }
+                None => {
+                    self.mandelbrot_view = Some(view);
+                    config.fractal = Fractal::Julia(view.center);
+                    config.set_view(View::new(Complex { re: 0.0, im: 0.0 }, 1.5));
+                }
+            },
+            Key::Quit => return false
+        }
+        true
+    }
+}
+
+/// Return the command line options that render what `config` shows.
+pub fn options(config: &Config) -> String {
+    let view = config.view();
+    let mut text = format!("--center {:?},{:?} --zoom {:?}",
+                           view.center.re, view.center.im, view.zoom());
+    if view.rotation != 0.0 {
+        text += &format!(" --rotate {:?}", view.rotation);
+    }
+    text += &format!(" --limit {}", config.limit);
+    if let Some(ref palette) = config.palette {
+        text += &format!(" --palette {}", palette);
+    }
+    if let Some(ref trap) = config.trap {
+        text += &format!(" --trap {}", trap);
+    }
+    if config.coloring != Coloring::Escape {
+        text += &format!(" --coloring {}", config.coloring.spec());
+    }
+    if let Some(ref light) = config.light {
+        text += &format!(" --light {}", light);
+    }
+    if config.precision != Precision::Double {
+        text += &format!(" --precision {}", config.precision.name());
+    }
+    if let Some(ref overlay) = config.overlay {
+        text += &format!(" --overlay {}", overlay.replace(' ', ""));
+    }
+    if let Some(ref title) = config.title {
+        text += &format!(" --title {:?}", title);
+    }
+    if let Fractal::Julia(c) = config.fractal {
+        text += &format!(" --julia {:?},{:?}", c.re, c.im);
+    }
+    text
+}
+
+/// Return the escapes that draw `pixels`, an 8-bit image of size `bounds`
+/// with `channels` samples per pixel, two rows to a line of text, from the
+/// top left of the screen.
+pub fn draw(pixels: &[u8], bounds: (usize, usize), channels: usize) -> String {
+    let (width, height) = bounds;
+    let rgb = |x: usize, y: usize| {
+        let p = &pixels[(y * width + x) * channels ..][.. channels];
+        if channels == 1 { (p[0], p[0], p[0]) } else { (p[0], p[1], p[2]) }
+    };
+    let mut text = String::from("\x1b[H");
+    for y in (0 .. height).step_by(2) {
+        for x in 0 .. width {
+            let (r, g, b) = rgb(x, y);
+            text += &format!("\x1b[38;2;{};{};{}m", r, g, b);
+            if y + 1 < height {
+                let (r, g, b) = rgb(x, y + 1);
+                text += &format!("\x1b[48;2;{};{};{}m", r, g, b);
+            } else {
+                text += "\x1b[49m";
+            }
+            text += "\u{2580}";
+        }
+        text += "\x1b[0m\r\n";
+    }
+    text
+}
+
+/// Run "stty" on the terminal with `args`, returning what it prints.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("standard input is not a terminal"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Return the terminal's size, as columns and rows of text.
+fn terminal_size() -> io::Result<(usize, usize)> {
+    let size = stty(&["size"])?;
+    let mut numbers = size.split_whitespace().filter_map(|n| n.parse().ok());
+    match (numbers.next(), numbers.next()) {
+        (Some(rows), Some(columns)) => Ok((columns, rows)),
+        _ => Err(io::Error::other("can't tell the terminal's size"))
+    }
+}
+
+/// Puts the terminal back the way it was when dropped.
+struct RawMode {
+    saved: String
+}
+
+impl RawMode {
+    fn enter() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[?1049h\x1b[?25l");         // alternate screen, no cursor
+        io::stdout().flush()?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Show what `config` describes in the terminal, rendering with `threads`
+/// threads, until the user quits. Returns the options for the last view.
+pub fn run(config: Config, threads: usize) -> io::Result<String> {
+    let mut viewer = Viewer::new(Config { depth: 8, ..config });
+    let raw = RawMode::enter()?;
+    let mut stdin = io::stdin();
+    let mut input = [0; 64];
+
+    loop {
+        let (columns, rows) = terminal_size()?;
+        viewer.resize((columns.max(1), rows.saturating_sub(1).max(1) * 2));
+        let config = &viewer.config;
+        let pixels = render_config(config, threads);
+        let mut status = options(config);
+        status.truncate(columns);
+        let mut out = io::stdout();
+        write!(out, "{}\x1b[2K{}", draw(&pixels, config.bounds, config.channels()), status)?;
+        out.flush()?;
+
+        let n = stdin.read(&mut input)?;
+        if n == 0 || !parse_keys(&input[.. n]).into_iter().all(|key| viewer.apply(key)) {
+            break;
+        }
+    }
+    drop(raw);
+    Ok(options(&viewer.config))
+}
+
+#[test]
+fn test_parse_keys() {
+    assert_eq!(parse_keys(b"\x1b[A\x1b[Dl+-x]p"),
+               [Key::Pan(0, -1), Key::Pan(-1, 0), Key::Pan(1, 0), Key::ZoomIn, Key::ZoomOut,
+                Key::MoreIterations, Key::NextPalette]);
+    assert_eq!(parse_keys(b"\x1b"), [Key::Quit]);
+    assert_eq!(parse_keys(b"Jq"), [Key::ToggleJulia, Key::Quit]);
+}
+
+#[test]
+fn test_viewer_keys() {
+    let view = View::new(Complex { re: -0.5, im: 0.0 }, 2.0);
+    let (upper_left, lower_right) = view.corners((80, 40));
+    let mut viewer = Viewer::new(Config::new((80, 40), upper_left, lower_right, "unused.png"));
+
+    viewer.apply(Key::Pan(1, 0));
+    assert_eq!(viewer.config.view().center, Complex { re: 0.5, im: 0.0 });
+    viewer.apply(Key::ZoomIn);
+    assert_eq!(viewer.config.view().zoom(), 2.0);
+    viewer.apply(Key::FewerIterations);
+    assert_eq!(viewer.config.limit, 127);
+    viewer.apply(Key::NextPalette);
+    assert_eq!(viewer.config.palette.as_deref(), Some("gray"));
+    assert_eq!(options(&viewer.config), "--center 0.5,0.0 --zoom 2.0 --limit 127 --palette gray");
+
+    let before = viewer.config.view();
+    viewer.apply(Key::ToggleJulia);
+    assert_eq!(viewer.config.fractal, Fractal::Julia(Complex { re: 0.5, im: 0.0 }));
+    viewer.apply(Key::ToggleJulia);
+    assert_eq!(viewer.config.fractal, Fractal::Mandelbrot);
+    assert_eq!(viewer.config.view(), before);
+    assert!(!viewer.apply(Key::Quit));
+
+    // Limits given on the command line can start past the range.
+    viewer.config.limit = u32::MAX - 1;
+    viewer.apply(Key::MoreIterations);
+    assert_eq!(viewer.config.limit, u32::MAX - 1);
+    viewer.config.limit = LIMITS.1 / 2 + 1;
+    viewer.apply(Key::MoreIterations);
+    assert_eq!(viewer.config.limit, LIMITS.1);
+}
+
+#[test]
+fn test_draw() {
+    // Two pixels across, three down: the last line has no bottom pixels.
+    let text = draw(&[0, 255, 10, 20, 30, 40], (2, 3), 1);
+    assert_eq!(text, "\x1b[H\
+                      \x1b[38;2;0;0;0m\x1b[48;2;10;10;10m\u{2580}\
+                      \x1b[38;2;255;255;255m\x1b[48;2;20;20;20m\u{2580}\x1b[0m\r\n\
+                      \x1b[38;2;30;30;30m\x1b[49m\u{2580}\
+                      \x1b[38;2;40;40;40m\x1b[49m\u{2580}\x1b[0m\r\n");
+}
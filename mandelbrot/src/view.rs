@@ -0,0 +1,174 @@
+/* Views
+ * -----
+ * Giving the upper-left and lower-right corners of the region to plot is
+ * simple, but it's easy to pick corners whose shape doesn't match the image,
+ * which stretches the set, and there's no way to turn the picture. A "View"
+ * says instead where the picture is centered, how far it is zoomed in, and
+ * how far it is turned:
+ *
+ *      center = -0.75 + 0.1i, radius = 0.05, rotation = 30 degrees
+ *
+ * The radius is half the span of the image's shorter side, so the view
+ * stretches along the longer side to fit whatever shape the image has, and
+ * pixels are always square. A zoom of 1 shows a radius of 2, enough for the
+ * whole Mandelbrot set; each doubling of the zoom halves the radius.
+ *
+ * Rotation is counterclockwise, in degrees, about the center: the image is
+ * the frame "corners" gives, turned by that much. With no rotation,
+ * "View::pixel_to_point" agrees with "render::pixel_to_point" given those
+ * corners, and "point_to_pixel" takes points back to the pixels they fall in.
+ */
+
+use num::Complex;
+use render::pixel_to_point;
+
+/// The radius a zoom of 1 shows.
+pub const BASE_RADIUS: f64 = 2.0;
+
+/// A region of the complex plane, independent of the image size.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct View {
+    pub center: Complex<f64>,
+    /// Half the span of the image's shorter side.
+    pub radius: f64,
+    /// Counterclockwise, in degrees.
+    pub rotation: f64
+}
+
+impl View {
+    /// Return an unrotated view of radius `radius` about `center`.
+    pub fn new(center: Complex<f64>, radius: f64) -> View {
+        View { center, radius, rotation: 0.0 }
+    }
+
+    /// Return an unrotated view about `center`, zoomed in `zoom` times.
+    pub fn with_zoom(center: Complex<f64>, zoom: f64) -> View {
+        View::new(center, BASE_RADIUS / zoom)
+    }
+
+    /// Return the unrotated view whose frame has the given corners.
+    pub fn from_corners(upper_left: Complex<f64>, lower_right: Complex<f64>) -> View {
+        let (width, height) = (lower_right.re - upper_left.re,
+                               upper_left.im - lower_right.im);
+        View::new((upper_left + lower_right) / 2.0, width.abs().min(height.abs()) / 2.0)
+    }
+
+    pub fn zoom(&self) -> f64 {
+        BASE_RADIUS / self.radius
+    }
+
+    /// Return the distance on the complex plane between neighboring pixels of
+    /// an image of size `bounds`.
+    pub fn pixel_size(&self, bounds: (usize, usize)) -> f64 {
+        2.0 * self.radius / bounds.0.min(bounds.1) as f64
+    }
+
+    /// Return the upper-left and lower-right corners of this view's frame
+    /// before rotation, fitted to an image of size `bounds`.
+    pub fn corners(&self, bounds: (usize, usize)) -> (Complex<f64>, Complex<f64>) {
+        let size = self.pixel_size(bounds);
+        let half = Complex { re: bounds.0 as f64 * size / 2.0, im: bounds.1 as f64 * size / 2.0 };
+        (self.center - half.conj(), self.center + half.conj())
+    }
+
+    /// Return the rotation as a complex number of magnitude one.
+    fn turn(&self) -> Complex<f64> {
+        let radians = self.rotation.to_radians();
+        Complex { re: radians.cos(), im: radians.sin() }
+    }
+
+    /// Turn `point` about the center by this view's rotation.
+    pub fn rotate(&self, point: Complex<f64>) -> Complex<f64> {
+        if self.rotation == 0.0 {
+            return point;
+        }
+        self.center + (point - self.center) * self.turn()
+    }
+
+    /// Return the point on the complex plane that pixel `pixel` shows, in an
+    /// image of size `bounds`.
+    pub fn pixel_to_point(&self, bounds: (usize, usize), pixel: (usize, usize)) -> Complex<f64> {
+        let (upper_left, lower_right) = self.corners(bounds);
+        self.rotate(pixel_to_point(bounds, pixel, upper_left, lower_right))
+    }
+
+    /// Return where `point` falls in an image of size `bounds`, as a column
+    /// and row that may be fractional, negative, or beyond the image.
+    pub fn point_to_position(&self, bounds: (usize, usize), point: Complex<f64>) -> (f64, f64) {
+        let offset = if self.rotation == 0.0 {
+            point - self.center
+        } else {
+            (point - self.center) * self.turn().conj()
+        };
+        let size = self.pixel_size(bounds);
+        (bounds.0 as f64 / 2.0 + offset.re / size,
+         bounds.1 as f64 / 2.0 - offset.im / size)
+    }
+
+    /// Return the pixel of an image of size `bounds` that `point` falls in,
+    /// or `None` if it falls outside the image.
+    #[allow(dead_code)]
+    pub fn point_to_pixel(&self, bounds: (usize, usize), point: Complex<f64>)
+        -> Option<(usize, usize)>
+    {
+        let (column, row) = self.point_to_position(bounds, point);
+        let (column, row) = (column.floor(), row.floor());
+        if column < 0.0 || row < 0.0 || column >= bounds.0 as f64 || row >= bounds.1 as f64 {
+            return None;
+        }
+        Some((column as usize, row as usize))
+    }
+}
+
+#[cfg(test)]
+fn assert_near(a: Complex<f64>, b: Complex<f64>) {
+    assert!((a - b).norm() < 1e-12, "{} is not near {}", a, b);
+}
+
+#[test]
+fn test_corners() {
+    let view = View::from_corners(Complex { re: -2.0, im: 1.0 }, Complex { re: 1.0, im: -1.0 });
+    assert_eq!(view, View::new(Complex { re: -0.5, im: 0.0 }, 1.0));
+    assert_eq!(view.zoom(), 2.0);
+    assert_eq!(view.corners((300, 200)),
+               (Complex { re: -2.0, im: 1.0 }, Complex { re: 1.0, im: -1.0 }));
+
+    // A tall image widens the view upward and downward instead.
+    assert_eq!(view.corners((100, 200)),
+               (Complex { re: -1.5, im: 2.0 }, Complex { re: 0.5, im: -2.0 }));
+    assert_eq!(View::with_zoom(Complex { re: 0.0, im: 0.0 }, 4.0).corners((10, 10)),
+               (Complex { re: -0.5, im: 0.5 }, Complex { re: 0.5, im: -0.5 }));
+}
+
+#[test]
+fn test_pixel_to_point() {
+    let mut view = View::new(Complex { re: 1.0, im: 1.0 }, 1.0);
+    let (upper_left, lower_right) = view.corners((40, 20));
+    for &pixel in &[(0, 0), (13, 7), (39, 19)] {
+        assert_eq!(view.pixel_to_point((40, 20), pixel),
+                   pixel_to_point((40, 20), pixel, upper_left, lower_right));
+    }
+
+    // A quarter turn takes the upper-left corner to the lower-left one.
+    view.rotation = 90.0;
+    assert_near(view.pixel_to_point((40, 20), (0, 0)), Complex { re: 0.0, im: -1.0 });
+    assert_near(view.pixel_to_point((40, 20), (20, 10)), view.center);
+}
+
+#[test]
+fn test_point_to_pixel() {
+    let bounds = (64, 48);
+    for &rotation in &[0.0, 30.0, -135.0] {
+        let view = View { center: Complex { re: -0.75, im: 0.1 }, radius: 0.05, rotation };
+        for &pixel in &[(0, 0), (5, 40), (32, 24), (63, 47)] {
+            let (column, row) = view.point_to_position(bounds, view.pixel_to_point(bounds, pixel));
+            assert!((column - pixel.0 as f64).abs() < 1e-9 && (row - pixel.1 as f64).abs() < 1e-9);
+
+            // The middle of a pixel falls in that pixel.
+            let fine = (bounds.0 * 2, bounds.1 * 2);
+            let middle = view.pixel_to_point(fine, (pixel.0 * 2 + 1, pixel.1 * 2 + 1));
+            assert_eq!(view.point_to_pixel(bounds, middle), Some(pixel));
+        }
+        assert_eq!(view.point_to_pixel(bounds, Complex { re: 2.0, im: 0.0 }), None);
+    }
+}